@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use renet::Bytes;
+
+mod utils;
+
+use utils::*;
+
+// Whatever arrives off the wire, decoding must return an error instead of panicking,
+// and anything that does decode must encode again without panicking
+fuzz_target!(|data: &[u8]| {
+    let bytes = Bytes::copy_from_slice(data);
+    if let Ok(message) = ServerMessage::try_from(bytes.clone()) {
+        let _: Bytes = message.into();
+    }
+    if let Ok(handshake) = Handshake::try_from(bytes.clone()) {
+        let _ = handshake.respond(SUPPORTED_FEATURES);
+        let _: Bytes = handshake.into();
+    }
+    if let Ok(response) = HandshakeResponse::try_from(bytes.clone()) {
+        let _: Bytes = response.into();
+    }
+    // LAN beacons come from anyone on the network
+    if let Ok(beacon) = Beacon::try_from(bytes) {
+        let _: Bytes = beacon.into();
+    }
+});
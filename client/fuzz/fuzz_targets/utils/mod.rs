@@ -0,0 +1,24 @@
+// The game is a binary crate, so the fuzz target pulls in just the modules the decoder is made of
+#![allow(dead_code)]
+
+#[path = "../../../src/utils/deck.rs"]
+mod deck;
+pub use deck::*;
+#[path = "../../../src/utils/lobby.rs"]
+pub mod lobby;
+#[path = "../../../src/utils/message.rs"]
+mod message;
+pub use message::*;
+#[path = "../../../src/utils/handshake.rs"]
+mod handshake;
+pub use handshake::*;
+#[path = "../../../src/utils/hand.rs"]
+mod hand;
+#[path = "../../../src/utils/chat.rs"]
+pub mod chat;
+#[path = "../../../src/utils/address.rs"]
+mod address;
+pub use address::*;
+#[path = "../../../src/utils/discovery.rs"]
+mod discovery;
+pub use discovery::*;
@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use std::collections::VecDeque;
 
 const OFF_SCREEN_BOUNDS: f32 = 60.0;
 
@@ -6,7 +7,8 @@ pub struct AnimatorPlugin;
 
 impl Plugin for AnimatorPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, animate_objects);
+        app.add_event::<TweenCompleted>()
+            .add_systems(Update, (animate_objects, run_tweens, run_timelines));
     }
 }
 
@@ -69,3 +71,164 @@ fn animate_objects(
         animated_object.scale += new_scale;
     }
 }
+
+// What happens to an entity once its tween or timeline is done
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TweenEnd {
+    // Stays where it ended up, only the tween is removed
+    #[default]
+    Keep,
+    Despawn,
+}
+
+// Sent when a tween or a whole timeline finishes, unless the entity was despawned for it
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TweenCompleted {
+    pub entity: Entity,
+}
+
+// Moves an entity from one transform to another over a set time.
+// Parts that are the same at both ends are left alone, so a tween on the rotation
+// can run alongside one on the translation.
+#[derive(Component, Debug, Clone)]
+pub struct Tween {
+    pub start: Transform,
+    pub end: Transform,
+    pub duration: f32,
+    // Seconds to wait before moving, the entity sits at `start` meanwhile
+    pub delay: f32,
+    pub easing: EaseFunction,
+    pub on_complete: TweenEnd,
+    elapsed: f32,
+}
+
+impl Tween {
+    pub fn new(start: Transform, end: Transform, duration: f32) -> Self {
+        Tween {
+            start,
+            end,
+            duration,
+            delay: 0.0,
+            easing: EaseFunction::CubicInOut,
+            on_complete: TweenEnd::Keep,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn with_easing(mut self, easing: EaseFunction) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    pub fn with_delay(mut self, delay: f32) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn despawn_on_complete(mut self) -> Self {
+        self.on_complete = TweenEnd::Despawn;
+        self
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.delay + self.duration
+    }
+
+    // Moves time on and updates the transform, returns whether the tween is done
+    fn advance(&mut self, delta: f32, transform: &mut Transform) -> bool {
+        self.elapsed += delta;
+        let progress = if self.duration > 0.0 { (self.elapsed - self.delay) / self.duration } else { 1.0 };
+        let eased = EasingCurve::new(0.0, 1.0, self.easing).sample_clamped(progress.clamp(0.0, 1.0));
+        if self.start.translation != self.end.translation {
+            transform.translation = self.start.translation.lerp(self.end.translation, eased);
+        }
+        if self.start.rotation != self.end.rotation {
+            transform.rotation = self.start.rotation.slerp(self.end.rotation, eased);
+        }
+        if self.start.scale != self.end.scale {
+            transform.scale = self.start.scale.lerp(self.end.scale, eased);
+        }
+        self.is_finished()
+    }
+}
+
+// Tweens played one step after another, everything within a step plays at the same time
+#[derive(Component, Debug, Clone, Default)]
+pub struct Timeline {
+    steps: VecDeque<Vec<Tween>>,
+    pub on_complete: TweenEnd,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline::default()
+    }
+
+    // Starts once everything added so far has finished
+    pub fn then(mut self, tween: Tween) -> Self {
+        self.steps.push_back(vec![tween]);
+        self
+    }
+
+    // Plays alongside the last tween added
+    pub fn with(mut self, tween: Tween) -> Self {
+        match self.steps.back_mut() {
+            Some(step) => step.push(tween),
+            None => self.steps.push_back(vec![tween]),
+        }
+        self
+    }
+
+    pub fn despawn_on_complete(mut self) -> Self {
+        self.on_complete = TweenEnd::Despawn;
+        self
+    }
+}
+
+fn finish(commands: &mut Commands, entity: Entity, on_complete: TweenEnd, completed: &mut EventWriter<TweenCompleted>) {
+    match on_complete {
+        TweenEnd::Keep => {
+            completed.send(TweenCompleted { entity });
+        }
+        TweenEnd::Despawn => commands.entity(entity).despawn_recursive(),
+    }
+}
+
+fn run_tweens(
+    mut commands: Commands,
+    mut tween_query: Query<(Entity, &mut Transform, &mut Tween)>,
+    mut completed: EventWriter<TweenCompleted>,
+    time: Res<Time>,
+) {
+    for (entity, mut transform, mut tween) in tween_query.iter_mut() {
+        if tween.advance(time.delta_secs(), &mut transform) {
+            commands.entity(entity).remove::<Tween>();
+            finish(&mut commands, entity, tween.on_complete, &mut completed);
+        }
+    }
+}
+
+fn run_timelines(
+    mut commands: Commands,
+    mut timeline_query: Query<(Entity, &mut Transform, &mut Timeline)>,
+    mut completed: EventWriter<TweenCompleted>,
+    time: Res<Time>,
+) {
+    for (entity, mut transform, mut timeline) in timeline_query.iter_mut() {
+        if let Some(step) = timeline.steps.front_mut() {
+            let mut is_step_done = true;
+            for tween in step.iter_mut() {
+                if !tween.is_finished() {
+                    is_step_done &= tween.advance(time.delta_secs(), &mut transform);
+                }
+            }
+            if is_step_done {
+                timeline.steps.pop_front();
+            }
+        }
+        if timeline.steps.is_empty() {
+            commands.entity(entity).remove::<Timeline>();
+            finish(&mut commands, entity, timeline.on_complete, &mut completed);
+        }
+    }
+}
@@ -8,7 +8,7 @@ pub struct BackgroundAnimationPlugin;
 impl Plugin for BackgroundAnimationPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::MainMenu), spawn_feature_card)
-            .add_systems(Update, spawn_background_cards.run_if(
+            .add_systems(Update, (spawn_background_cards, start_feature_card_spin).run_if(
                 in_state(GameState::MainMenu)
                     .or(in_state(GameState::Settings))
             )
@@ -18,6 +18,7 @@ impl Plugin for BackgroundAnimationPlugin {
 
 const BACKGROUND_CARD_SPEED: f32 = 100.0;
 const FEATURED_CARD_ROTATION_SPEED: f32 = 1.0;
+const FEATURED_CARD_POP_DURATION: f32 = 0.6;
 const MAX_BACKGROUND_CARDS: usize = 40;
 const BACKGROUND_CARD_SCALE: f32 = 70.0;
 
@@ -43,26 +44,40 @@ fn spawn_feature_card(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
 ) {
-    let mut rng = rand::thread_rng();
+    let transform = Transform{
+        translation: Vec3::new(0., 0., -3.),
+        scale: Vec3::splat(BACKGROUND_CARD_SCALE),
+        ..default()
+    };
+    // pops in first, start_feature_card_spin takes over once it's full size
     commands.spawn((
         FeatureCard,
-        Transform{
-            translation: Vec3::new(0., 0., -3.),
-            scale: Vec3::splat(BACKGROUND_CARD_SCALE),
-            ..default()
-        },
+        transform.with_scale(Vec3::ZERO),
+        Tween::new(transform.with_scale(Vec3::ZERO), transform, FEATURED_CARD_POP_DURATION).with_easing(EaseFunction::BackOut),
         SceneRoot(game_assets.deck.cards[0].model.clone()),
-        AnimatedObject {
-            rotation: Vec3::new(rng.gen_range(0.0..1.5), rng.gen_range(0.0..1.5), rng.gen_range(0.0..1.5)),
-            scale: Vec3::ZERO,
-            translation: Vec3::ZERO,
-            speed: FEATURED_CARD_ROTATION_SPEED,
-            update_rotation: seeded_random_rotation,
-            ..default()
-        },
     ));
 }
 
+fn start_feature_card_spin(
+    mut commands: Commands,
+    mut completed: EventReader<TweenCompleted>,
+    feature_card: Query<(), With<FeatureCard>>,
+) {
+    let mut rng = rand::thread_rng();
+    for event in completed.read() {
+        if feature_card.contains(event.entity) {
+            commands.entity(event.entity).insert(AnimatedObject {
+                rotation: Vec3::new(rng.gen_range(0.0..1.5), rng.gen_range(0.0..1.5), rng.gen_range(0.0..1.5)),
+                scale: Vec3::ZERO,
+                translation: Vec3::ZERO,
+                speed: FEATURED_CARD_ROTATION_SPEED,
+                update_rotation: seeded_random_rotation,
+                ..default()
+            });
+        }
+    }
+}
+
 fn spawn_background_cards(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
@@ -4,11 +4,13 @@ mod background;
 pub use background::*;
 mod animator;
 pub use animator::*;
+mod table;
+pub use table::*;
 
 pub struct GameAnimationPlugin;
 
 impl Plugin for GameAnimationPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((BackgroundAnimationPlugin, AnimatorPlugin));
+        app.add_plugins((BackgroundAnimationPlugin, AnimatorPlugin, TableAnimationPlugin));
     }
 }
@@ -0,0 +1,301 @@
+use bevy::prelude::*;
+use crate::{GameState, GameAssets};
+use crate::animations::{Timeline, Tween};
+use crate::utils::{BytesCard, Lobby, Settings, Stage};
+use crate::screens::table::{card_position, seat_world_position, BOARD_HEIGHT, CARD_SCALE, HOLE_CARD_HEIGHT};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+pub struct TableAnimationPlugin;
+
+impl Plugin for TableAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TableEvent>()
+            .init_resource::<TableAnimations>()
+            .add_systems(OnEnter(GameState::InGame), setup_table_animations)
+            .add_systems(OnExit(GameState::InGame), cleanup_table_animations)
+            .add_systems(Update, (watch_table, queue_table_events, play_table_animations).chain().in_set(TableAnimationSystems).run_if(in_state(GameState::InGame)));
+    }
+}
+
+// Anything drawing the resting cards runs after this, so they don't show up under the flying ones
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TableAnimationSystems;
+
+// Where cards come from and chips go to, on the felt
+const DECK_POSITION: Vec3 = Vec3::new(-2.6, 1.6, 0.3);
+const POT_POSITION: Vec3 = Vec3::new(0.0, 1.6, 0.1);
+// Cards in front of other seats are smaller than our own
+const SEAT_CARD_SCALE: f32 = CARD_SCALE * 0.6;
+const SEAT_CARD_SPREAD: f32 = 0.35;
+const DEAL_DURATION: f32 = 0.35;
+// Time between one dealt card and the next
+const DEAL_STAGGER: f32 = 0.08;
+const FLIP_DURATION: f32 = 0.3;
+const CHIP_DURATION: f32 = 0.45;
+const MAX_CHIPS: i32 = 5;
+
+// Something that happened at the table worth animating, worked out by comparing the lobby between frames.
+// Positions are taken when it happens, so seats moving around later don't throw it off.
+#[derive(Event, Debug, Clone)]
+pub enum TableEvent {
+    // Two cards face down to each seat in the hand, in dealing order, `own` being our seat among them
+    HandDealt { seats: Vec<[Vec3; 2]>, own: Option<usize> },
+    // New board cards, turned face up as they land
+    BoardDealt(Vec<(BytesCard, Vec3)>),
+    // A player's cards turned over where they sit
+    CardsShown(Vec<(BytesCard, Vec3)>),
+    // A bet going into the pot, or the pot going to a winner
+    ChipsMoved { from: Vec3, to: Vec3, amount: i32 },
+}
+
+// Events waiting to be played, one at a time so a deal finishes before the flop comes out
+#[derive(Resource, Default)]
+pub struct TableAnimations {
+    queue: VecDeque<TableEvent>,
+    seen: Option<SeenTable>,
+    busy: bool,
+}
+
+impl TableAnimations {
+    // The table screen holds back the resting cards until everything has landed
+    pub fn is_busy(&self) -> bool {
+        self.busy
+    }
+}
+
+// The bits of the lobby we compare from one frame to the next
+#[derive(Clone, PartialEq)]
+struct SeenTable {
+    stage: Stage,
+    board: usize,
+    // client id, money, how many cards we can see
+    players: Vec<(u64, i32, usize)>,
+}
+
+impl SeenTable {
+    fn new(lobby: &Lobby) -> Self {
+        SeenTable {
+            stage: lobby.stage,
+            board: lobby.board.len(),
+            players: lobby.players.iter().map(|player| (player.client_id, player.money, player.hand.len())).collect(),
+        }
+    }
+}
+
+// Anything spawned here, cleared when we leave the table
+#[derive(Component)]
+struct TableAnimation;
+
+// Shown down cards stay face up in front of their seat until the next deal
+#[derive(Component)]
+struct ShownCard;
+
+#[derive(Resource)]
+struct ChipAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn setup_table_animations(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ChipAssets {
+        mesh: meshes.add(Cylinder::new(0.15, 0.05)),
+        material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.85, 0.7, 0.2),
+            ..Default::default()
+        }),
+    });
+}
+
+fn watch_table(
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    settings: Res<Settings>,
+    mut animations: ResMut<TableAnimations>,
+    mut events: EventWriter<TableEvent>,
+) {
+    let seen = SeenTable::new(&lobby);
+    let Some(previous) = animations.seen.replace(seen.clone()) else {
+        // Whatever was already on the table when we sat down isn't news
+        return;
+    };
+    if previous == seen || !settings.gameplay.table_animations {
+        return;
+    }
+    let count = lobby.players.len();
+    let own_index = lobby.players.iter().position(|player| player.client_id == game_assets.client_id).unwrap_or(0);
+    let seat_of = |client_id: u64| lobby.players.iter().position(|player| player.client_id == client_id);
+    let before = |client_id: u64| previous.players.iter().find(|(id, ..)| *id == client_id);
+
+    // Shown down cards first, then the pot, so the winner is known before the chips move
+    for player in lobby.players.iter().filter(|player| player.client_id != game_assets.client_id) {
+        let was_hidden = before(player.client_id).is_none_or(|(_, _, cards)| *cards == 0);
+        if was_hidden && !player.hand.is_empty() {
+            let seat = seat_of(player.client_id).unwrap_or(0);
+            let center = seat_world_position(seat, count, own_index);
+            let cards = player.hand.iter().enumerate().map(|(index, card)| {
+                (card.clone(), center + Vec3::new((index as f32 - 0.5) * SEAT_CARD_SPREAD, 0.0, 0.2))
+            }).collect();
+            events.send(TableEvent::CardsShown(cards));
+        }
+    }
+    // Money going down went into the pot, money going up came out of it
+    for (seat, player) in lobby.players.iter().enumerate() {
+        let Some((_, money, _)) = before(player.client_id) else {
+            continue;
+        };
+        let position = seat_world_position(seat, count, own_index);
+        if player.money > *money {
+            events.send(TableEvent::ChipsMoved { from: POT_POSITION, to: position, amount: player.money - money });
+        }
+    }
+    if seen.stage == Stage::PreFlop && previous.stage != Stage::PreFlop {
+        // Dealt round the table starting left of the button
+        let in_hand: Vec<usize> = (1..=count)
+            .map(|offset| (lobby.button as usize + offset) % count)
+            .filter(|seat| !lobby.players[*seat].is_folded)
+            .collect();
+        let own = in_hand.iter().position(|seat| *seat == own_index);
+        let seats = in_hand.iter().map(|seat| {
+            if *seat == own_index {
+                [card_position(0, 2, HOLE_CARD_HEIGHT), card_position(1, 2, HOLE_CARD_HEIGHT)]
+            } else {
+                let center = seat_world_position(*seat, count, own_index);
+                [center - Vec3::X * SEAT_CARD_SPREAD / 2.0, center + Vec3::X * SEAT_CARD_SPREAD / 2.0]
+            }
+        }).collect();
+        events.send(TableEvent::HandDealt { seats, own });
+    }
+    // Blinds and bets, after the deal so the blinds slide in as the cards arrive
+    for (seat, player) in lobby.players.iter().enumerate() {
+        let Some((_, money, _)) = before(player.client_id) else {
+            continue;
+        };
+        if player.money < *money {
+            let position = seat_world_position(seat, count, own_index);
+            events.send(TableEvent::ChipsMoved { from: position, to: POT_POSITION, amount: money - player.money });
+        }
+    }
+    if seen.board > previous.board {
+        let cards = lobby.board.iter().enumerate()
+            .skip(previous.board)
+            .map(|(index, card)| (card.clone(), card_position(index, lobby.board.len(), BOARD_HEIGHT)))
+            .collect();
+        events.send(TableEvent::BoardDealt(cards));
+    }
+}
+
+fn queue_table_events(
+    mut events: EventReader<TableEvent>,
+    mut animations: ResMut<TableAnimations>,
+) {
+    for event in events.read() {
+        animations.queue.push_back(event.clone());
+    }
+}
+
+// Starts the next event once the last one has finished moving
+fn play_table_animations(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    chip_assets: Res<ChipAssets>,
+    lobby: Res<Lobby>,
+    mut animations: ResMut<TableAnimations>,
+    moving_query: Query<(Has<Tween>, Has<Timeline>), With<TableAnimation>>,
+    shown_query: Query<Entity, With<ShownCard>>,
+) {
+    if moving_query.iter().any(|(tween, timeline)| tween || timeline) {
+        animations.busy = true;
+        return;
+    }
+    let Some(event) = animations.queue.pop_front() else {
+        animations.busy = false;
+        return;
+    };
+    animations.busy = true;
+    let face_down = Quat::from_rotation_y(PI);
+    let from_deck = Transform::from_translation(DECK_POSITION).with_rotation(face_down).with_scale(Vec3::splat(SEAT_CARD_SCALE));
+    match event {
+        TableEvent::HandDealt { seats, own } => {
+            for entity in shown_query.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+            // One card to everyone, then the second
+            let rounds = seats.len();
+            for (seat, cards) in seats.iter().enumerate() {
+                let scale = if own == Some(seat) { CARD_SCALE } else { SEAT_CARD_SCALE };
+                for (round, destination) in cards.iter().enumerate() {
+                    let to = Transform::from_translation(*destination).with_rotation(face_down).with_scale(Vec3::splat(scale));
+                    spawn_card(&mut commands, game_assets.deck.cards[0].model.clone(), from_deck, Timeline::new()
+                        .then(Tween::new(from_deck, to, DEAL_DURATION).with_delay((round * rounds + seat) as f32 * DEAL_STAGGER))
+                        .despawn_on_complete());
+                }
+            }
+        }
+        TableEvent::BoardDealt(cards) => {
+            // Slides over face down growing as it goes, then turns over where it lands
+            for (index, (card, destination)) in cards.into_iter().enumerate() {
+                let delay = index as f32 * DEAL_STAGGER;
+                let landed = Transform::from_translation(destination).with_rotation(face_down).with_scale(Vec3::splat(CARD_SCALE));
+                spawn_card(&mut commands, game_assets.deck.find_model_from_bytes_card(card), from_deck, Timeline::new()
+                    .then(Tween::new(from_deck, from_deck.with_translation(destination), DEAL_DURATION).with_delay(delay))
+                    .with(Tween::new(from_deck, from_deck.with_scale(landed.scale), DEAL_DURATION).with_delay(delay).with_easing(EaseFunction::QuadraticOut))
+                    .then(Tween::new(landed, landed.with_rotation(Quat::IDENTITY), FLIP_DURATION).with_easing(EaseFunction::BackOut))
+                    .despawn_on_complete());
+            }
+        }
+        TableEvent::CardsShown(cards) => {
+            for (card, position) in cards {
+                let at = Transform::from_translation(position).with_scale(Vec3::splat(SEAT_CARD_SCALE));
+                let entity = spawn_card(&mut commands, game_assets.deck.find_model_from_bytes_card(card), at.with_rotation(face_down), Timeline::new()
+                    .then(Tween::new(at.with_rotation(face_down), at, FLIP_DURATION).with_easing(EaseFunction::BackOut)));
+                commands.entity(entity).insert(ShownCard);
+            }
+        }
+        TableEvent::ChipsMoved { from, to, amount } => {
+            // A small stack, taller for bigger amounts
+            let chips = (amount / lobby.settings.big_blind.max(1)).clamp(1, MAX_CHIPS);
+            // Flat side towards the camera
+            let facing = Quat::from_rotation_x(PI / 2.0);
+            for chip in 0..chips {
+                let lift = Vec3::new(0.0, chip as f32 * 0.06, 0.1);
+                let start = Transform::from_translation(from + lift).with_rotation(facing);
+                commands.spawn((
+                    Mesh3d(chip_assets.mesh.clone()),
+                    MeshMaterial3d(chip_assets.material.clone()),
+                    start,
+                    Tween::new(start, start.with_translation(to + lift), CHIP_DURATION)
+                        .with_delay(chip as f32 * 0.03)
+                        .with_easing(EaseFunction::QuadraticOut)
+                        .despawn_on_complete(),
+                    TableAnimation,
+                ));
+            }
+        }
+    }
+}
+
+fn spawn_card(commands: &mut Commands, model: Handle<Scene>, transform: Transform, timeline: Timeline) -> Entity {
+    commands.spawn((
+        transform,
+        SceneRoot(model),
+        timeline,
+        TableAnimation,
+    )).id()
+}
+
+fn cleanup_table_animations(
+    mut commands: Commands,
+    query: Query<Entity, With<TableAnimation>>,
+    mut animations: ResMut<TableAnimations>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<ChipAssets>();
+    *animations = TableAnimations::default();
+}
@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 use crate::Deck;
+use crate::utils::DEFAULT_PORT;
 use std::net::{SocketAddr, IpAddr, Ipv4Addr};
 
 pub struct AssetLoaderPlugin;
@@ -16,8 +17,18 @@ pub struct GameAssets {
     pub font: Handle<Font>,
     pub deck: Deck,
     pub player_name: String,
+    // Only needed when the host runs a login service, an empty password connects unsecured
+    pub password: String,
+    // The address as typed, host names and all. It's looked up again on every connect, server_address is what it came to last time.
+    pub server_host: String,
     pub server_address: SocketAddr,
     pub client_id: u64,
+    // Secret proving to the server that a reconnecting client owns its seat
+    pub session_token: u64,
+    // Why the last connection attempt failed, shown on the join screen
+    pub connection_error: Option<String>,
+    // Join the table as a spectator instead of taking a seat
+    pub spectate: bool,
 }
 
 impl Default for GameAssets {
@@ -26,8 +37,13 @@ impl Default for GameAssets {
             font: Handle::default(),
             deck: Deck::new_empty(),
             player_name: String::new(),
-            server_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 2163),
+            password: String::new(),
+            server_host: format!("127.0.0.1:{DEFAULT_PORT}"),
+            server_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), DEFAULT_PORT),
             client_id: 0,
+            session_token: 0,
+            connection_error: None,
+            spectate: false,
         }
     }
 }
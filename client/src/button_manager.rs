@@ -1,41 +1,54 @@
 use bevy::prelude::*;
-use crate::{GameState, ServerMode};
-use bevy_framepace::FramepaceSettings;
+use crate::{GameState, ServerMode, GameAssets};
+use crate::utils::{ServerMessage, Settings};
+use bevy_renet::renet::{RenetClient, DefaultChannel, Bytes};
 use std::sync::Arc;
+use bevy_simple_text_input::{TextInput, TextInputInactive};
 
 pub struct ButtonManagerPlugin;
 
 impl Plugin for ButtonManagerPlugin {
     fn build(&self, app: &mut App){
-        app.add_systems(Update, update_buttons);
+        app.add_systems(Update, (update_buttons, focus_text_inputs));
     }
 }
 
+// Edits the shared game assets, e.g. the address typed on the join screen
+pub type AssetsChange = Arc<dyn Fn(&mut ResMut<GameAssets>) + Send + Sync>;
 
 #[derive(Resource, Clone)]
 pub enum ButtonAction {
     ChangeState(Arc<dyn Fn(&mut ResMut<NextState<GameState>>) + Send + Sync>),
-    ChangeWindow(Arc<dyn Fn(&mut Query<&mut Window>) + Send + Sync>),
-    ChangeFPS(Arc<dyn Fn(&mut ResMut<FramepaceSettings>) + Send + Sync>),
+    // Settings are applied to the window and saved to disk by config::apply_settings_system
+    ChangeSettings(Arc<dyn Fn(&mut Settings) + Send + Sync>),
     //CreateRequest(Arc<dyn Fn(&mut ResMut<CardServer>) + Send + Sync>),
     Other(Arc<dyn Fn() + Send + Sync>),
     ChangeServerMode(Arc<dyn Fn(&mut ResMut<NextState<ServerMode>>) + Send + Sync>),
+    ChangeAssets(AssetsChange),
+    // Sends the message to the server, does nothing when not connected as a client
+    SendMessage(Arc<dyn Fn() -> ServerMessage + Send + Sync>),
 }
 
 impl ButtonAction {
     fn execute(
         &self,
         game_state: &mut ResMut<NextState<GameState>>,
-        window_query: &mut Query<&mut Window>,
-        framespace_settings: &mut ResMut<FramepaceSettings>,
+        settings: &mut ResMut<Settings>,
        // card_server: &mut ResMut<CardServer>,
         server_mode: &mut ResMut<NextState<ServerMode>>,
+        game_assets: &mut ResMut<GameAssets>,
+        client: &mut Option<ResMut<RenetClient>>,
     ) {
         match self {
             ButtonAction::ChangeState(f) => f(game_state),
-            ButtonAction::ChangeWindow(f) => f(window_query),
-            ButtonAction::ChangeFPS(f) => f(framespace_settings),
+            ButtonAction::ChangeSettings(f) => f(settings),
             ButtonAction::ChangeServerMode(f) => f(server_mode),
+            ButtonAction::ChangeAssets(f) => f(game_assets),
+            ButtonAction::SendMessage(f) => {
+                if let Some(client) = client {
+                    client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(f()));
+                }
+            }
             //ButtonAction::CreateRequest(f) => f(card_server),
             ButtonAction::Other(f) => f(),
         }
@@ -75,14 +88,15 @@ impl Default for ButtonPosition {
     }
 }
 
-pub fn spawn_button(
-    parent: &mut ChildBuilder,
+// Returns the button so screens can tag it with their own components
+pub fn spawn_button<'a>(
+    parent: &'a mut ChildBuilder,
     text: &str,
     font: Handle<Font>,
     button_position: ButtonPosition,
     button_assets: ButtonAssets,
-){
-    parent.spawn((
+) -> EntityCommands<'a> {
+    let mut button = parent.spawn((
         Button,
         Node {
             position_type: PositionType::Absolute,
@@ -102,7 +116,8 @@ pub fn spawn_button(
         BorderRadius::MAX,
         BorderColor(button_assets.normal.into()),
         button_assets,
-    )).with_child((
+    ));
+    button.with_child((
         Text::new(text),
         TextFont {
             font,
@@ -111,14 +126,16 @@ pub fn spawn_button(
         },
         TextColor(Color::WHITE.into()),
     ));
+    button
 }
 
 fn update_buttons(
     mut game_state: ResMut<NextState<GameState>>,
-    mut framespace_settings: ResMut<FramepaceSettings>,
+    mut settings: ResMut<Settings>,
     mut server_mode: ResMut<NextState<ServerMode>>,
+    mut game_assets: ResMut<GameAssets>,
+    mut client: Option<ResMut<RenetClient>>,
     //mut card_server: ResMut<CardServer>,
-    mut window_query: Query<&mut Window>,
     mut interaction_query: Query<(
         &Interaction,
         &mut BackgroundColor,
@@ -131,7 +148,7 @@ fn update_buttons(
             Interaction::Pressed => {
                 *background_color = BackgroundColor(button_assets.pressed);
                 *border_color = BorderColor(button_assets.pressed);
-                button_assets.on_click.execute(&mut game_state, &mut window_query, &mut framespace_settings, &mut server_mode);
+                button_assets.on_click.execute(&mut game_state, &mut settings, &mut server_mode, &mut game_assets, &mut client);
             }
             Interaction::Hovered => {
                 *background_color = BackgroundColor(button_assets.hovered);
@@ -143,4 +160,18 @@ fn update_buttons(
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// Clicking a text box makes it the only one that takes keyboard input
+fn focus_text_inputs(
+    clicked_query: Query<(Entity, &Interaction), Changed<Interaction>>,
+    mut text_input_query: Query<(Entity, &mut TextInputInactive), With<TextInput>>,
+) {
+    for (clicked_entity, interaction) in clicked_query.iter() {
+        if *interaction == Interaction::Pressed && text_input_query.contains(clicked_entity) {
+            for (entity, mut inactive) in text_input_query.iter_mut() {
+                inactive.0 = entity != clicked_entity;
+            }
+        }
+    }
+}
@@ -1,11 +1,9 @@
 use bevy::prelude::*;
 use bevy::window::{WindowMode, PresentMode, MonitorSelection};
-use bevy_renet::netcode::NetcodeServerPlugin;
 use bevy_simple_text_input::*;
-use bevy_framepace::{FramepacePlugin, Limiter, FramepaceSettings};
+use bevy_framepace::FramepacePlugin;
 use bevy_renet::*;
 use std::env;
-use bevy_renet::netcode::NetcodeClientPlugin;
 use crate::utils::lobby::Action;
 
 pub const GAME_NAME: &str = "Jack of Diamonds";
@@ -36,12 +34,31 @@ fn main() {
     #[cfg(target_os = "windows")]
     env::set_var("WGPU_BACKEND", "dx12");
 
+    // Bots playing each other with no window, see utils/simulation.rs
+    let args: Vec<String> = env::args().collect();
+    if args.iter().any(|arg| arg == "--simulate") {
+        std::process::exit(run_simulation(&args));
+    }
+    // Saves a login for hosting in secure mode, see utils/auth.rs
+    if args.iter().any(|arg| arg == "--add-account") {
+        std::process::exit(run_add_account(&args));
+    }
+
+    // Read before the window opens so it starts at the saved size
+    let settings = Settings::load();
+    // --latency, --jitter, --loss and --duplicate start with the network conditioner on, it can also be changed from the debug menu
+    let network_conditions = NetworkConditions::from_args(&args).unwrap_or_else(|error| {
+        println!("Ignoring the network options: {error}");
+        NetworkConditions::default()
+    });
+
     App::new()
     .insert_resource(ClearColor(Color::BLACK))
     .add_plugins(DefaultPlugins.set(WindowPlugin { // Default Plugins
         primary_window: Some(Window {
             title: GAME_NAME.to_string(),
-            resolution: (1920.0, 1080.0).into(),
+            resolution: settings.window_resolution(),
+            mode: settings.video.window_mode.window_mode(),
             //mode: WindowMode::SizedFullscreen(MonitorSelection::Primary),
             present_mode: PresentMode::AutoVsync,
             ..Default::default()
@@ -52,23 +69,24 @@ fn main() {
     }))
     .init_state::<GameState>()
     .init_state::<ServerMode>()
+    .insert_resource(settings)
+    .insert_resource(network_conditions)
     .init_resource::<GameAssets>()
     .init_resource::<Lobby>()
     .add_event::<Action>()
-    .add_plugins((TextInputPlugin, FramepacePlugin, RenetServerPlugin, RenetClientPlugin, NetcodeServerPlugin, NetcodeClientPlugin)) // External Plugins
-    .add_plugins((AssetLoaderPlugin, ScreenPlugin, ButtonManagerPlugin, GameAnimationPlugin, ServerPlugin))// In-Crate Plugins
+    .add_plugins((TextInputPlugin, FramepacePlugin, RenetServerPlugin, RenetClientPlugin)) // External Plugins
+    .add_plugins((AssetLoaderPlugin, ScreenPlugin, ButtonManagerPlugin, GameAnimationPlugin, ServerPlugin, UdpPlugin, LoopbackPlugin, ConfigPlugin))// In-Crate Plugins
     .add_systems(Startup, setup)
     .run();
 }
 
 
 
-fn setup(mut commands: Commands, mut framespace_settings: ResMut<FramepaceSettings>,) {
+fn setup(mut commands: Commands) {
     // Spawns the camera
     commands.spawn((Camera3d::default(), Transform::from_xyz(0.0, 0.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y)));
 
-    // Sets the framepace limiter to 60 fps
-    framespace_settings.limiter = Limiter::from_framerate(60.0);
+    // The framepace limiter is set from the saved settings by config::apply_settings_system
 
     // Spawns the point light
     commands.spawn((PointLight {
@@ -0,0 +1,376 @@
+use bevy::prelude::*;
+use bevy::ui::RelativeCursorPosition;
+use crate::{GameState, GameAssets};
+use crate::{ButtonAction, ButtonAssets, ButtonPosition, spawn_button};
+use crate::utils::{Action, Lobby, Settings};
+use bevy_simple_text_input::*;
+use std::sync::Arc;
+
+pub struct BetPanelPlugin;
+
+impl Plugin for BetPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BetSize>()
+            .add_systems(OnEnter(GameState::InGame), setup_bet_panel)
+            .add_systems(OnExit(GameState::InGame), cleanup_bet_panel)
+            .add_systems(Update, (update_bet_bounds, slider_listener, preset_listener, bet_input_listener, bet_shortcuts, update_bet_panel).chain().run_if(in_state(GameState::InGame)));
+    }
+}
+
+const SLIDER_WIDTH: f32 = 560.0;
+const HANDLE_WIDTH: f32 = 16.0;
+
+// What we would raise to, as the total bet for this round, kept inside what the rules allow
+#[derive(Resource, Default)]
+pub struct BetSize {
+    pub raise_to: i32,
+    // Smallest raise on offer and all in, both as totals, zero when we can't raise
+    pub min: i32,
+    pub max: i32,
+    // What we already put in this round
+    pub already_in: i32,
+}
+
+impl BetSize {
+    pub fn is_available(&self) -> bool {
+        self.max > 0
+    }
+
+    pub fn set(&mut self, raise_to: i32) {
+        self.raise_to = raise_to.clamp(self.min, self.max);
+    }
+
+    // The raise to send, going all in through its own action so the server marks us all in
+    pub fn action(&self) -> Action {
+        if self.raise_to >= self.max {
+            Action::AllIn
+        } else {
+            Action::Raise(self.raise_to - self.already_in)
+        }
+    }
+}
+
+// Common bet sizes, as a share of the pot after we call
+#[derive(Component, Clone, Copy)]
+enum BetPreset {
+    Third,
+    Half,
+    TwoThirds,
+    Pot,
+    AllIn,
+}
+
+impl BetPreset {
+    const ALL: [BetPreset; 5] = [BetPreset::Third, BetPreset::Half, BetPreset::TwoThirds, BetPreset::Pot, BetPreset::AllIn];
+
+    fn label(&self) -> &'static str {
+        match self {
+            BetPreset::Third => "1/3",
+            BetPreset::Half => "1/2",
+            BetPreset::TwoThirds => "2/3",
+            BetPreset::Pot => "Pot",
+            BetPreset::AllIn => "All in",
+        }
+    }
+
+    fn key(&self) -> KeyCode {
+        match self {
+            BetPreset::Third => KeyCode::Digit1,
+            BetPreset::Half => KeyCode::Digit2,
+            BetPreset::TwoThirds => KeyCode::Digit3,
+            BetPreset::Pot => KeyCode::Digit4,
+            BetPreset::AllIn => KeyCode::Digit5,
+        }
+    }
+
+    // Total to raise to, before it is clamped
+    fn raise_to(&self, lobby: &Lobby, client_id: u64, bet: &BetSize) -> i32 {
+        let pot_after_call = lobby.pot + lobby.to_call(client_id);
+        let (numerator, denominator) = match self {
+            BetPreset::Third => (1, 3),
+            BetPreset::Half => (1, 2),
+            BetPreset::TwoThirds => (2, 3),
+            BetPreset::Pot => (1, 1),
+            BetPreset::AllIn => return bet.max,
+        };
+        lobby.current_bet + pot_after_call * numerator / denominator
+    }
+}
+
+#[derive(Component)]
+struct BetPanel;
+
+#[derive(Component)]
+struct BetSlider;
+
+#[derive(Component)]
+struct BetSliderHandle;
+
+#[derive(Component)]
+struct BetInput;
+
+fn setup_bet_panel(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+) {
+    // Sits above the action bar, only shown while we can raise
+    commands.spawn((Node {
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(100.0),
+        right: Val::Px(20.0),
+        width: Val::Px(760.0),
+        height: Val::Px(110.0),
+        ..Default::default()
+    },
+    Visibility::Hidden,
+    BetPanel,
+    ))
+    .with_children(|parent| {
+        for (index, preset) in BetPreset::ALL.iter().enumerate() {
+            spawn_button(
+                parent,
+                preset.label(),
+                game_assets.font.clone(),
+                ButtonPosition {
+                    top: Val::Px(0.0),
+                    left: Val::Px(index as f32 * 150.0),
+                    width: Val::Px(140.0),
+                    height: Val::Px(45.0),
+                    font_size: 20.0,
+                    ..Default::default()
+                },
+                ButtonAssets {
+                    normal: Color::srgb(0.35, 0.35, 0.35),
+                    hovered: Color::srgb(0.45, 0.45, 0.45),
+                    pressed: Color::srgb(0.25, 0.25, 0.25),
+                    // preset_listener knows the pot, so it sets the size
+                    on_click: ButtonAction::Other(Arc::new(|| {})),
+                },
+            ).insert(*preset);
+        }
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(15.0),
+                left: Val::Px(0.0),
+                width: Val::Px(SLIDER_WIDTH),
+                height: Val::Px(14.0),
+                ..Default::default()
+            },
+            BackgroundColor(Color::srgb(0.3, 0.3, 0.3)),
+            BorderRadius::MAX,
+            Interaction::None,
+            RelativeCursorPosition::default(),
+            BetSlider,
+        ))
+        .with_child((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(-8.0),
+                width: Val::Px(HANDLE_WIDTH),
+                height: Val::Px(30.0),
+                ..Default::default()
+            },
+            BackgroundColor(Color::WHITE),
+            BorderRadius::all(Val::Px(4.0)),
+            BetSliderHandle,
+        ));
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(0.0),
+                right: Val::Px(0.0),
+                width: Val::Px(170.0),
+                height: Val::Px(44.0),
+                border: UiRect::all(Val::Px(2.0)),
+                padding: UiRect::all(Val::Px(5.0)),
+                ..Default::default()
+            },
+            BorderColor(Color::WHITE),
+            BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+            Interaction::None,
+            TextInput,
+            TextInputInactive(true),
+            TextInputSettings {
+                retain_on_submit: true,
+                ..Default::default()
+            },
+            TextInputTextFont(TextFont {
+                font: game_assets.font.clone(),
+                font_size: 22.0,
+                ..Default::default()
+            }),
+            BetInput,
+        ));
+    });
+}
+
+// Works out how much we can raise each frame, and starts each turn at the smallest raise
+fn update_bet_bounds(
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    mut bet: ResMut<BetSize>,
+) {
+    let client_id = game_assets.client_id;
+    let player = lobby.players.iter().find(|player| player.client_id == client_id);
+    let can_raise = lobby.legal_actions(client_id).iter().any(|action| matches!(action, Action::Raise(_)));
+    let (min, max, already_in) = match player {
+        Some(player) if can_raise => (player.bet_this_turn + lobby.min_raise(client_id), player.bet_this_turn + player.money, player.bet_this_turn),
+        _ => (0, 0, 0),
+    };
+    if (bet.min, bet.max, bet.already_in) == (min, max, already_in) {
+        return;
+    }
+    let is_new_turn = !bet.is_available();
+    bet.min = min;
+    bet.max = max;
+    bet.already_in = already_in;
+    if is_new_turn {
+        bet.raise_to = min;
+    } else {
+        let raise_to = bet.raise_to;
+        bet.set(raise_to);
+    }
+}
+
+fn slider_listener(
+    slider_query: Query<(&Interaction, &RelativeCursorPosition), With<BetSlider>>,
+    mut bet: ResMut<BetSize>,
+) {
+    if !bet.is_available() {
+        return;
+    }
+    for (interaction, cursor) in slider_query.iter() {
+        // Interaction stays pressed while the mouse is held, so the handle follows a drag
+        let (Interaction::Pressed, Some(position)) = (interaction, cursor.normalized) else {
+            continue;
+        };
+        let share = position.x.clamp(0.0, 1.0);
+        let raise_to = bet.min + ((bet.max - bet.min) as f32 * share).round() as i32;
+        if raise_to != bet.raise_to {
+            bet.set(raise_to);
+        }
+    }
+}
+
+fn preset_listener(
+    preset_query: Query<(&Interaction, &BetPreset), Changed<Interaction>>,
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    mut bet: ResMut<BetSize>,
+) {
+    for (interaction, preset) in preset_query.iter() {
+        if *interaction == Interaction::Pressed && bet.is_available() {
+            let raise_to = preset.raise_to(&lobby, game_assets.client_id, &bet);
+            bet.set(raise_to);
+        }
+    }
+}
+
+fn bet_input_listener(
+    mut events: EventReader<TextInputSubmitEvent>,
+    mut input_query: Query<&mut TextInputInactive, With<BetInput>>,
+    mut bet: ResMut<BetSize>,
+) {
+    for event in events.read() {
+        let Ok(mut inactive) = input_query.get_mut(event.entity) else {
+            continue;
+        };
+        // Done typing, the keyboard goes back to the shortcuts
+        inactive.0 = true;
+        if let Ok(raise_to) = event.value.trim().parse::<i32>() {
+            bet.set(raise_to);
+        }
+        // Forces the box to show the clamped amount even if it didn't change
+        bet.set_changed();
+    }
+}
+
+// F folds, C checks or calls, R raises what the panel shows and A goes all in.
+// 1 to 5 pick a preset and the arrow keys move the raise a big blind at a time.
+fn bet_shortcuts(
+    keys: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    text_input_query: Query<&TextInputInactive>,
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    mut bet: ResMut<BetSize>,
+    mut actions: EventWriter<Action>,
+) {
+    // Typing in the chat or the bet box isn't playing
+    if !settings.gameplay.keyboard_shortcuts || text_input_query.iter().any(|inactive| !inactive.0) {
+        return;
+    }
+    let legal = lobby.legal_actions(game_assets.client_id);
+    if legal.is_empty() {
+        return;
+    }
+    let action = if keys.just_pressed(KeyCode::KeyF) {
+        Some(Action::Fold)
+    } else if keys.just_pressed(KeyCode::KeyC) {
+        legal.iter().copied().find(|action| matches!(action, Action::Check | Action::Call))
+    } else if keys.just_pressed(KeyCode::KeyR) && bet.is_available() {
+        Some(bet.action())
+    } else if keys.just_pressed(KeyCode::KeyA) {
+        Some(Action::AllIn)
+    } else {
+        None
+    };
+    if let Some(action) = action {
+        actions.send(action);
+        return;
+    }
+    if !bet.is_available() {
+        return;
+    }
+    for preset in BetPreset::ALL {
+        if keys.just_pressed(preset.key()) {
+            let raise_to = preset.raise_to(&lobby, game_assets.client_id, &bet);
+            bet.set(raise_to);
+        }
+    }
+    let step = lobby.settings.big_blind.max(1);
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        let raise_to = bet.raise_to + step;
+        bet.set(raise_to);
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) {
+        let raise_to = bet.raise_to - step;
+        bet.set(raise_to);
+    }
+}
+
+fn update_bet_panel(
+    bet: Res<BetSize>,
+    mut panel_query: Query<&mut Visibility, With<BetPanel>>,
+    mut handle_query: Query<&mut Node, With<BetSliderHandle>>,
+    mut input_query: Query<(&mut TextInputValue, &TextInputInactive), With<BetInput>>,
+) {
+    if !bet.is_changed() {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        visibility.set_if_neq(if bet.is_available() { Visibility::Inherited } else { Visibility::Hidden });
+    }
+    let share = if bet.max > bet.min { (bet.raise_to - bet.min) as f32 / (bet.max - bet.min) as f32 } else { 1.0 };
+    for mut node in handle_query.iter_mut() {
+        node.left = Val::Px(share * (SLIDER_WIDTH - HANDLE_WIDTH));
+    }
+    // Leave the box alone while it is being typed in
+    for (mut value, inactive) in input_query.iter_mut() {
+        if inactive.0 {
+            value.0 = bet.raise_to.to_string();
+        }
+    }
+}
+
+fn cleanup_bet_panel(
+    mut commands: Commands,
+    query: Query<Entity, With<BetPanel>>,
+    mut bet: ResMut<BetSize>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    *bet = BetSize::default();
+}
@@ -0,0 +1,160 @@
+use bevy::prelude::*;
+use bevy_simple_text_input::*;
+use crate::{GameState, GameAssets};
+use crate::utils::{ChatCommand, ChatLine, ChatLog, Lobby};
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Lobby), setup_chat_panel)
+            .add_systems(OnEnter(GameState::InGame), setup_chat_panel)
+            .add_systems(OnExit(GameState::Lobby), cleanup_chat_panel)
+            .add_systems(OnExit(GameState::InGame), cleanup_chat_panel)
+            .add_systems(Update, (update_chat_panel, chat_input_listener).run_if(in_state(GameState::Lobby).or(in_state(GameState::InGame))));
+    }
+}
+
+// Lines shown in the panel, older ones are still in the chat log
+const VISIBLE_LINES: usize = 12;
+
+#[derive(Component)]
+struct ChatPanel;
+
+#[derive(Component)]
+struct ChatLines;
+
+#[derive(Component)]
+struct ChatInput;
+
+fn setup_chat_panel(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+) {
+    // Bottom left corner, the lines on top and a text box to type into below
+    commands.spawn((Node {
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(20.0),
+        left: Val::Px(20.0),
+        width: Val::Px(420.0),
+        height: Val::Px(280.0),
+        flex_direction: FlexDirection::Column,
+        padding: UiRect::all(Val::Px(5.0)),
+        ..Default::default()
+    },
+    BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+    ChatPanel,
+    ))
+    .with_children(|parent| {
+        parent.spawn((
+            Node {
+                flex_grow: 1.0,
+                overflow: Overflow::clip(),
+                ..Default::default()
+            },
+            Text::new(""),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 16.0,
+                ..Default::default()
+            },
+            TextColor(Color::WHITE),
+            TextLayout::new(JustifyText::Left, LineBreak::WordBoundary),
+            ChatLines,
+        ));
+        parent.spawn((
+            Node {
+                border: UiRect::all(Val::Px(2.0)),
+                padding: UiRect::all(Val::Px(5.0)),
+                width: Val::Percent(100.0),
+                height: Val::Px(36.0),
+                ..Default::default()
+            },
+            BorderColor(Color::WHITE),
+            BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+            Interaction::None,
+            TextInput,
+            // Keys only go to the chat once it's clicked
+            TextInputInactive(true),
+            TextInputTextFont(TextFont {
+                font: game_assets.font.clone(),
+                font_size: 16.0,
+                ..Default::default()
+            }),
+            TextInputPlaceholder {
+                value: "Say something, or /mute name".to_string(),
+                text_font: Some(TextFont {
+                    font: game_assets.font.clone(),
+                    font_size: 16.0,
+                    ..Default::default()
+                }),
+                text_color: Some(TextColor(Color::srgb(0.6, 0.6, 0.6))),
+            },
+            ChatInput,
+        ));
+    });
+}
+
+fn update_chat_panel(
+    chat_log: Res<ChatLog>,
+    added_query: Query<(), Added<ChatLines>>,
+    mut text_query: Query<&mut Text, With<ChatLines>>,
+) {
+    if !chat_log.is_changed() && added_query.is_empty() {
+        return;
+    }
+    let skip = chat_log.lines.len().saturating_sub(VISIBLE_LINES);
+    let lines: Vec<String> = chat_log.lines.iter()
+        .skip(skip)
+        .map(|line| format!("{}: {}", line.name, line.text))
+        .collect();
+    for mut text in text_query.iter_mut() {
+        text.0 = lines.join("\n");
+    }
+}
+
+fn chat_input_listener(
+    mut events: EventReader<TextInputSubmitEvent>,
+    mut chat_commands: EventWriter<ChatCommand>,
+    mut chat_log: ResMut<ChatLog>,
+    input_query: Query<(), With<ChatInput>>,
+    lobby: Res<Lobby>,
+) {
+    for event in events.read() {
+        if !input_query.contains(event.entity) {
+            continue;
+        }
+        let text = event.value.trim();
+        let (command, name) = text.split_once(' ').unwrap_or((text, ""));
+        let muted = match command {
+            "/mute" => true,
+            "/unmute" => false,
+            _ => {
+                chat_commands.send(ChatCommand::Say(text.to_string()));
+                continue;
+            }
+        };
+        let name = name.trim();
+        let Some(player) = lobby.players.iter().find(|player| player.name.eq_ignore_ascii_case(name)) else {
+            chat_log.push(ChatLine::dealer(format!("Nobody called {name} is at the table")));
+            continue;
+        };
+        if muted {
+            chat_log.muted.insert(player.client_id);
+            chat_log.push(ChatLine::dealer(format!("Muted {}", player.name)));
+        } else {
+            chat_log.muted.remove(&player.client_id);
+            chat_log.push(ChatLine::dealer(format!("Unmuted {}", player.name)));
+        }
+        chat_commands.send(ChatCommand::Mute(player.client_id, muted));
+    }
+}
+
+fn cleanup_chat_panel(
+    mut commands: Commands,
+    query: Query<Entity, With<ChatPanel>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+use crate::{GameState, GameAssets, ServerMode};
+use crate::{ButtonAction, ButtonAssets, ButtonPosition, spawn_button};
+use crate::utils::{connect_failure_message, ConnectFailed, UdpClientTransport};
+use renet_netcode::NetcodeTransportError;
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+pub struct ConnectingPlugin;
+
+impl Plugin for ConnectingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Connecting), setup_connecting)
+            .add_systems(OnExit(GameState::Connecting), cleanup_connecting)
+            .add_systems(Update, (watch_connection, give_up_connecting, update_connecting_text).chain().run_if(in_state(GameState::Connecting)));
+    }
+}
+
+// Gives up on a server that hasn't let us in after this many seconds
+const CONNECT_TIMEOUT: f32 = 10.0;
+
+// How long we've been waiting on the server, and why we stopped if it didn't work out
+#[derive(Resource, Default)]
+struct ConnectAttempt {
+    elapsed: f32,
+    failure: Option<String>,
+}
+
+#[derive(Component)]
+struct ConnectingContainer;
+
+#[derive(Component)]
+struct ConnectingText;
+
+fn setup_connecting(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    server_mode: Res<State<ServerMode>>,
+) {
+    // Joiners go back to pick another server, a host stops hosting
+    let cancel = if *server_mode.get() == ServerMode::Host {
+        ButtonAction::ChangeServerMode(Arc::new(|server_mode| {
+            server_mode.set(ServerMode::None);
+        }))
+    } else {
+        ButtonAction::ChangeState(Arc::new(|game_state| {
+            game_state.set(GameState::JoinServer);
+        }))
+    };
+    commands.insert_resource(ConnectAttempt::default());
+    commands.spawn((Node {
+        position_type: PositionType::Absolute,
+        top: Val::Percent(40.0),
+        align_content: AlignContent::Center,
+        align_items: AlignItems::Center,
+        align_self: AlignSelf::Center,
+        justify_content: JustifyContent::Center,
+        justify_items: JustifyItems::Center,
+        justify_self: JustifySelf::Center,
+        ..Default::default()
+    },
+    ConnectingContainer
+    ))
+    .with_children(|parent| {
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Auto,
+                width: Val::Px(700.0),
+                ..Default::default()
+            },
+            Text::new(""),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 32.0,
+                ..Default::default()
+            },
+            TextColor(Color::WHITE),
+            TextLayout::new(JustifyText::Center, LineBreak::WordBoundary),
+            ConnectingText,
+        ));
+        // Leaving this screen hangs up the half made connection
+        spawn_button(
+            parent,
+            "Cancel",
+            game_assets.font.clone(),
+            ButtonPosition {
+                top: Val::Px(100.0),
+                left: Val::Px(-100.0),
+                width: Val::Px(200.0),
+                ..Default::default()
+            },
+            ButtonAssets {
+                normal: Color::srgb(0.5, 0.5, 0.5),
+                hovered: Color::srgb(0.5, 0.5, 0.5),
+                pressed: Color::srgb(0.3, 0.3, 0.3),
+                on_click: cancel,
+            },
+        );
+    });
+}
+
+// Works out why the server can't be reached, if it can't.
+// A version mismatch is caught by the handshake once we're through, see receive_handshake_system.
+fn watch_connection(
+    mut attempt: ResMut<ConnectAttempt>,
+    time: Res<Time>,
+    transport: Option<Res<UdpClientTransport>>,
+    mut transport_errors: EventReader<NetcodeTransportError>,
+    mut connect_failures: EventReader<ConnectFailed>,
+    game_assets: Res<GameAssets>,
+) {
+    attempt.elapsed += time.delta_secs();
+    let address = game_assets.server_host.as_str();
+    // The address couldn't be found or the login was turned down
+    let mut failure = connect_failures.read().last().map(|ConnectFailed(error)| error.clone());
+    for error in transport_errors.read() {
+        // Some systems tell us straight away when nothing listens on the port
+        if let NetcodeTransportError::IO(error) = error {
+            if matches!(error.kind(), ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset) {
+                failure = Some(format!("{address} refused the connection, check that the server is running"));
+            }
+        }
+    }
+    if let Some(reason) = transport.as_ref().and_then(|transport| transport.disconnect_reason()) {
+        failure = Some(connect_failure_message(reason, address));
+    }
+    if failure.is_none() && attempt.elapsed >= CONNECT_TIMEOUT {
+        failure = Some(format!("{address} didn't answer within {CONNECT_TIMEOUT} seconds"));
+    }
+    if failure.is_some() {
+        attempt.failure = failure;
+    }
+}
+
+// Sends a joiner back to the join screen with the reason, a host that can't reach its own server stops hosting
+fn give_up_connecting(
+    attempt: Res<ConnectAttempt>,
+    server_mode: Res<State<ServerMode>>,
+    mut next_server_mode: ResMut<NextState<ServerMode>>,
+    mut game_assets: ResMut<GameAssets>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let Some(failure) = &attempt.failure else {
+        return;
+    };
+    // The handshake already explained why the server won't have us
+    if game_assets.connection_error.is_some() {
+        return;
+    }
+    println!("Could not join {}: {failure}", game_assets.server_host);
+    if *server_mode.get() == ServerMode::Host {
+        next_server_mode.set(ServerMode::None);
+    } else {
+        game_assets.connection_error = Some(failure.clone());
+        game_state.set(GameState::JoinServer);
+    }
+}
+
+fn update_connecting_text(
+    attempt: Res<ConnectAttempt>,
+    game_assets: Res<GameAssets>,
+    mut text_query: Query<&mut Text, With<ConnectingText>>,
+    mut shown_seconds: Local<Option<u32>>,
+) {
+    let seconds = attempt.elapsed as u32;
+    let is_new = text_query.iter().any(|text| text.0.is_empty());
+    if *shown_seconds == Some(seconds) && !is_new {
+        return;
+    }
+    *shown_seconds = Some(seconds);
+    let verb = if game_assets.spectate { "Connecting to watch" } else { "Connecting to" };
+    for mut text in text_query.iter_mut() {
+        text.0 = format!("{verb} {}...\n{seconds}s", game_assets.server_host);
+    }
+}
+
+fn cleanup_connecting(
+    mut commands: Commands,
+    query: Query<Entity, With<ConnectingContainer>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<ConnectAttempt>();
+}
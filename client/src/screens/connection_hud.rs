@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+use crate::{GameState, GameAssets};
+use crate::utils::{ConnectionGrade, ConnectionQuality, Lobby, TableConnections};
+
+// Our own ping, loss and bandwidth along the top of the lobby and table, with a warning underneath when anyone's connection is in trouble
+pub struct ConnectionHudPlugin;
+
+impl Plugin for ConnectionHudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Lobby), setup_connection_hud)
+            .add_systems(OnEnter(GameState::InGame), setup_connection_hud)
+            .add_systems(OnExit(GameState::Lobby), cleanup_connection_hud)
+            .add_systems(OnExit(GameState::InGame), cleanup_connection_hud)
+            .add_systems(Update, (update_connection_stats, update_connection_warnings).run_if(in_state(GameState::Lobby).or(in_state(GameState::InGame))));
+    }
+}
+
+// Seconds between refreshes, any faster and the numbers are too jumpy to read
+const REFRESH_INTERVAL: f32 = 0.5;
+
+#[derive(Component)]
+struct ConnectionHud;
+
+#[derive(Component)]
+struct ConnectionStatsText;
+
+#[derive(Component)]
+struct ConnectionWarningText;
+
+fn setup_connection_hud(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+) {
+    commands.spawn((Node {
+        position_type: PositionType::Absolute,
+        top: Val::Px(4.0),
+        width: Val::Percent(100.0),
+        flex_direction: FlexDirection::Column,
+        align_items: AlignItems::Center,
+        ..Default::default()
+    },
+    // Never in the way of a click
+    PickingBehavior::IGNORE,
+    ConnectionHud,
+    ))
+    .with_children(|parent| {
+        parent.spawn((
+            Text::new(""),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 14.0,
+                ..Default::default()
+            },
+            TextColor(Color::srgb(0.6, 0.6, 0.6)),
+            ConnectionStatsText,
+        ));
+        parent.spawn((
+            Text::new(""),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 14.0,
+                ..Default::default()
+            },
+            TextColor(Color::srgb(1.0, 0.6, 0.2)),
+            ConnectionWarningText,
+        ));
+    });
+}
+
+fn grade_color(grade: ConnectionGrade) -> Color {
+    match grade {
+        ConnectionGrade::Good => Color::srgb(0.5, 0.9, 0.5),
+        ConnectionGrade::Fair => Color::srgb(1.0, 0.85, 0.3),
+        ConnectionGrade::Poor => Color::srgb(1.0, 0.4, 0.4),
+    }
+}
+
+fn update_connection_stats(
+    client: Option<Res<RenetClient>>,
+    game_assets: Res<GameAssets>,
+    time: Res<Time>,
+    mut text_query: Query<(&mut Text, &mut TextColor), With<ConnectionStatsText>>,
+    mut since_refresh: Local<f32>,
+) {
+    *since_refresh += time.delta_secs();
+    let is_new = text_query.iter().any(|(text, _)| text.0.is_empty());
+    if *since_refresh < REFRESH_INTERVAL && !is_new {
+        return;
+    }
+    *since_refresh = 0.0;
+    let (line, color) = match client.as_deref() {
+        Some(client) if client.is_connected() => {
+            let quality = ConnectionQuality::new(game_assets.client_id, client.rtt(), client.packet_loss());
+            let line = format!(
+                "Ping {} ms   Loss {:.1}%   Down {:.1} KB/s   Up {:.1} KB/s",
+                quality.ping_ms,
+                quality.packet_loss * 100.0,
+                client.bytes_received_per_sec() / 1024.0,
+                client.bytes_sent_per_sec() / 1024.0,
+            );
+            (line, grade_color(quality.grade()))
+        }
+        _ => ("Not connected".to_string(), grade_color(ConnectionGrade::Poor)),
+    };
+    for (mut text, mut text_color) in text_query.iter_mut() {
+        text.0 = line.clone();
+        text_color.0 = color;
+    }
+}
+
+// Our own connection first, then anyone else at the table who's lagging or gone
+fn update_connection_warnings(
+    client: Option<Res<RenetClient>>,
+    lobby: Res<Lobby>,
+    table_connections: Res<TableConnections>,
+    game_assets: Res<GameAssets>,
+    mut text_query: Query<&mut Text, With<ConnectionWarningText>>,
+) {
+    let mut warnings = Vec::new();
+    match client.as_deref() {
+        Some(client) if client.is_connected() => {
+            let quality = ConnectionQuality::new(game_assets.client_id, client.rtt(), client.packet_loss());
+            if quality.is_lagging() {
+                warnings.push("Your connection is unstable".to_string());
+            }
+        }
+        Some(client) if client.is_connecting() => warnings.push("Reconnecting...".to_string()),
+        _ => warnings.push("Connection lost".to_string()),
+    }
+    for player in lobby.players.iter().filter(|player| player.client_id != game_assets.client_id) {
+        if player.is_disconnected {
+            warnings.push(format!("{} lost connection", player.name));
+        } else if let Some(quality) = table_connections.get(player.client_id).filter(|quality| quality.is_lagging()) {
+            warnings.push(format!("{} is lagging ({} ms)", player.name, quality.ping_ms));
+        }
+    }
+    let line = warnings.join("   ");
+    for mut text in text_query.iter_mut() {
+        if text.0 != line {
+            text.0 = line.clone();
+        }
+    }
+}
+
+fn cleanup_connection_hud(
+    mut commands: Commands,
+    query: Query<Entity, With<ConnectionHud>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,182 @@
+use bevy::prelude::*;
+use crate::GameAssets;
+use crate::{ButtonAction, ButtonAssets, ButtonPosition, spawn_button};
+use crate::utils::{NetworkConditions, MAX_DELAY_MS};
+use std::sync::Arc;
+
+// F3 opens a panel over whatever screen is showing, for now it only has the network conditioner
+pub struct DebugMenuPlugin;
+
+impl Plugin for DebugMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (toggle_debug_menu, condition_button_listener, update_condition_text).chain());
+    }
+}
+
+const ROW_HEIGHT: f32 = 40.0;
+
+#[derive(Component)]
+struct DebugMenu;
+
+#[derive(Clone, Copy, PartialEq)]
+enum ConditionField {
+    Enabled,
+    Latency,
+    Jitter,
+    Loss,
+    Duplicate,
+}
+
+impl ConditionField {
+    const ALL: [ConditionField; 5] = [ConditionField::Enabled, ConditionField::Latency, ConditionField::Jitter, ConditionField::Loss, ConditionField::Duplicate];
+
+    fn describe(self, conditions: &NetworkConditions) -> String {
+        match self {
+            ConditionField::Enabled => format!("Bad network: {}", if conditions.enabled { "on" } else { "off" }),
+            ConditionField::Latency => format!("Latency: {} ms", conditions.latency_ms),
+            ConditionField::Jitter => format!("Jitter: {} ms", conditions.jitter_ms),
+            ConditionField::Loss => format!("Loss: {}%", conditions.loss_percent),
+            ConditionField::Duplicate => format!("Duplicates: {}%", conditions.duplicate_percent),
+        }
+    }
+
+    // The value with how much one click moves it and how far it goes
+    fn value_mut(self, conditions: &mut NetworkConditions) -> Option<(&mut f32, f32, f32)> {
+        match self {
+            ConditionField::Enabled => None,
+            ConditionField::Latency => Some((&mut conditions.latency_ms, 50.0, MAX_DELAY_MS)),
+            ConditionField::Jitter => Some((&mut conditions.jitter_ms, 25.0, MAX_DELAY_MS)),
+            ConditionField::Loss => Some((&mut conditions.loss_percent, 1.0, 100.0)),
+            ConditionField::Duplicate => Some((&mut conditions.duplicate_percent, 1.0, 100.0)),
+        }
+    }
+}
+
+#[derive(Component)]
+struct ConditionText(ConditionField);
+
+// Which value a button changes, and which way
+#[derive(Component)]
+struct ConditionButton(ConditionField, f32);
+
+fn debug_button_assets() -> ButtonAssets {
+    ButtonAssets {
+        normal: Color::srgb(0.3, 0.3, 0.3),
+        hovered: Color::srgb(0.4, 0.4, 0.4),
+        pressed: Color::srgb(0.2, 0.2, 0.2),
+        // condition_button_listener does the work
+        on_click: ButtonAction::Other(Arc::new(|| {})),
+    }
+}
+
+fn small_button(top: f32, left: f32, width: f32) -> ButtonPosition {
+    ButtonPosition {
+        top: Val::Px(top),
+        left: Val::Px(left),
+        width: Val::Px(width),
+        height: Val::Px(32.0),
+        font_size: 18.0,
+        ..Default::default()
+    }
+}
+
+fn toggle_debug_menu(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    game_assets: Res<GameAssets>,
+    menu_query: Query<Entity, With<DebugMenu>>,
+) {
+    if !keys.just_pressed(KeyCode::F3) {
+        return;
+    }
+    if let Ok(menu) = menu_query.get_single() {
+        commands.entity(menu).despawn_recursive();
+        return;
+    }
+    commands.spawn((Node {
+        position_type: PositionType::Absolute,
+        top: Val::Px(20.0),
+        right: Val::Px(20.0),
+        width: Val::Px(360.0),
+        height: Val::Px(ROW_HEIGHT * (ConditionField::ALL.len() + 2) as f32),
+        ..Default::default()
+    },
+    BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.8)),
+    GlobalZIndex(10),
+    DebugMenu,
+    ))
+    .with_children(|parent| {
+        for (row, field) in ConditionField::ALL.into_iter().enumerate() {
+            let top = 10.0 + ROW_HEIGHT * row as f32;
+            parent.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(top + 6.0),
+                    left: Val::Px(10.0),
+                    ..Default::default()
+                },
+                Text::new(""),
+                TextFont {
+                    font: game_assets.font.clone(),
+                    font_size: 18.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+                ConditionText(field),
+            ));
+            if field == ConditionField::Enabled {
+                spawn_button(parent, "Toggle", game_assets.font.clone(), small_button(top, 230.0, 120.0), debug_button_assets())
+                    .insert(ConditionButton(field, 0.0));
+            } else {
+                spawn_button(parent, "-", game_assets.font.clone(), small_button(top, 230.0, 55.0), debug_button_assets())
+                    .insert(ConditionButton(field, -1.0));
+                spawn_button(parent, "+", game_assets.font.clone(), small_button(top, 295.0, 55.0), debug_button_assets())
+                    .insert(ConditionButton(field, 1.0));
+            }
+        }
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0 + ROW_HEIGHT * ConditionField::ALL.len() as f32),
+                left: Val::Px(10.0),
+                width: Val::Px(340.0),
+                ..Default::default()
+            },
+            // Each side only conditions what it sends
+            Text::new("Slows down what this game sends, turn it on at both ends to slow down both ways"),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 14.0,
+                ..Default::default()
+            },
+            TextColor(Color::srgb(0.7, 0.7, 0.7)),
+        ));
+    });
+}
+
+fn condition_button_listener(
+    mut conditions: ResMut<NetworkConditions>,
+    button_query: Query<(&Interaction, &ConditionButton), Changed<Interaction>>,
+) {
+    for (interaction, ConditionButton(field, direction)) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match field.value_mut(&mut conditions) {
+            Some((value, step, max)) => *value = (*value + step * direction).clamp(0.0, max),
+            None => conditions.enabled = !conditions.enabled,
+        }
+    }
+}
+
+fn update_condition_text(
+    conditions: Res<NetworkConditions>,
+    mut text_query: Query<(&mut Text, &ConditionText)>,
+) {
+    for (mut text, ConditionText(field)) in text_query.iter_mut() {
+        // New rows start out empty
+        if conditions.is_changed() || text.0.is_empty() {
+            text.0 = field.describe(&conditions);
+        }
+    }
+}
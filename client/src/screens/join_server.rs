@@ -1,25 +1,66 @@
 use bevy::prelude::*;
 use crate::GameState;
 use crate::GameAssets;
+use crate::{ButtonAction, ButtonAssets, ButtonPosition, spawn_button};
+use crate::utils::{LanBrowser, lan_browser_system, check_server_address, PROTOCOL_VERSION, Settings, SavedServer, ServerPinger, ServerStatus, server_pinger_system, unix_time};
 use bevy_simple_text_input::*;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 pub struct JoinServerPlugin;
 
 impl Plugin for JoinServerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::JoinServer), setup_join_server)
-            .add_systems(OnExit(GameState::JoinServer), (input_grabber,cleanup_join_server).chain())
-            .add_systems(Update, input_listener.run_if(in_state(GameState::JoinServer)));
+        app.add_systems(OnEnter(GameState::JoinServer), (setup_join_server, start_lan_browser, start_server_pinger))
+            .add_systems(OnExit(GameState::JoinServer), (input_grabber, remember_statuses, cleanup_join_server).chain())
+            .add_systems(Update, (input_listener, watch_listener, update_join_error, update_lan_list, lan_entry_listener, favorite_listener, update_saved_servers, saved_server_listener, ping_saved_servers).run_if(in_state(GameState::JoinServer)))
+            .add_systems(Update, lan_browser_system.run_if(in_state(GameState::JoinServer).and(resource_exists::<LanBrowser>)))
+            .add_systems(Update, server_pinger_system.run_if(in_state(GameState::JoinServer).and(resource_exists::<ServerPinger>)));
     }
 }
 
 #[derive(Component)]
 struct JoinServerContainer;
 
+#[derive(Component)]
+struct LanGameList;
+
+// A game found on the local network, clicking it joins
+#[derive(Component)]
+struct LanGameEntry(SocketAddr);
+
+// The box the server address is typed into
+#[derive(Component)]
+struct AddressInput;
+
+// Why we can't join, from a typo in the address to the server turning us away
+#[derive(Component)]
+struct JoinErrorText;
+
+// Saves the typed address as a favorite under whatever is typed here
+#[derive(Component)]
+struct FavoriteLabelInput;
+
+#[derive(Component)]
+struct SavedServerList;
+
+// The buttons on a saved server's row, all keyed by its address
+#[derive(Component)]
+enum SavedServerButton {
+    Join(String),
+    ToggleFavorite(String),
+    Remove(String),
+}
+
+// How often saved servers are asked for their status while the join screen is open
+const PING_INTERVAL: f32 = 10.0;
+
 fn setup_join_server(
     mut commands: Commands,
-    game_assets: Res<GameAssets>,
+    mut game_assets: ResMut<GameAssets>,
+    settings: Res<Settings>,
 ) {
+    game_assets.spectate = false;
     //Text box where the user can enter the server address
     commands.spawn((Node {
         position_type: PositionType::Absolute,
@@ -50,6 +91,24 @@ fn setup_join_server(
             TextColor(Color::WHITE.into()),
             TextLayout::new(JustifyText::Center, LineBreak::WordBoundary),
         ));
+        // Why we ended up back here, if the last connection failed, kept up to date by update_join_error
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(-60.0),
+                width: Val::Px(600.0),
+                ..Default::default()
+            },
+            Text::new(game_assets.connection_error.clone().unwrap_or_default()),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 20.0,
+                ..Default::default()
+            },
+            TextColor(Color::srgb(0.9, 0.3, 0.3)),
+            TextLayout::new(JustifyText::Center, LineBreak::WordBoundary),
+            JoinErrorText,
+        ));
         parent.spawn((
             Node{
                 position_type: PositionType::Absolute,
@@ -64,13 +123,18 @@ fn setup_join_server(
             BorderColor(Color::WHITE.into()),
             BackgroundColor(Color::srgb(0.15, 0.15, 0.15).into()),
             TextInput,
+            AddressInput,
+            // Clicking between this and the label box moves the typing focus
+            Interaction::None,
+            // The server we joined last time
+            TextInputValue(settings.last_server.clone()),
             TextInputTextFont ( TextFont {
                 font: game_assets.font.clone(),
                 font_size: 20.0,
                 ..Default::default()
             }),
             TextInputPlaceholder{
-                value: "Username".to_string(),
+                value: "Server address".to_string(),
                 text_font: Some(TextFont {
                     font: game_assets.font.clone(),
                     font_size: 20.0,
@@ -80,15 +144,226 @@ fn setup_join_server(
                 ..Default::default()
             },
         ));
+        // Joins the typed address without taking a seat
+        spawn_button(
+            parent,
+            "Watch",
+            game_assets.font.clone(),
+            ButtonPosition {
+                top: Val::Px(50.0),
+                left: Val::Px(220.0),
+                width: Val::Px(120.0),
+                height: Val::Px(50.0),
+                font_size: 20.0,
+                ..Default::default()
+            },
+            ButtonAssets {
+                normal: Color::srgb(0.5, 0.5, 0.5),
+                hovered: Color::srgb(0.5, 0.5, 0.5),
+                pressed: Color::srgb(0.3, 0.3, 0.3),
+                on_click: ButtonAction::ChangeAssets(Arc::new(|game_assets| {
+                    game_assets.spectate = true;
+                })),
+            },
+        );
+        // Saves the typed address as a favorite, submitted with enter like the address
+        parent.spawn((
+            Node{
+                position_type: PositionType::Absolute,
+                top: Val::Px(110.0),
+                left: Val::Px(0.0),
+                border: UiRect::all(Val::Px(5.0)),
+                padding: UiRect::all(Val::Px(5.0)),
+                width: Val::Px(340.0),
+                height: Val::Px(50.0),
+                ..Default::default()
+            },
+            BorderColor(Color::srgb(0.6, 0.6, 0.3)),
+            BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+            TextInput,
+            TextInputInactive(true),
+            Interaction::None,
+            FavoriteLabelInput,
+            TextInputTextFont ( TextFont {
+                font: game_assets.font.clone(),
+                font_size: 20.0,
+                ..Default::default()
+            }),
+            TextInputPlaceholder{
+                value: "Name it to save as a favorite".to_string(),
+                text_font: Some(TextFont {
+                    font: game_assets.font.clone(),
+                    font_size: 18.0,
+                    ..Default::default()
+                }),
+                text_color: Some(TextColor(Color::srgb(0.7, 0.7, 0.7))),
+            },
+        ));
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(180.0),
+                width: Val::Px(600.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            LanGameList,
+        ));
     });
+
+    // Favorites and recently joined servers down the left side, filled in by update_saved_servers
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(10.0),
+            left: Val::Percent(3.0),
+            width: Val::Px(560.0),
+            flex_direction: FlexDirection::Column,
+            ..Default::default()
+        },
+        SavedServerList,
+        JoinServerContainer,
+    ));
 }
 
-fn input_grabber(
+fn start_lan_browser(mut commands: Commands) {
+    if let Some(browser) = LanBrowser::new() {
+        commands.insert_resource(browser);
+    }
+}
+
+// Rebuilds the list of LAN games whenever one shows up, goes away or changes
+fn update_lan_list(
+    mut commands: Commands,
+    browser: Option<Res<LanBrowser>>,
+    list_query: Query<Entity, With<LanGameList>>,
+    added_query: Query<(), Added<LanGameList>>,
+    game_assets: Res<GameAssets>,
+) {
+    let Some(browser) = browser else {
+        return;
+    };
+    if !browser.is_changed() && added_query.is_empty() {
+        return;
+    }
+    let mut games: Vec<_> = browser.games.iter().collect();
+    games.sort_by(|(_, a), (_, b)| a.table_name.cmp(&b.table_name));
+    for list in list_query.iter() {
+        commands.entity(list).despawn_descendants().with_children(|parent| {
+            let heading = if games.is_empty() { "No games found on your network" } else { "Games on your network" };
+            parent.spawn((
+                Text::new(heading),
+                TextFont {
+                    font: game_assets.font.clone(),
+                    font_size: 24.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            for (address, beacon) in games.iter() {
+                let compatible = beacon.protocol_version == PROTOCOL_VERSION;
+                let label = format!(
+                    "{}  {}/{} players  {}/{} blinds  {} chips{}",
+                    beacon.table_name,
+                    beacon.players,
+                    beacon.max_seats,
+                    beacon.small_blind,
+                    beacon.big_blind,
+                    beacon.starting_stack,
+                    if compatible { "" } else { "  (different version)" },
+                );
+                let normal = if compatible { Color::srgb(0.5, 0.5, 0.5) } else { Color::srgb(0.25, 0.25, 0.25) };
+                let mut entry = parent.spawn((
+                    Button,
+                    Node {
+                        width: Val::Percent(100.0),
+                        height: Val::Px(40.0),
+                        margin: UiRect::top(Val::Px(5.0)),
+                        border: UiRect::all(Val::Px(5.0)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..Default::default()
+                    },
+                    BackgroundColor(normal),
+                    BorderRadius::MAX,
+                    BorderColor(normal),
+                    ButtonAssets {
+                        normal,
+                        hovered: normal,
+                        pressed: Color::srgb(0.3, 0.3, 0.3),
+                        // Joining needs the address and a state change, lan_entry_listener does both
+                        on_click: ButtonAction::Other(Arc::new(|| {})),
+                    },
+                ));
+                entry.with_child((
+                    Text::new(label),
+                    TextFont {
+                        font: game_assets.font.clone(),
+                        font_size: 18.0,
+                        ..Default::default()
+                    },
+                    TextColor(Color::WHITE),
+                ));
+                // Joining a game we can't talk to would only end in a version mismatch
+                if compatible {
+                    entry.insert(LanGameEntry(**address));
+                }
+            }
+        });
+    }
+}
+
+fn lan_entry_listener(
     mut game_assets: ResMut<GameAssets>,
-    text_input_query: Query<&TextInputValue, With<TextInput>>,
+    mut game_state: ResMut<NextState<GameState>>,
+    entry_query: Query<(&Interaction, &LanGameEntry), Changed<Interaction>>,
+    mut text_input_query: Query<&mut TextInputValue, With<AddressInput>>,
+) {
+    for (interaction, entry) in entry_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        game_assets.server_host = entry.0.to_string();
+        game_assets.server_address = entry.0;
+        game_assets.connection_error = None;
+        game_assets.spectate = false;
+        // input_grabber remembers the text box on the way out, so it has to agree
+        for mut text_input in text_input_query.iter_mut() {
+            text_input.0 = entry.0.to_string();
+        }
+        game_state.set(GameState::Connecting);
+    }
+}
+
+// Picks the typed address to join, or says what's wrong with it and stays on the join screen.
+// Host names are looked up by the connecting screen, where waiting on DNS doesn't freeze anything.
+fn start_join(text: &str, spectate: bool, game_assets: &mut GameAssets, game_state: &mut NextState<GameState>) {
+    match check_server_address(text) {
+        Ok(()) => {
+            game_assets.server_host = text.trim().to_string();
+            game_assets.connection_error = None;
+            game_assets.spectate = spectate;
+            game_state.set(GameState::Connecting);
+            println!("Server address: {}", game_assets.server_host);
+        }
+        Err(error) => {
+            game_assets.connection_error = Some(error);
+            game_assets.spectate = false;
+        }
+    }
+}
+
+// Keeps what was typed for next time, host names and all
+fn input_grabber(
+    mut settings: ResMut<Settings>,
+    text_input_query: Query<&TextInputValue, With<AddressInput>>,
 ) {
     if let Ok(text_input) = text_input_query.get_single() {
-        game_assets.server_address = text_input.0.to_string().parse().unwrap();
+        let address = text_input.0.trim();
+        if !address.is_empty() && settings.last_server != address {
+            settings.last_server = address.to_string();
+        }
     }
 }
 
@@ -96,11 +371,43 @@ fn input_listener(
     mut game_assets: ResMut<GameAssets>,
     mut events: EventReader<TextInputSubmitEvent>,
     mut game_state: ResMut<NextState<GameState>>,
+    address_query: Query<(), With<AddressInput>>,
 ) {
     for event in events.read() {
-        game_assets.server_address = event.value.clone().parse().unwrap();
-        game_state.set(GameState::Lobby);
-        println!("Server address: {}", game_assets.server_address);
+        if !address_query.contains(event.entity) {
+            continue;
+        }
+        start_join(&event.value, false, &mut game_assets, &mut game_state);
+    }
+}
+
+// The Watch button only flags the join, the address comes from the text box
+fn watch_listener(
+    mut game_assets: ResMut<GameAssets>,
+    mut game_state: ResMut<NextState<GameState>>,
+    address_query: Query<&TextInputValue, With<AddressInput>>,
+) {
+    if !game_assets.is_changed() || !game_assets.spectate {
+        return;
+    }
+    let Ok(address) = address_query.get_single() else {
+        return;
+    };
+    start_join(&address.0, true, &mut game_assets, &mut game_state);
+}
+
+fn update_join_error(
+    game_assets: Res<GameAssets>,
+    mut text_query: Query<&mut Text, With<JoinErrorText>>,
+) {
+    if !game_assets.is_changed() {
+        return;
+    }
+    let error = game_assets.connection_error.clone().unwrap_or_default();
+    for mut text in text_query.iter_mut() {
+        if text.0 != error {
+            text.0 = error.clone();
+        }
     }
 }
 
@@ -111,4 +418,235 @@ fn cleanup_join_server(
     for entity in query.iter() {
         commands.entity(entity).despawn_recursive();
     }
+    commands.remove_resource::<LanBrowser>();
+    commands.remove_resource::<ServerPinger>();
+}
+
+fn start_server_pinger(mut commands: Commands) {
+    if let Some(pinger) = ServerPinger::new() {
+        commands.insert_resource(pinger);
+    }
+}
+
+// Asks every saved server how it's doing when the screen opens, then every few seconds
+fn ping_saved_servers(
+    pinger: Option<ResMut<ServerPinger>>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+    mut since_last: Local<Option<f32>>,
+) {
+    let Some(mut pinger) = pinger else {
+        return;
+    };
+    let elapsed = since_last.get_or_insert(PING_INTERVAL);
+    *elapsed += time.delta_secs();
+    // A server saved just now gets asked straight away
+    let is_new = settings.servers.iter().any(|server| !pinger.statuses.contains_key(&server.address));
+    if *elapsed < PING_INTERVAL && !is_new {
+        return;
+    }
+    *elapsed = 0.0;
+    for server in settings.servers.iter() {
+        pinger.query(&server.address);
+    }
+}
+
+// "5 minutes ago" and the like, for servers that didn't answer
+fn time_ago(unix_seconds: u64) -> String {
+    let seconds = unix_time().saturating_sub(unix_seconds);
+    match seconds {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", seconds / 60),
+        3600..86400 => format!("{} h ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+fn saved_server_status(server: &SavedServer, status: Option<&ServerStatus>) -> String {
+    let last_seen = if server.last_seen == 0 { "never seen".to_string() } else { format!("last seen {}", time_ago(server.last_seen)) };
+    match status {
+        Some(ServerStatus::Online(beacon, ping)) if beacon.protocol_version != PROTOCOL_VERSION => {
+            format!("{}  different version, {} ms", beacon.table_name, ping.as_millis())
+        }
+        Some(ServerStatus::Online(beacon, ping)) => {
+            format!("{}  {}/{} players, {} ms", beacon.table_name, beacon.players, beacon.max_seats, ping.as_millis())
+        }
+        Some(ServerStatus::Offline) => format!("Offline, {last_seen}"),
+        _ => match server.last_ping_ms {
+            Some(ping) => format!("Checking... {last_seen} at {ping} ms"),
+            None => format!("Checking... {last_seen}"),
+        },
+    }
+}
+
+// Rebuilds the saved servers whenever one is added, changed or answers a ping
+fn update_saved_servers(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    pinger: Option<Res<ServerPinger>>,
+    list_query: Query<(Entity, Ref<SavedServerList>)>,
+    game_assets: Res<GameAssets>,
+) {
+    let pinger_changed = pinger.as_ref().is_some_and(|pinger| pinger.is_changed());
+    let is_new = list_query.iter().any(|(_, list)| list.is_added());
+    if !settings.is_changed() && !pinger_changed && !is_new {
+        return;
+    }
+    let favorites: Vec<&SavedServer> = settings.servers.iter().filter(|server| server.favorite).collect();
+    let recent: Vec<&SavedServer> = settings.servers.iter().filter(|server| !server.favorite).collect();
+    for (list, _) in list_query.iter() {
+        commands.entity(list).despawn_descendants().with_children(|parent| {
+            for (heading, servers) in [("Favorites", &favorites), ("Recent servers", &recent)] {
+                if servers.is_empty() {
+                    continue;
+                }
+                parent.spawn((
+                    Text::new(heading),
+                    TextFont {
+                        font: game_assets.font.clone(),
+                        font_size: 24.0,
+                        ..Default::default()
+                    },
+                    TextColor(Color::WHITE),
+                    Node {
+                        margin: UiRect::top(Val::Px(10.0)),
+                        ..Default::default()
+                    },
+                ));
+                for server in servers.iter() {
+                    let status = pinger.as_ref().and_then(|pinger| pinger.statuses.get(&server.address));
+                    let label = format!("{}\n{}", server.name(), saved_server_status(server, status));
+                    parent.spawn(Node {
+                        width: Val::Percent(100.0),
+                        margin: UiRect::top(Val::Px(5.0)),
+                        column_gap: Val::Px(5.0),
+                        ..Default::default()
+                    })
+                    .with_children(|row| {
+                        spawn_row_button(row, &game_assets, &label, Val::Percent(100.0), SavedServerButton::Join(server.address.clone()));
+                        let favorite = if server.favorite { "Unfav" } else { "Fav" };
+                        spawn_row_button(row, &game_assets, favorite, Val::Px(80.0), SavedServerButton::ToggleFavorite(server.address.clone()));
+                        spawn_row_button(row, &game_assets, "X", Val::Px(50.0), SavedServerButton::Remove(server.address.clone()));
+                    });
+                }
+            }
+        });
+    }
+}
+
+// Same look as the LAN list entries, sized to sit in a row
+fn spawn_row_button(parent: &mut ChildBuilder, game_assets: &GameAssets, text: &str, width: Val, button: SavedServerButton) {
+    let normal = Color::srgb(0.5, 0.5, 0.5);
+    parent.spawn((
+        Button,
+        Node {
+            width,
+            min_width: width,
+            flex_shrink: if matches!(button, SavedServerButton::Join(_)) { 1.0 } else { 0.0 },
+            min_height: Val::Px(50.0),
+            border: UiRect::all(Val::Px(5.0)),
+            padding: UiRect::horizontal(Val::Px(10.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        BackgroundColor(normal),
+        BorderRadius::all(Val::Px(10.0)),
+        BorderColor(normal),
+        ButtonAssets {
+            normal,
+            hovered: normal,
+            pressed: Color::srgb(0.3, 0.3, 0.3),
+            // saved_server_listener does the work
+            on_click: ButtonAction::Other(Arc::new(|| {})),
+        },
+        button,
+    ))
+    .with_child((
+        Text::new(text),
+        TextFont {
+            font: game_assets.font.clone(),
+            font_size: 16.0,
+            ..Default::default()
+        },
+        TextColor(Color::WHITE),
+        TextLayout::new_with_justify(JustifyText::Center),
+    ));
+}
+
+fn saved_server_listener(
+    mut game_assets: ResMut<GameAssets>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut settings: ResMut<Settings>,
+    button_query: Query<(&Interaction, &SavedServerButton), Changed<Interaction>>,
+    mut address_query: Query<&mut TextInputValue, With<AddressInput>>,
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            SavedServerButton::Join(address) => {
+                // input_grabber remembers the text box on the way out, so it has to agree
+                for mut text_input in address_query.iter_mut() {
+                    text_input.0 = address.clone();
+                }
+                start_join(address, false, &mut game_assets, &mut game_state);
+            }
+            SavedServerButton::ToggleFavorite(address) => {
+                if let Some(server) = settings.servers.iter_mut().find(|server| server.address == *address) {
+                    server.favorite = !server.favorite;
+                }
+            }
+            SavedServerButton::Remove(address) => {
+                settings.servers.retain(|server| server.address != *address);
+            }
+        }
+    }
+}
+
+// Enter in the label box saves whatever address is typed as a favorite under that name
+fn favorite_listener(
+    mut events: EventReader<TextInputSubmitEvent>,
+    mut settings: ResMut<Settings>,
+    mut game_assets: ResMut<GameAssets>,
+    mut label_query: Query<&mut TextInputInactive, With<FavoriteLabelInput>>,
+    address_query: Query<&TextInputValue, With<AddressInput>>,
+) {
+    for event in events.read() {
+        let Ok(mut inactive) = label_query.get_mut(event.entity) else {
+            continue;
+        };
+        inactive.0 = true;
+        let Ok(address) = address_query.get_single() else {
+            continue;
+        };
+        // Saved as typed, so a host name still finds its server after the IP changes
+        match check_server_address(&address.0) {
+            Ok(()) => settings.favorite_server(address.0.trim(), event.value.trim()),
+            Err(error) => game_assets.connection_error = Some(error),
+        }
+    }
+}
+
+// Keeps what the pings found, so the list has something to show next time before they answer
+fn remember_statuses(
+    pinger: Option<Res<ServerPinger>>,
+    mut settings: ResMut<Settings>,
+) {
+    let Some(pinger) = pinger else {
+        return;
+    };
+    let now = unix_time();
+    let mut servers = settings.servers.clone();
+    for server in servers.iter_mut() {
+        if let Some(ServerStatus::Online(_, ping)) = pinger.statuses.get(&server.address) {
+            server.last_seen = now;
+            server.last_ping_ms = Some(ping.as_millis() as u32);
+        }
+    }
+    // One save on the way out instead of one per ping
+    if servers != settings.servers {
+        settings.servers = servers;
+    }
 }
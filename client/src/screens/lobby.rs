@@ -1,25 +1,592 @@
 use bevy::prelude::*;
-use crate::GameState;
+use crate::{GameState, ServerMode};
 use crate::GameAssets;
+use crate::{ButtonAction, ButtonAssets, ButtonPosition, spawn_button};
+use crate::utils::{Lobby, ServerMessage, Stage, TableConnections, TableDirectory, TableId, TableSettings, Variant};
+use bevy_renet::renet::{RenetClient, DefaultChannel, Bytes};
+use bevy_simple_text_input::*;
+use std::sync::Arc;
 
 pub struct LobbyPlugin;
 
 impl Plugin for LobbyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Lobby), setup_lobby);
+        app.add_systems(OnEnter(GameState::Lobby), setup_lobby)
+            .add_systems(OnExit(GameState::Lobby), cleanup_lobby)
+            .add_systems(Update, (update_table_info, update_player_list, update_table_list, update_lobby_buttons, ready_listener, table_entry_listener, variant_listener, new_table_listener, refresh_table_list).run_if(in_state(GameState::Lobby)));
     }
 }
 
+// How often the table list is asked for again while sitting in the lobby, in seconds
+const TABLE_LIST_REFRESH: f32 = 5.0;
+
 #[derive(Component)]
 struct LobbyContainer;
 
+#[derive(Component)]
+struct LobbyTitle;
+
+#[derive(Component)]
+struct LobbySettings;
+
+#[derive(Component)]
+struct LobbyPlayerList;
+
+#[derive(Component)]
+struct LobbyTableList;
+
+// Another table on the server, clicking it moves us there
+#[derive(Component)]
+struct LobbyTableEntry(TableId);
+
+#[derive(Component)]
+struct NewTableInput;
+
+// The rules picked for a new table, read when its name is submitted
+#[derive(Component, Clone, Copy)]
+enum NewTableField {
+    SmallBlind,
+    BigBlind,
+    Seats,
+}
+
+// Clicking it goes to the next variant
+#[derive(Component)]
+struct NewTableVariant(Variant);
+
+// Buttons that are only shown to some of the people at the table
+#[derive(Component, PartialEq)]
+enum LobbyButton {
+    StartGame,
+    Ready,
+    TakeSeat,
+}
+
 fn setup_lobby(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
+    server_mode: Res<State<ServerMode>>,
 ) {
-    // A circle that contains the usernames of the players in the lobby
+    let is_host = *server_mode.get() == ServerMode::Host;
+    let button_assets = |on_click| ButtonAssets {
+        normal: Color::srgb(0.5, 0.5, 0.5),
+        hovered: Color::srgb(0.5, 0.5, 0.5),
+        pressed: Color::srgb(0.3, 0.3, 0.3),
+        on_click,
+    };
     commands.spawn((Node {
         position_type: PositionType::Absolute,
+        width: Val::Percent(100.0),
+        height: Val::Percent(100.0),
         ..Default::default()
-    }, LobbyContainer));
-}
\ No newline at end of file
+    }, LobbyContainer))
+    .with_children(|parent| {
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(20.0),
+                left: Val::Px(20.0),
+                ..Default::default()
+            },
+            Text::new(""),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 40.0,
+                ..Default::default()
+            },
+            TextColor(Color::WHITE),
+            LobbyTitle,
+        ));
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(75.0),
+                left: Val::Px(20.0),
+                ..Default::default()
+            },
+            Text::new(""),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 20.0,
+                ..Default::default()
+            },
+            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+            LobbySettings,
+        ));
+        // Seated players, filled in by update_player_list
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(120.0),
+                left: Val::Px(20.0),
+                width: Val::Px(520.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(6.0),
+                ..Default::default()
+            },
+            LobbyPlayerList,
+        ));
+        // The other tables on the server, filled in by update_table_list
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(20.0),
+                right: Val::Px(20.0),
+                width: Val::Px(420.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(6.0),
+                ..Default::default()
+            },
+            LobbyTableList,
+        ));
+        // The host always runs the main table, only players can open another one
+        if !is_host {
+            parent.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(380.0),
+                    right: Val::Px(20.0),
+                    width: Val::Px(420.0),
+                    height: Val::Px(36.0),
+                    border: UiRect::all(Val::Px(2.0)),
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..Default::default()
+                },
+                BorderColor(Color::WHITE),
+                BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                Interaction::None,
+                TextInput,
+                TextInputInactive(true),
+                TextInputTextFont(TextFont {
+                    font: game_assets.font.clone(),
+                    font_size: 16.0,
+                    ..Default::default()
+                }),
+                TextInputPlaceholder {
+                    value: "Name a new table and press enter".to_string(),
+                    text_font: Some(TextFont {
+                        font: game_assets.font.clone(),
+                        font_size: 16.0,
+                        ..Default::default()
+                    }),
+                    text_color: Some(TextColor(Color::srgb(0.6, 0.6, 0.6))),
+                },
+                NewTableInput,
+            ));
+            let defaults = TableSettings::default();
+            let fields = [
+                (NewTableField::SmallBlind, "Small blind", defaults.small_blind.to_string()),
+                (NewTableField::BigBlind, "Big blind", defaults.big_blind.to_string()),
+                (NewTableField::Seats, "Seats", defaults.max_seats.to_string()),
+            ];
+            parent.spawn(Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(426.0),
+                right: Val::Px(20.0),
+                width: Val::Px(420.0),
+                column_gap: Val::Px(10.0),
+                ..Default::default()
+            })
+            .with_children(|row| {
+                for (field, label, value) in fields {
+                    row.spawn(Node {
+                        flex_direction: FlexDirection::Column,
+                        flex_grow: 1.0,
+                        ..Default::default()
+                    })
+                    .with_children(|column| {
+                        column.spawn((
+                            Text::new(label),
+                            TextFont {
+                                font: game_assets.font.clone(),
+                                font_size: 14.0,
+                                ..Default::default()
+                            },
+                            TextColor(Color::srgb(0.8, 0.8, 0.8)),
+                        ));
+                        column.spawn((
+                            Node {
+                                height: Val::Px(36.0),
+                                border: UiRect::all(Val::Px(2.0)),
+                                padding: UiRect::all(Val::Px(5.0)),
+                                ..Default::default()
+                            },
+                            BorderColor(Color::WHITE),
+                            BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                            Interaction::None,
+                            TextInput,
+                            TextInputValue(value),
+                            TextInputInactive(true),
+                            // Only the name box sends the table off, these keep what was typed
+                            TextInputSettings {
+                                retain_on_submit: true,
+                                ..Default::default()
+                            },
+                            TextInputTextFont(TextFont {
+                                font: game_assets.font.clone(),
+                                font_size: 16.0,
+                                ..Default::default()
+                            }),
+                            field,
+                        ));
+                    });
+                }
+            });
+            spawn_button(
+                parent,
+                &defaults.variant.to_string(),
+                game_assets.font.clone(),
+                ButtonPosition {
+                    top: Val::Px(496.0),
+                    right: Val::Px(20.0),
+                    width: Val::Px(420.0),
+                    height: Val::Px(40.0),
+                    font_size: 18.0,
+                    ..Default::default()
+                },
+                // variant_listener knows which variant is picked, so it moves it on
+                button_assets(ButtonAction::Other(Arc::new(|| {}))),
+            ).insert(NewTableVariant(defaults.variant));
+        }
+
+        spawn_button(
+            parent,
+            "Leave",
+            game_assets.font.clone(),
+            ButtonPosition {
+                bottom: Val::Px(20.0),
+                right: Val::Px(20.0),
+                ..Default::default()
+            },
+            // Leaving the server mode hangs up, or shuts the server down for the host
+            button_assets(ButtonAction::ChangeServerMode(Arc::new(|server_mode| {
+                server_mode.set(ServerMode::None);
+            }))),
+        );
+        // The server deals the first hand and sends everyone at the table into the game
+        let start_game = ButtonAction::SendMessage(Arc::new(|| ServerMessage::StartGame));
+        spawn_button(
+            parent,
+            "Start game",
+            game_assets.font.clone(),
+            ButtonPosition {
+                bottom: Val::Px(20.0),
+                right: Val::Px(240.0),
+                width: Val::Px(250.0),
+                ..Default::default()
+            },
+            button_assets(start_game),
+        ).insert(LobbyButton::StartGame);
+        // ready_listener knows whether we are ready, so it sends the message
+        spawn_button(
+            parent,
+            "Ready",
+            game_assets.font.clone(),
+            ButtonPosition {
+                bottom: Val::Px(20.0),
+                right: Val::Px(510.0),
+                width: Val::Px(250.0),
+                ..Default::default()
+            },
+            button_assets(ButtonAction::Other(Arc::new(|| {}))),
+        ).insert(LobbyButton::Ready);
+        // Spectators go on the waitlist and are seated when a seat opens between hands
+        spawn_button(
+            parent,
+            "Take a seat",
+            game_assets.font.clone(),
+            ButtonPosition {
+                bottom: Val::Px(20.0),
+                right: Val::Px(510.0),
+                width: Val::Px(250.0),
+                ..Default::default()
+            },
+            button_assets(ButtonAction::SendMessage(Arc::new(|| ServerMessage::RequestSeat))),
+        ).insert(LobbyButton::TakeSeat);
+    });
+}
+
+fn update_table_info(
+    lobby: Res<Lobby>,
+    mut title_query: Query<&mut Text, (With<LobbyTitle>, Without<LobbySettings>)>,
+    mut settings_query: Query<&mut Text, (With<LobbySettings>, Without<LobbyTitle>)>,
+) {
+    if !lobby.is_changed() {
+        return;
+    }
+    let title = if lobby.name.is_empty() { "Waiting for the table".to_string() } else { lobby.name.clone() };
+    for mut text in title_query.iter_mut() {
+        if text.0 != title {
+            text.0 = title.clone();
+        }
+    }
+    let settings = &lobby.settings;
+    let summary = format!(
+        "{}   Blinds {}/{}   {}/{} seats   {} starting chips",
+        settings.variant, settings.small_blind, settings.big_blind, lobby.players.len(), settings.max_seats, settings.starting_stack,
+    );
+    for mut text in settings_query.iter_mut() {
+        if text.0 != summary {
+            text.0 = summary.clone();
+        }
+    }
+}
+
+fn update_player_list(
+    mut commands: Commands,
+    lobby: Res<Lobby>,
+    table_connections: Res<TableConnections>,
+    list_query: Query<Entity, With<LobbyPlayerList>>,
+    added_query: Query<(), Added<LobbyPlayerList>>,
+    game_assets: Res<GameAssets>,
+    mut shown: Local<Vec<(String, bool)>>,
+) {
+    let rows: Vec<(String, bool)> = lobby.players.iter().map(|player| {
+        let mut label = format!("{}   {} chips   {}", player.name, player.money, if player.is_ready { "Ready" } else { "Not ready" });
+        if lobby.owner == Some(player.client_id) {
+            label.push_str("   (runs the table)");
+        }
+        if player.is_disconnected {
+            label.push_str("   (away)");
+        } else if let Some(quality) = table_connections.get(player.client_id) {
+            label.push_str(&format!("   {} ms", quality.ping_ms));
+            if quality.is_lagging() {
+                label.push_str(" (lagging)");
+            }
+        }
+        (label, player.is_ready)
+    }).collect();
+    // Only rebuild when something we show changed
+    if *shown == rows && added_query.is_empty() {
+        return;
+    }
+    for list in list_query.iter() {
+        commands.entity(list).despawn_descendants().with_children(|parent| {
+            if rows.is_empty() {
+                parent.spawn((
+                    Text::new("Nobody is seated yet"),
+                    TextFont {
+                        font: game_assets.font.clone(),
+                        font_size: 24.0,
+                        ..Default::default()
+                    },
+                    TextColor(Color::srgb(0.6, 0.6, 0.6)),
+                ));
+            }
+            for (label, is_ready) in rows.iter() {
+                let color = if *is_ready { Color::srgb(0.5, 0.9, 0.5) } else { Color::WHITE };
+                parent.spawn((
+                    Text::new(label.clone()),
+                    TextFont {
+                        font: game_assets.font.clone(),
+                        font_size: 24.0,
+                        ..Default::default()
+                    },
+                    TextColor(color),
+                ));
+            }
+        });
+    }
+    *shown = rows;
+}
+
+fn update_table_list(
+    mut commands: Commands,
+    table_directory: Res<TableDirectory>,
+    lobby: Res<Lobby>,
+    server_mode: Res<State<ServerMode>>,
+    list_query: Query<(Entity, Ref<LobbyTableList>)>,
+    game_assets: Res<GameAssets>,
+    mut shown_table: Local<Option<TableId>>,
+) {
+    let table_id = (!lobby.name.is_empty()).then_some(lobby.id);
+    let is_new = list_query.iter().any(|(_, list)| list.is_added());
+    if !table_directory.is_changed() && *shown_table == table_id && !is_new {
+        return;
+    }
+    *shown_table = table_id;
+    let can_move = *server_mode.get() == ServerMode::Join;
+    for (list, _) in list_query.iter() {
+        commands.entity(list).despawn_descendants().with_children(|parent| {
+            parent.spawn((
+                Text::new("Tables on this server"),
+                TextFont {
+                    font: game_assets.font.clone(),
+                    font_size: 24.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            for table in table_directory.tables.iter() {
+                let is_ours = table_id == Some(table.id);
+                let label = format!(
+                    "{}  {}/{} players  {} chips{}",
+                    table.name,
+                    table.players,
+                    table.max_seats,
+                    table.starting_stack,
+                    if is_ours { "  (you are here)" } else if table.in_progress { "  (playing)" } else { "" },
+                );
+                let mut entry = parent.spawn((
+                    Node {
+                        padding: UiRect::all(Val::Px(6.0)),
+                        ..Default::default()
+                    },
+                    BackgroundColor(if is_ours { Color::srgb(0.25, 0.35, 0.25) } else { Color::srgb(0.2, 0.2, 0.2) }),
+                ));
+                entry.with_child((
+                    Text::new(label),
+                    TextFont {
+                        font: game_assets.font.clone(),
+                        font_size: 18.0,
+                        ..Default::default()
+                    },
+                    TextColor(Color::WHITE),
+                ));
+                if can_move && !is_ours {
+                    entry.insert((Button, LobbyTableEntry(table.id)));
+                }
+            }
+        });
+    }
+}
+
+// Shows the buttons that apply to us: start for whoever runs the table, ready for seated players, a seat for spectators
+fn update_lobby_buttons(
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    mut button_query: Query<(&LobbyButton, &mut Visibility, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    let is_waiting = lobby.stage == Stage::Waiting;
+    let runs_table = lobby.owner == Some(game_assets.client_id);
+    let seat = lobby.players.iter().find(|player| player.client_id == game_assets.client_id);
+    let shown = |show: bool| if show { Visibility::Inherited } else { Visibility::Hidden };
+
+    for (button, mut visibility, children) in button_query.iter_mut() {
+        let show = match button {
+            LobbyButton::StartGame => runs_table && is_waiting,
+            LobbyButton::Ready => is_waiting && seat.is_some(),
+            LobbyButton::TakeSeat => seat.is_none() && !lobby.name.is_empty(),
+        };
+        visibility.set_if_neq(shown(show));
+        if *button != LobbyButton::Ready {
+            continue;
+        }
+        let label = if seat.is_some_and(|player| player.is_ready) { "Not ready" } else { "Ready" };
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(*child) {
+                if text.0 != label {
+                    text.0 = label.to_string();
+                }
+            }
+        }
+    }
+}
+
+fn ready_listener(
+    client: Option<ResMut<RenetClient>>,
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    button_query: Query<(&Interaction, &LobbyButton), Changed<Interaction>>,
+) {
+    let Some(mut client) = client else {
+        return;
+    };
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Pressed || *button != LobbyButton::Ready {
+            continue;
+        }
+        let is_ready = lobby.players.iter().any(|player| player.client_id == game_assets.client_id && player.is_ready);
+        client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::Ready(!is_ready)));
+    }
+}
+
+fn table_entry_listener(
+    client: Option<ResMut<RenetClient>>,
+    entry_query: Query<(&Interaction, &LobbyTableEntry), Changed<Interaction>>,
+) {
+    let Some(mut client) = client else {
+        return;
+    };
+    for (interaction, entry) in entry_query.iter() {
+        if *interaction == Interaction::Pressed {
+            client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::JoinTable(entry.0)));
+        }
+    }
+}
+
+fn variant_listener(
+    mut button_query: Query<(&Interaction, &mut NewTableVariant, &Children), Changed<Interaction>>,
+    mut text_query: Query<&mut Text>,
+) {
+    for (interaction, mut variant, children) in button_query.iter_mut() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        variant.0 = variant.0.next();
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(*child) {
+                text.0 = variant.0.to_string();
+            }
+        }
+    }
+}
+
+fn new_table_listener(
+    mut events: EventReader<TextInputSubmitEvent>,
+    client: Option<ResMut<RenetClient>>,
+    input_query: Query<(), With<NewTableInput>>,
+    field_query: Query<(&NewTableField, &TextInputValue)>,
+    variant_query: Query<&NewTableVariant>,
+) {
+    let Some(mut client) = client else {
+        return;
+    };
+    for event in events.read() {
+        if !input_query.contains(event.entity) {
+            continue;
+        }
+        // Anything that isn't a number stays at the default, the server clamps the rest
+        let mut settings = TableSettings::default();
+        for (field, value) in field_query.iter() {
+            let value = value.0.trim();
+            match field {
+                NewTableField::SmallBlind => settings.small_blind = value.parse().unwrap_or(settings.small_blind),
+                NewTableField::BigBlind => settings.big_blind = value.parse().unwrap_or(settings.big_blind),
+                NewTableField::Seats => settings.max_seats = value.parse().unwrap_or(settings.max_seats),
+            }
+        }
+        if let Ok(variant) = variant_query.get_single() {
+            settings.variant = variant.0;
+        }
+        // The server names it after us if the box was left empty
+        client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::CreateTable(event.value.clone(), settings)));
+    }
+}
+
+// Tables open and fill up while we wait, keep the list current
+fn refresh_table_list(
+    client: Option<ResMut<RenetClient>>,
+    time: Res<Time>,
+    mut since_last_refresh: Local<f32>,
+) {
+    let Some(mut client) = client.filter(|client| client.is_connected()) else {
+        return;
+    };
+    *since_last_refresh += time.delta_secs();
+    if *since_last_refresh < TABLE_LIST_REFRESH {
+        return;
+    }
+    *since_last_refresh = 0.0;
+    client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::ListTables));
+}
+
+fn cleanup_lobby(
+    mut commands: Commands,
+    query: Query<Entity, With<LobbyContainer>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
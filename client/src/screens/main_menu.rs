@@ -2,6 +2,7 @@ use bevy::prelude::*;
 use crate::button_manager::{ButtonAssets, spawn_button, ButtonAction};
 use crate::GameState;
 use crate::GameAssets;
+use crate::utils::Settings;
 use std::sync::Arc;
 use crate::ButtonPosition;
 use bevy_simple_text_input::*;
@@ -19,6 +20,12 @@ impl Plugin for MainMenuPlugin {
 #[derive(Component)]
 struct MainMenuContainer;
 
+#[derive(Component)]
+struct UsernameInput;
+
+#[derive(Component)]
+struct PasswordInput;
+
 const PLAY_BUTTON: Color = Color::srgb(0.15, 0.45, 0.15);
 const SETTINGS_BUTTON: Color = Color::srgb(0.15, 0.15, 0.45);
 const QUIT_BUTTON: Color = Color::srgb(0.45, 0.15, 0.15);
@@ -35,6 +42,7 @@ const HEIGHT_FROM_TOP: f32 = 160.0;
 fn setup_main_menu(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
+    settings: Res<Settings>,
 ){
     commands.spawn((
         Node{
@@ -61,7 +69,7 @@ fn setup_main_menu(
                 ..Default::default()
             }
         ));
-        spawn_main_menu_buttons(parent, game_assets);
+        spawn_main_menu_buttons(parent, game_assets, &settings.player_name);
     });
 }
 
@@ -69,6 +77,7 @@ fn setup_main_menu(
 fn spawn_main_menu_buttons(
     parent: &mut ChildBuilder,
     game_assets: Res<GameAssets>,
+    player_name: &str,
 ){
     let mut button_height = HEIGHT_FROM_TOP;
     let increment = BUTTON_INCREMENT;
@@ -110,6 +119,10 @@ fn spawn_main_menu_buttons(
         BorderColor(PLAY_BUTTON),
         BackgroundColor(Color::srgb(0.15, 0.15, 0.15).into()),
         TextInput,
+        // Whatever name was used last time
+        TextInputValue(player_name.to_string()),
+        Interaction::None,
+        UsernameInput,
         TextInputTextFont ( TextFont {
             font: game_assets.font.clone(),
             font_size: BUTTON_FONT_SIZE+5.0,
@@ -127,6 +140,45 @@ fn spawn_main_menu_buttons(
         },
     ));
 
+    button_height += increment;
+    // Spawn textbox for the password, only needed for servers with a login service
+    parent.spawn((
+        Node{
+            position_type: PositionType::Absolute,
+            top: Val::Px(button_height),
+            left: Val::Px(0.),
+            border: UiRect::all(Val::Px(5.0)),
+            padding: UiRect::all(Val::Px(5.0)),
+            width: Val::Px(200.0),
+            height: Val::Px(BUTTON_HEIGHT),
+            ..Default::default()
+        },
+        BorderColor(PLAY_BUTTON),
+        BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+        TextInput,
+        Interaction::None,
+        PasswordInput,
+        TextInputInactive(true),
+        TextInputSettings {
+            mask_character: Some('*'),
+            retain_on_submit: true,
+        },
+        TextInputTextFont ( TextFont {
+            font: game_assets.font.clone(),
+            font_size: BUTTON_FONT_SIZE+5.0,
+            ..Default::default()
+        }),
+        TextInputPlaceholder{
+            value: "Password".to_string(),
+            text_font: Some(TextFont {
+                font: game_assets.font.clone(),
+                font_size: BUTTON_FONT_SIZE-5.0,
+                ..Default::default()
+            }),
+            text_color: Some(TextColor(Color::WHITE)),
+        },
+    ));
+
 
     button_height += increment;
     spawn_button(
@@ -179,23 +231,30 @@ fn spawn_main_menu_buttons(
 
 fn input_grabber(
     mut game_assets: ResMut<GameAssets>,
-    text_input_query: Query<&TextInputValue, With<TextInput>>,
+    mut settings: ResMut<Settings>,
+    username_query: Query<&TextInputValue, With<UsernameInput>>,
+    password_query: Query<&TextInputValue, With<PasswordInput>>,
 ) {
-    if let Ok(text_input) = text_input_query.get_single() {
+    if let Ok(text_input) = username_query.get_single() {
         game_assets.player_name = text_input.0.to_string();
         println!("Player name: {}", game_assets.player_name);
+        // Only touch the settings when the name changed, every change is a save
+        if settings.player_name != game_assets.player_name {
+            settings.player_name = game_assets.player_name.clone();
+        }
+    }
+    if let Ok(text_input) = password_query.get_single() {
+        game_assets.password = text_input.0.to_string();
     }
 }
 
 fn input_listener(
-    mut game_assets: ResMut<GameAssets>,
     mut events: EventReader<TextInputSubmitEvent>,
     mut game_state: ResMut<NextState<GameState>>,
 ) {
-    for event in events.read() {
-        game_assets.player_name = event.value.clone();
+    // The values themselves are picked up by input_grabber when the menu closes
+    for _ in events.read() {
         game_state.set(GameState::ServerSelect);
-        println!("Player name: {}", game_assets.player_name);
     }
 }
 
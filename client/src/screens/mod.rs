@@ -10,15 +10,29 @@ mod server_select;
 use server_select::ServerSelectPlugin;
 mod join_server;
 use join_server::JoinServerPlugin;
+mod connecting;
+use connecting::ConnectingPlugin;
 mod lobby;
 use lobby::LobbyPlugin;
+mod chat;
+use chat::ChatPlugin;
+pub(crate) mod table;
+use table::TablePlugin;
+mod bet_panel;
+use bet_panel::BetPanelPlugin;
+mod pre_actions;
+use pre_actions::PreActionPlugin;
+mod debug_menu;
+use debug_menu::DebugMenuPlugin;
+mod connection_hud;
+use connection_hud::ConnectionHudPlugin;
 
 
 pub struct ScreenPlugin;
 
 impl Plugin for ScreenPlugin {
     fn build(&self, app: &mut App){
-        app.add_plugins((LoadingScreenPlugin, MainMenuPlugin, SettingsPlugin, ServerSelectPlugin, JoinServerPlugin, LobbyPlugin));
+        app.add_plugins((LoadingScreenPlugin, MainMenuPlugin, SettingsPlugin, ServerSelectPlugin, JoinServerPlugin, ConnectingPlugin, LobbyPlugin, ChatPlugin, TablePlugin, BetPanelPlugin, PreActionPlugin, DebugMenuPlugin, ConnectionHudPlugin));
     }
 }
 
@@ -0,0 +1,226 @@
+use bevy::prelude::*;
+use crate::{GameState, GameAssets};
+use crate::{ButtonAction, ButtonAssets, ButtonPosition, spawn_button};
+use crate::utils::{Action, Lobby, Stage};
+use std::sync::Arc;
+
+pub struct PreActionPlugin;
+
+impl Plugin for PreActionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<QueuedPreAction>()
+            .add_systems(OnEnter(GameState::InGame), setup_pre_actions)
+            .add_systems(OnExit(GameState::InGame), cleanup_pre_actions)
+            .add_systems(Update, (invalidate_pre_action, fire_pre_action, pre_action_listener, update_pre_action_bar).chain().run_if(in_state(GameState::InGame)));
+    }
+}
+
+// What to do once it is our turn, picked while someone else is still thinking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PreAction {
+    // Check if nobody bets, fold otherwise
+    CheckFold,
+    Check,
+    // Only calls the amount it was queued with, a raise in between cancels it
+    Call(i32),
+    // Calls whatever it costs, going all in if that's what it takes
+    CallAny,
+    Fold,
+    // Like check/fold, but it keeps going for the rest of the hand
+    FoldToAnyBet,
+}
+
+impl PreAction {
+    fn label(&self) -> String {
+        match self {
+            PreAction::CheckFold => "Check/fold".to_string(),
+            PreAction::Check => "Check".to_string(),
+            PreAction::Call(amount) => format!("Call {}", amount),
+            PreAction::CallAny => "Call any".to_string(),
+            PreAction::Fold => "Fold".to_string(),
+            PreAction::FoldToAnyBet => "Fold to any bet".to_string(),
+        }
+    }
+
+    // The action to play now that it is our turn
+    fn resolve(&self, legal: &[Action]) -> Action {
+        let can_check = legal.iter().any(|action| matches!(action, Action::Check));
+        let can_call = legal.iter().any(|action| matches!(action, Action::Call));
+        match self {
+            PreAction::CheckFold | PreAction::FoldToAnyBet | PreAction::Fold if can_check => Action::Check,
+            PreAction::CheckFold | PreAction::FoldToAnyBet | PreAction::Fold => Action::Fold,
+            PreAction::Check => Action::Check,
+            PreAction::CallAny if can_check => Action::Check,
+            // Short stacks can only call by going all in
+            PreAction::Call(_) | PreAction::CallAny if can_call => Action::Call,
+            PreAction::Call(_) | PreAction::CallAny => Action::AllIn,
+        }
+    }
+}
+
+// The pre-action we are holding, along with what the table looked like when it was picked
+#[derive(Resource, Default)]
+struct QueuedPreAction {
+    action: Option<PreAction>,
+    stage: Stage,
+    to_call: i32,
+}
+
+#[derive(Component)]
+struct PreActionBar;
+
+// Toggles its pre-action when clicked
+#[derive(Component)]
+struct PreActionButton(PreAction);
+
+fn setup_pre_actions(
+    mut commands: Commands,
+) {
+    // Takes the action bar's place while it is someone else's turn
+    commands.spawn((Node {
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(20.0),
+        right: Val::Px(20.0),
+        width: Val::Px(880.0),
+        height: Val::Px(65.0),
+        ..Default::default()
+    }, PreActionBar));
+}
+
+// Pre-actions we could queue right now, empty on our own turn or when we're out of the hand
+fn offered_pre_actions(lobby: &Lobby, client_id: u64) -> Vec<PreAction> {
+    let Some(player) = lobby.players.iter().find(|player| player.client_id == client_id) else {
+        return Vec::new();
+    };
+    let is_our_turn = lobby.players.get(lobby.turn as usize).is_some_and(|player| player.client_id == client_id);
+    if !lobby.is_betting() || is_our_turn || player.is_folded || player.is_all_in || player.hand.is_empty() {
+        return Vec::new();
+    }
+    let to_call = lobby.to_call(client_id);
+    if to_call == 0 {
+        vec![PreAction::CheckFold, PreAction::Check, PreAction::CallAny, PreAction::FoldToAnyBet]
+    } else {
+        vec![PreAction::Fold, PreAction::Call(to_call.min(player.money)), PreAction::CallAny, PreAction::FoldToAnyBet]
+    }
+}
+
+// Drops the queued pre-action once the table has moved on from what it was picked for
+fn invalidate_pre_action(
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    mut queued: ResMut<QueuedPreAction>,
+) {
+    let Some(action) = queued.action else {
+        return;
+    };
+    let client_id = game_assets.client_id;
+    let in_hand = lobby.players.iter()
+        .find(|player| player.client_id == client_id)
+        .is_some_and(|player| !player.is_folded && !player.is_all_in && !player.hand.is_empty());
+    let to_call = lobby.to_call(client_id);
+    let is_stale = match action {
+        PreAction::FoldToAnyBet => false,
+        PreAction::Check => to_call > 0 || lobby.stage != queued.stage,
+        PreAction::Call(_) => to_call != queued.to_call || lobby.stage != queued.stage,
+        _ => lobby.stage != queued.stage,
+    };
+    if !lobby.is_betting() || !in_hand || is_stale {
+        queued.action = None;
+    }
+}
+
+// Plays the queued pre-action as soon as the turn comes to us
+fn fire_pre_action(
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    mut queued: ResMut<QueuedPreAction>,
+    mut actions: EventWriter<Action>,
+) {
+    let Some(pre_action) = queued.action else {
+        return;
+    };
+    let legal = lobby.legal_actions(game_assets.client_id);
+    if legal.is_empty() {
+        return;
+    }
+    actions.send(pre_action.resolve(&legal));
+    // Fold to any bet sticks around for the next street
+    if pre_action == PreAction::FoldToAnyBet {
+        queued.stage = lobby.stage;
+    } else {
+        queued.action = None;
+    }
+}
+
+fn pre_action_listener(
+    button_query: Query<(&Interaction, &PreActionButton), Changed<Interaction>>,
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    mut queued: ResMut<QueuedPreAction>,
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if queued.action == Some(button.0) {
+            queued.action = None;
+        } else {
+            queued.action = Some(button.0);
+            queued.stage = lobby.stage;
+            queued.to_call = lobby.to_call(game_assets.client_id);
+        }
+    }
+}
+
+fn update_pre_action_bar(
+    mut commands: Commands,
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    queued: Res<QueuedPreAction>,
+    bar_query: Query<(Entity, Ref<PreActionBar>)>,
+    mut shown: Local<Vec<(PreAction, bool)>>,
+) {
+    let offered: Vec<(PreAction, bool)> = offered_pre_actions(&lobby, game_assets.client_id).into_iter()
+        .map(|pre_action| (pre_action, queued.action == Some(pre_action)))
+        .collect();
+    let is_new = bar_query.iter().any(|(_, bar)| bar.is_added());
+    if *shown == offered && !is_new {
+        return;
+    }
+    for (bar, _) in bar_query.iter() {
+        commands.entity(bar).despawn_descendants().with_children(|parent| {
+            for (index, (pre_action, is_queued)) in offered.iter().rev().enumerate() {
+                // Ticked boxes stand out so it's clear what will happen on our turn
+                let color = if *is_queued { Color::srgb(0.2, 0.45, 0.6) } else { Color::srgb(0.3, 0.3, 0.3) };
+                spawn_button(
+                    parent,
+                    &pre_action.label(),
+                    game_assets.font.clone(),
+                    ButtonPosition {
+                        right: Val::Px(index as f32 * 220.0),
+                        ..Default::default()
+                    },
+                    ButtonAssets {
+                        normal: color,
+                        hovered: color.lighter(0.1),
+                        pressed: color.darker(0.1),
+                        // pre_action_listener ticks it
+                        on_click: ButtonAction::Other(Arc::new(|| {})),
+                    },
+                ).insert(PreActionButton(*pre_action));
+            }
+        });
+    }
+    *shown = offered;
+}
+
+fn cleanup_pre_actions(
+    mut commands: Commands,
+    query: Query<Entity, With<PreActionBar>>,
+    mut queued: ResMut<QueuedPreAction>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    *queued = QueuedPreAction::default();
+}
@@ -97,7 +97,8 @@ fn change_game_state(
 ) {
     match server_mode.get() {
         ServerMode::Host => {
-            game_state.set(GameState::Lobby);
+            // The host joins its own server like any other player
+            game_state.set(GameState::Connecting);
         }
         ServerMode::Join => {
             game_state.set(GameState::JoinServer);
@@ -2,10 +2,9 @@ use bevy::prelude::*;
 use crate::GameState;
 use crate::GameAssets;
 use crate::ButtonAction;
-use bevy::window::{WindowResolution, WindowMode};
+use crate::utils::{Settings, WindowModeSetting};
 use crate::button_manager::{spawn_button, ButtonAssets};
 use std::sync::Arc;
-use bevy_framepace::{Limiter, FramepaceSettings};
 use crate::ButtonPosition;
 
 pub struct SettingsPlugin;
@@ -93,14 +92,10 @@ fn setup_settings(
             top,
             vec!["3840x2160", "2560x1440", "1920x1080", "1600x900"],
             |resolution: &str| {
-                let resolution = resolution.to_string();    
-                ButtonAction::ChangeWindow(Arc::new(move |windows: &mut Query<&mut Window>| {
-                    let res = resolution.split_once('x').unwrap();
-                    let width = res.0.parse::<f32>().unwrap();
-                    let height = res.1.parse::<f32>().unwrap();
-                    if let Ok(mut window) = windows.get_single_mut() {
-                        window.resolution = WindowResolution::new(width, height);
-                    }
+                let (width, height) = resolution.split_once('x').unwrap();
+                let resolution = (width.parse::<u32>().unwrap(), height.parse::<u32>().unwrap());
+                ButtonAction::ChangeSettings(Arc::new(move |settings: &mut Settings| {
+                    settings.video.resolution = resolution;
                 }))
             }
         );
@@ -112,9 +107,9 @@ fn setup_settings(
             top,
             vec!["60", "120", "240"],
             move|fps: &str| {
-                let fps = Arc::new(fps.to_string());
-                ButtonAction::ChangeFPS(Arc::new(move |framespace_settings: &mut ResMut<FramepaceSettings>| {
-                    framespace_settings.limiter = Limiter::from_framerate(fps.parse::<f64>().unwrap());
+                let fps = fps.parse::<u32>().unwrap();
+                ButtonAction::ChangeSettings(Arc::new(move |settings: &mut Settings| {
+                    settings.video.fps = fps;
                 }))
             }
         );
@@ -126,22 +121,78 @@ fn setup_settings(
             top,
             vec!["Windowed", "Borderless", "Fullscreen"],
             move|window_mode: &str| {
-                let window_mode = Arc::new(window_mode.to_string());
-                ButtonAction::ChangeWindow(Arc::new(move |windows: &mut Query<&mut Window>| {
-                    let window_mode = match window_mode.as_str() {
-                        "Windowed" => WindowMode::Windowed,
-                        "Borderless" => WindowMode::SizedFullscreen(MonitorSelection::Primary),
-                        "Fullscreen" => WindowMode::Fullscreen(MonitorSelection::Primary),
-                        _ => WindowMode::Windowed,
-                    };
-                    if let Ok(mut window) = windows.get_single_mut() {
-                        window.mode = window_mode;
-                    }
+                let window_mode = match window_mode {
+                    "Borderless" => WindowModeSetting::Borderless,
+                    "Fullscreen" => WindowModeSetting::Fullscreen,
+                    _ => WindowModeSetting::Windowed,
+                };
+                ButtonAction::ChangeSettings(Arc::new(move |settings: &mut Settings| {
+                    settings.video.window_mode = window_mode;
                 }))
             }
         );
+    });
 
-
+    // Second column, for sound and how the table behaves
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(25.),
+            top: Val::Percent(5.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(10.0),
+            width: Val::Px(300.0),
+            padding: UiRect::all(Val::Px(10.0)),
+            ..Default::default()
+        },
+        SettingsContainer,
+    )).with_children(|parent|{
+        let mut top = SETTINGS_TITLE_TOP + BUTTON_HEIGHT + 20.0;
+        setup_setting_section(
+            parent,
+            game_assets.font.clone(),
+            "Volume",
+            top,
+            vec!["Muted", "50%", "100%"],
+            move|volume: &str| {
+                let volume = match volume {
+                    "Muted" => 0.0,
+                    "50%" => 0.5,
+                    _ => 1.0,
+                };
+                ButtonAction::ChangeSettings(Arc::new(move |settings: &mut Settings| {
+                    settings.audio.volume = volume;
+                }))
+            }
+        );
+        top += (BUTTON_HEIGHT + 20.0) * 3.0 + 50.0;
+        setup_setting_section(
+            parent,
+            game_assets.font.clone(),
+            "Table Animations",
+            top,
+            vec!["On", "Off"],
+            move|choice: &str| {
+                let is_on = choice == "On";
+                ButtonAction::ChangeSettings(Arc::new(move |settings: &mut Settings| {
+                    settings.gameplay.table_animations = is_on;
+                }))
+            }
+        );
+        top += (BUTTON_HEIGHT + 20.0) * 2.0 + 50.0;
+        setup_setting_section(
+            parent,
+            game_assets.font.clone(),
+            "Keyboard Shortcuts",
+            top,
+            vec!["On", "Off"],
+            move|choice: &str| {
+                let is_on = choice == "On";
+                ButtonAction::ChangeSettings(Arc::new(move |settings: &mut Settings| {
+                    settings.gameplay.keyboard_shortcuts = is_on;
+                }))
+            }
+        );
     });
 }
 
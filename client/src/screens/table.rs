@@ -0,0 +1,421 @@
+use bevy::prelude::*;
+use crate::{GameState, ServerMode};
+use crate::GameAssets;
+use crate::{ButtonAction, ButtonAssets, ButtonPosition, spawn_button};
+use crate::utils::{Action, BytesCard, Lobby, Stage, TableConnections, TurnClock};
+use super::bet_panel::BetSize;
+use crate::animations::{TableAnimationSystems, TableAnimations};
+use std::f32::consts::{PI, TAU};
+use std::sync::Arc;
+
+pub struct TablePlugin;
+
+impl Plugin for TablePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), setup_table)
+            .add_systems(OnExit(GameState::InGame), cleanup_table)
+            .add_systems(Update, (update_seats, update_pot, update_turn_text, update_table_cards.after(TableAnimationSystems), update_action_bar, action_button_listener).run_if(in_state(GameState::InGame)));
+    }
+}
+
+// The felt, in world units, the camera sits 10 units in front of it
+const TABLE_RADIUS_X: f32 = 6.2;
+const TABLE_RADIUS_Y: f32 = 3.0;
+pub(crate) const CARD_SCALE: f32 = 16.0;
+const CARD_SPACING: f32 = 1.1;
+pub(crate) const BOARD_HEIGHT: f32 = 0.3;
+pub(crate) const HOLE_CARD_HEIGHT: f32 = -1.9;
+// Where the seats sit around the screen, in percent from the center
+const SEAT_RADIUS_X: f32 = 42.0;
+const SEAT_RADIUS_Y: f32 = 38.0;
+const SEAT_WIDTH: f32 = 220.0;
+
+#[derive(Component)]
+struct TableScene;
+
+#[derive(Component)]
+struct TableUi;
+
+#[derive(Component)]
+struct SeatList;
+
+#[derive(Component)]
+struct PotText;
+
+#[derive(Component)]
+struct TurnText;
+
+#[derive(Component)]
+struct ActionBar;
+
+// A card on the felt, respawned whenever the board or our hand changes
+#[derive(Component)]
+struct TableCard;
+
+// Plays its action when clicked, the action bar is rebuilt whenever what we can do changes
+#[derive(Component)]
+struct ActionButton(Action);
+
+fn setup_table(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    game_assets: Res<GameAssets>,
+) {
+    commands.spawn((
+        Mesh3d(meshes.add(Ellipse::new(TABLE_RADIUS_X, TABLE_RADIUS_Y))),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::srgb(0.05, 0.35, 0.15),
+            unlit: true,
+            ..Default::default()
+        })),
+        Transform::from_xyz(0.0, 0.0, -0.5),
+        TableScene,
+    ));
+
+    commands.spawn((Node {
+        position_type: PositionType::Absolute,
+        width: Val::Percent(100.0),
+        height: Val::Percent(100.0),
+        ..Default::default()
+    }, TableUi))
+    .with_children(|parent| {
+        // Seats around the table, filled in by update_seats
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..Default::default()
+            },
+            SeatList,
+        ));
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Percent(30.0),
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            Text::new(""),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 28.0,
+                ..Default::default()
+            },
+            TextColor(Color::WHITE),
+            TextLayout::new_with_justify(JustifyText::Center),
+            PotText,
+        ));
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Percent(62.0),
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            Text::new(""),
+            TextFont {
+                font: game_assets.font.clone(),
+                font_size: 22.0,
+                ..Default::default()
+            },
+            TextColor(Color::srgb(1.0, 0.85, 0.3)),
+            TextLayout::new_with_justify(JustifyText::Center),
+            TurnText,
+        ));
+        // What we can do on our turn, filled in by update_action_bar
+        parent.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(20.0),
+                right: Val::Px(20.0),
+                width: Val::Px(880.0),
+                height: Val::Px(65.0),
+                ..Default::default()
+            },
+            ActionBar,
+        ));
+        spawn_button(
+            parent,
+            "Leave",
+            game_assets.font.clone(),
+            ButtonPosition {
+                top: Val::Px(20.0),
+                right: Val::Px(20.0),
+                ..Default::default()
+            },
+            ButtonAssets {
+                normal: Color::srgb(0.5, 0.5, 0.5),
+                hovered: Color::srgb(0.5, 0.5, 0.5),
+                pressed: Color::srgb(0.3, 0.3, 0.3),
+                on_click: ButtonAction::ChangeServerMode(Arc::new(|server_mode| {
+                    server_mode.set(ServerMode::None);
+                })),
+            },
+        );
+    });
+}
+
+// Where a seat goes on screen, in percent, turned so our own seat is at the bottom
+fn seat_position(index: usize, count: usize, own_index: usize) -> (f32, f32) {
+    let angle = PI / 2.0 + TAU * (index + count - own_index) as f32 / count as f32;
+    (50.0 + SEAT_RADIUS_X * angle.cos(), 48.0 + SEAT_RADIUS_Y * angle.sin())
+}
+
+// The same seat on the felt, just inside its edge, for anything that flies to or from it
+pub(crate) fn seat_world_position(index: usize, count: usize, own_index: usize) -> Vec3 {
+    let angle = PI / 2.0 + TAU * (index + count - own_index) as f32 / count as f32;
+    // Screen y points down, world y points up
+    Vec3::new(TABLE_RADIUS_X * 0.8 * angle.cos(), -TABLE_RADIUS_Y * 0.8 * angle.sin(), 0.0)
+}
+
+// Where a card sits in a row of `count` cards centered on the table
+pub(crate) fn card_position(index: usize, count: usize, height: f32) -> Vec3 {
+    let offset = (count as f32 - 1.0) * CARD_SPACING / 2.0;
+    Vec3::new(index as f32 * CARD_SPACING - offset, height, 0.0)
+}
+
+fn update_seats(
+    mut commands: Commands,
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    table_connections: Res<TableConnections>,
+    list_query: Query<(Entity, Ref<SeatList>)>,
+    mut shown: Local<Vec<(String, bool)>>,
+) {
+    let own_index = lobby.players.iter().position(|player| player.client_id == game_assets.client_id).unwrap_or(0);
+    let seats: Vec<(String, bool)> = lobby.players.iter().enumerate().map(|(index, player)| {
+        let mut lines = vec![player.name.clone(), format!("{} chips", player.money)];
+        if lobby.button as usize == index && lobby.stage != Stage::Waiting {
+            lines[0].push_str("  (D)");
+        }
+        if player.bet_this_turn > 0 && lobby.is_betting() {
+            lines.push(format!("Bet {}", player.bet_this_turn));
+        }
+        let status = if player.is_disconnected {
+            Some("Away".to_string())
+        } else if player.is_folded && lobby.stage != Stage::Waiting {
+            Some("Folded".to_string())
+        } else if player.is_all_in {
+            Some("All in".to_string())
+        } else {
+            None
+        };
+        lines.extend(status);
+        // Away already says everything about a dropped connection
+        if let Some(quality) = table_connections.get(player.client_id).filter(|_| !player.is_disconnected) {
+            lines.push(format!("{} ms ({})", quality.ping_ms, quality.grade()));
+        }
+        // Opponents' cards only ever arrive at showdown
+        if player.client_id != game_assets.client_id && !player.hand.is_empty() {
+            let cards: Vec<String> = player.hand.iter().map(BytesCard::to_string).collect();
+            lines.push(cards.join(" "));
+        }
+        let is_turn = lobby.is_betting() && lobby.turn as usize == index;
+        (lines.join("\n"), is_turn)
+    }).collect();
+    let is_new = list_query.iter().any(|(_, list)| list.is_added());
+    if *shown == seats && !is_new {
+        return;
+    }
+    for (list, _) in list_query.iter() {
+        commands.entity(list).despawn_descendants().with_children(|parent| {
+            for (index, (label, is_turn)) in seats.iter().enumerate() {
+                let (left, top) = seat_position(index, seats.len(), own_index);
+                parent.spawn((
+                    Node {
+                        position_type: PositionType::Absolute,
+                        left: Val::Percent(left),
+                        top: Val::Percent(top),
+                        width: Val::Px(SEAT_WIDTH),
+                        margin: UiRect {
+                            left: Val::Px(-SEAT_WIDTH / 2.0),
+                            top: Val::Px(-40.0),
+                            ..Default::default()
+                        },
+                        padding: UiRect::all(Val::Px(8.0)),
+                        border: UiRect::all(Val::Px(2.0)),
+                        justify_content: JustifyContent::Center,
+                        ..Default::default()
+                    },
+                    BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.7)),
+                    BorderColor(if *is_turn { Color::srgb(1.0, 0.85, 0.3) } else { Color::srgb(0.3, 0.3, 0.3) }),
+                    BorderRadius::all(Val::Px(10.0)),
+                )).with_child((
+                    Text::new(label.clone()),
+                    TextFont {
+                        font: game_assets.font.clone(),
+                        font_size: 18.0,
+                        ..Default::default()
+                    },
+                    TextColor(Color::WHITE),
+                    TextLayout::new_with_justify(JustifyText::Center),
+                ));
+            }
+        });
+    }
+    *shown = seats;
+}
+
+fn update_pot(
+    lobby: Res<Lobby>,
+    mut text_query: Query<&mut Text, With<PotText>>,
+) {
+    let mut label = if lobby.pot > 0 { format!("Pot {}", lobby.pot) } else { String::new() };
+    // The card models don't tell every card apart yet, so the board is spelled out as well
+    if !lobby.board.is_empty() {
+        let cards: Vec<String> = lobby.board.iter().map(BytesCard::to_string).collect();
+        label.push_str(&format!("\n{}", cards.join("  ")));
+    }
+    for mut text in text_query.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+fn update_turn_text(
+    lobby: Res<Lobby>,
+    turn_clock: Res<TurnClock>,
+    game_assets: Res<GameAssets>,
+    mut text_query: Query<&mut Text, With<TurnText>>,
+) {
+    let label = match lobby.players.get(lobby.turn as usize).filter(|_| lobby.is_betting()) {
+        Some(player) => {
+            let name = if player.client_id == game_assets.client_id { "Your turn".to_string() } else { format!("{} to act", player.name) };
+            let bank = if turn_clock.using_time_bank { " (time bank)" } else { "" };
+            format!("{name}  {:.0}s{bank}", turn_clock.remaining.ceil())
+        }
+        None if lobby.stage == Stage::Showdown => "Next hand soon".to_string(),
+        None => String::new(),
+    };
+    for mut text in text_query.iter_mut() {
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}
+
+// Keeps the board and our hole cards on the felt in step with the lobby
+fn update_table_cards(
+    mut commands: Commands,
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    card_query: Query<Entity, With<TableCard>>,
+    table_query: Query<Ref<TableScene>>,
+    animations: Res<TableAnimations>,
+    mut shown: Local<(Vec<BytesCard>, Vec<BytesCard>)>,
+) {
+    // Cards still flying in, they get drawn here once they land
+    if animations.is_busy() {
+        return;
+    }
+    let hand = lobby.players.iter()
+        .find(|player| player.client_id == game_assets.client_id)
+        .map(|player| player.hand.clone())
+        .unwrap_or_default();
+    let is_new = table_query.iter().any(|table| table.is_added());
+    if shown.0 == lobby.board && shown.1 == hand && !is_new {
+        return;
+    }
+    for card in card_query.iter() {
+        commands.entity(card).despawn_recursive();
+    }
+    let rows = [(&lobby.board, BOARD_HEIGHT), (&hand, HOLE_CARD_HEIGHT)];
+    for (cards, height) in rows {
+        for (index, card) in cards.iter().enumerate() {
+            commands.spawn((
+                Transform {
+                    translation: card_position(index, cards.len(), height),
+                    scale: Vec3::splat(CARD_SCALE),
+                    ..Default::default()
+                },
+                SceneRoot(game_assets.deck.find_model_from_bytes_card(card.clone())),
+                TableCard,
+            ));
+        }
+    }
+    *shown = (lobby.board.clone(), hand);
+}
+
+fn update_action_bar(
+    mut commands: Commands,
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    bet: Res<BetSize>,
+    bar_query: Query<(Entity, Ref<ActionBar>)>,
+    mut shown: Local<Vec<String>>,
+) {
+    let player = lobby.players.iter().find(|player| player.client_id == game_assets.client_id);
+    let actions: Vec<(Action, String)> = lobby.legal_actions(game_assets.client_id).into_iter().map(|action| {
+        match action {
+            Action::Fold => (action, "Fold".to_string()),
+            Action::Check => (action, "Check".to_string()),
+            Action::Call => (action, format!("Call {}", lobby.to_call(game_assets.client_id))),
+            // The bet panel picks how much
+            Action::Raise(_) if lobby.current_bet == 0 => (bet.action(), format!("Bet {}", bet.raise_to)),
+            Action::Raise(_) => (bet.action(), format!("Raise to {}", bet.raise_to)),
+            Action::AllIn => (action, format!("All in {}", player.map_or(0, |player| player.money))),
+        }
+    }).collect();
+    let labels: Vec<String> = actions.iter().map(|(_, label)| label.clone()).collect();
+    let is_new = bar_query.iter().any(|(_, bar)| bar.is_added());
+    if *shown == labels && !is_new {
+        return;
+    }
+    for (bar, _) in bar_query.iter() {
+        commands.entity(bar).despawn_descendants().with_children(|parent| {
+            // Fold on the left, all in on the right
+            for (index, (action, label)) in actions.iter().rev().enumerate() {
+                let color = match action {
+                    Action::Fold => Color::srgb(0.6, 0.2, 0.2),
+                    Action::AllIn => Color::srgb(0.6, 0.4, 0.1),
+                    _ => Color::srgb(0.2, 0.45, 0.25),
+                };
+                spawn_button(
+                    parent,
+                    label,
+                    game_assets.font.clone(),
+                    ButtonPosition {
+                        right: Val::Px(index as f32 * 220.0),
+                        ..Default::default()
+                    },
+                    ButtonAssets {
+                        normal: color,
+                        hovered: color.lighter(0.1),
+                        pressed: color.darker(0.1),
+                        // action_button_listener plays it
+                        on_click: ButtonAction::Other(Arc::new(|| {})),
+                    },
+                ).insert(ActionButton(*action));
+            }
+        });
+    }
+    *shown = labels;
+}
+
+// Actions go out through client::send_message_system, which plays them locally and tells the server
+fn action_button_listener(
+    mut actions: EventWriter<Action>,
+    button_query: Query<(&Interaction, &ActionButton), Changed<Interaction>>,
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction == Interaction::Pressed {
+            actions.send(button.0);
+        }
+    }
+}
+
+fn cleanup_table(
+    mut commands: Commands,
+    ui_query: Query<Entity, With<TableUi>>,
+    scene_query: Query<Entity, With<TableScene>>,
+    card_query: Query<Entity, With<TableCard>>,
+) {
+    for entity in ui_query.iter().chain(scene_query.iter()).chain(card_query.iter()) {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -8,6 +8,7 @@ pub enum GameState {
     Settings,// Settings menu
     ServerSelect,// The menu where you pick if you want to host or join a server
     JoinServer,// Join server menu
+    Connecting,// Waiting on the server after picking one to join
     Lobby,// Lobby menu (if you are hosting a server, it automatically goes to the lobby)
     InGame,// In game
 }
@@ -0,0 +1,97 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::thread::JoinHandle;
+
+// Turns whatever was typed into the address box into something we can connect to.
+// Host names are looked up on their own thread, DNS can take seconds and the window has to keep drawing meanwhile.
+
+// Used when an address is typed without a port, it's the port hosts listen on
+pub const DEFAULT_PORT: u16 = 2163;
+
+// What a typed address points at, before any host name in it is looked up
+enum Target {
+    Address(SocketAddr),
+    Host(String, u16),
+}
+
+// Takes "host", "host:port", "ip", "ip:port" and "[ipv6]:port"
+fn parse_server_address(text: &str) -> Result<Target, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("Enter the address of the server to join".to_string());
+    }
+    if let Ok(address) = text.parse::<SocketAddr>() {
+        return Ok(Target::Address(address));
+    }
+    // A bare ip, v6 ones may come with brackets
+    if let Ok(ip) = text.trim_start_matches('[').trim_end_matches(']').parse() {
+        return Ok(Target::Address(SocketAddr::new(ip, DEFAULT_PORT)));
+    }
+    let (host, port) = match text.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) if port != 0 => (host, port),
+            _ => return Err(format!("\"{port}\" isn't a valid port, it should be a number between 1 and 65535")),
+        },
+        None => (text, DEFAULT_PORT),
+    };
+    if host.is_empty() || host.contains(char::is_whitespace) {
+        return Err(format!("\"{text}\" isn't a valid server address"));
+    }
+    Ok(Target::Host(host.to_string(), port))
+}
+
+fn look_up(host: &str, port: u16) -> Result<SocketAddr, String> {
+    let addresses: Vec<SocketAddr> = (host, port).to_socket_addrs()
+        .map_err(|_| format!("Couldn't find a server called \"{host}\""))?
+        .collect();
+    // Most hosts are only reachable over v4, so prefer it when the name has both
+    addresses.iter().find(|address| address.is_ipv4()).or(addresses.first()).copied()
+        .ok_or_else(|| format!("Couldn't find a server called \"{host}\""))
+}
+
+// Says what's wrong with an address without looking it up, so host names can be saved as typed
+pub fn check_server_address(text: &str) -> Result<(), String> {
+    parse_server_address(text).map(|_| ())
+}
+
+// Blocks on DNS for host names, so keep it off the main thread
+pub fn resolve_server_address(text: &str) -> Result<SocketAddr, String> {
+    match parse_server_address(text)? {
+        Target::Address(address) => Ok(address),
+        Target::Host(host, port) => look_up(&host, port),
+    }
+}
+
+// A typed address being resolved in the background, polled every frame until it's done
+pub struct AddressLookup {
+    result: Option<Result<SocketAddr, String>>,
+    thread: Option<JoinHandle<Result<SocketAddr, String>>>,
+}
+
+impl AddressLookup {
+    // Plain ips and typos are answered straight away, only host names get a thread
+    pub fn start(text: &str) -> Self {
+        let result = match parse_server_address(text) {
+            Ok(Target::Host(host, port)) => {
+                return AddressLookup {
+                    result: None,
+                    thread: Some(std::thread::spawn(move || look_up(&host, port))),
+                };
+            }
+            Ok(Target::Address(address)) => Ok(address),
+            Err(error) => Err(error),
+        };
+        AddressLookup {
+            result: Some(result),
+            thread: None,
+        }
+    }
+
+    // The address once it's known, or why it couldn't be found. Only returns it once.
+    pub fn poll(&mut self) -> Option<Result<SocketAddr, String>> {
+        if self.thread.as_ref().is_some_and(|thread| thread.is_finished()) {
+            let thread = self.thread.take().unwrap();
+            self.result = Some(thread.join().unwrap_or_else(|_| Err("Looking up the server address failed".to_string())));
+        }
+        self.result.take()
+    }
+}
@@ -0,0 +1,414 @@
+use bevy::prelude::*;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::Aead;
+use rand::{thread_rng, Rng};
+use renet_netcode::{ConnectToken, NETCODE_KEY_BYTES, NETCODE_USER_DATA_BYTES};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+use crate::utils::client::PROTOCOL_ID;
+
+// Small login service that hands out netcode connect tokens.
+// It listens on TCP on the same port number the game uses for UDP, and only runs when the host has an auth config.
+// Passwords never leave the player's machine. The host keeps a salted hash of each one, the client proves it knows
+// the password by hashing the host's one-off challenge with it, and the token comes back encrypted with a key only
+// someone who knows the password can work out. Someone listening in can still try guessing a weak password offline.
+
+// If this file exists next to the game, hosting runs in secure mode. Accounts are added with `client --add-account NAME`.
+pub const AUTH_CONFIG_PATH: &str = "auth.json";
+const TOKEN_EXPIRE_SECONDS: u64 = 300;
+const TOKEN_TIMEOUT_SECONDS: i32 = 15;
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(2);
+// Longest line either side reads, a sealed connect token is the biggest thing sent
+const MAX_LOGIN_LINE: u64 = 8 * 1024;
+// Logins being handled at once, anyone past that is hung up on straight away
+const MAX_CONCURRENT_LOGINS: usize = 16;
+// How often the login service checks whether it should stop while nobody is logging in
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+// Rounds of hashing between a password and what the host stores, so guessing passwords is slow
+const PASSWORD_HASH_ROUNDS: u32 = 100_000;
+
+// What the host keeps instead of the password
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub salt: String,
+    pub password_hash: String,
+}
+
+// Private key as 64 hex characters and a map of username to account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    pub private_key: String,
+    pub accounts: HashMap<String, Account>,
+}
+
+impl AuthConfig {
+    pub fn load() -> Option<Self> {
+        let contents = std::fs::read_to_string(AUTH_CONFIG_PATH).ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(config) => Some(config),
+            Err(error) => {
+                println!("Could not read {AUTH_CONFIG_PATH}: {error}");
+                None
+            }
+        }
+    }
+
+    pub fn private_key(&self) -> Option<[u8; NETCODE_KEY_BYTES]> {
+        from_hex(&self.private_key)?.try_into().ok()
+    }
+
+    fn account_hash(&self, username: &str) -> Option<(Vec<u8>, [u8; 32])> {
+        let account = self.accounts.get(username)?;
+        Some((from_hex(&account.salt)?, from_hex(&account.password_hash)?.try_into().ok()?))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Both sides work this out from the password, the host only ever has the result
+fn password_hash(salt: &[u8], password: &str) -> [u8; 32] {
+    let mut hash = *blake3::Hasher::new_derive_key("poker login password").update(salt).update(password.as_bytes()).finalize().as_bytes();
+    for _ in 0..PASSWORD_HASH_ROUNDS {
+        hash = *blake3::hash(&hash).as_bytes();
+    }
+    hash
+}
+
+// What the client sends back to show it knows the password, different for every challenge
+fn login_proof(password_hash: &[u8; 32], challenge: &[u8]) -> blake3::Hash {
+    blake3::keyed_hash(password_hash, &[b"proof".as_slice(), challenge].concat())
+}
+
+// The token is sealed with a key only this login can work out
+fn token_cipher(password_hash: &[u8; 32], challenge: &[u8]) -> ChaCha20Poly1305 {
+    let key = blake3::keyed_hash(password_hash, &[b"token".as_slice(), challenge].concat());
+    ChaCha20Poly1305::new(Key::from_slice(key.as_bytes()))
+}
+
+// Each key seals a single token, so the nonce never needs to change
+const TOKEN_NONCE: [u8; 12] = [0; 12];
+
+// Kept by a secure host so its own client can get in without going through the login service
+#[derive(Resource)]
+pub struct HostLogin {
+    pub private_key: [u8; NETCODE_KEY_BYTES],
+}
+
+impl HostLogin {
+    pub fn connect_token(&self, server_address: SocketAddr, client_id: u64, name: &str) -> Result<ConnectToken, String> {
+        let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        ConnectToken::generate(
+            current_time,
+            PROTOCOL_ID,
+            TOKEN_EXPIRE_SECONDS,
+            client_id,
+            TOKEN_TIMEOUT_SECONDS,
+            vec![server_address],
+            Some(&username_to_user_data(name)),
+            &self.private_key,
+        ).map_err(|error| format!("{:?}", error))
+    }
+}
+
+// One line of JSON each way: hello, challenge, proof, then the sealed token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum LoginMessage {
+    Hello { username: String },
+    Challenge { salt: String, challenge: String },
+    Proof(String),
+    Token(String),
+}
+
+fn send_login_message(stream: &mut TcpStream, message: &LoginMessage) -> Result<(), String> {
+    let mut line = serde_json::to_string(message).map_err(|error| error.to_string())?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).map_err(|error| error.to_string())
+}
+
+// Reads one line, never more than MAX_LOGIN_LINE of it
+fn read_login_message(reader: &mut BufReader<TcpStream>) -> Result<LoginMessage, String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LOGIN_LINE).read_line(&mut line).map_err(|error| error.to_string())?;
+    if !line.ends_with('\n') {
+        return Err("the login message was cut off or too long".to_string());
+    }
+    serde_json::from_str(&line).map_err(|error| error.to_string())
+}
+
+// One account always maps to the same client_id, so nobody can pick someone else's.
+// It has to come out the same on every build and machine, so it's a real hash of the name.
+pub fn account_client_id(username: &str) -> u64 {
+    let hash = blake3::derive_key("poker account client id", username.as_bytes());
+    u64::from_le_bytes(hash[..8].try_into().unwrap())
+}
+
+// The account name travels inside the connect token so the server knows who connected
+pub fn username_to_user_data(username: &str) -> [u8; NETCODE_USER_DATA_BYTES] {
+    let mut user_data = [0; NETCODE_USER_DATA_BYTES];
+    let bytes = username.as_bytes();
+    let len = bytes.len().min(NETCODE_USER_DATA_BYTES);
+    user_data[..len].copy_from_slice(&bytes[..len]);
+    user_data
+}
+
+pub fn user_data_to_username(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> String {
+    let len = user_data.iter().position(|byte| *byte == 0).unwrap_or(NETCODE_USER_DATA_BYTES);
+    String::from_utf8_lossy(&user_data[..len]).to_string()
+}
+
+// The login service, it runs while we host and stops when the resource goes
+#[derive(Resource)]
+pub struct AuthServer {
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AuthServer {
+    // Every login gets its own thread, so one slow client can't hold up the rest
+    pub fn start(config: AuthConfig, private_key: [u8; NETCODE_KEY_BYTES], server_address: SocketAddr) -> Option<Self> {
+        let listener = match TcpListener::bind(server_address).and_then(|listener| listener.set_nonblocking(true).map(|_| listener)) {
+            Ok(listener) => listener,
+            Err(error) => {
+                println!("Could not start the login service at {server_address}: {error}");
+                return None;
+            }
+        };
+        println!("Login service listening at {server_address}");
+        let config = Arc::new(config);
+        let logins = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = stopped.clone();
+        let thread = std::thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    // Nobody knocking, look again after checking whether we should stop
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                        std::thread::sleep(ACCEPT_INTERVAL);
+                        continue;
+                    }
+                    Err(_) => continue,
+                };
+                if logins.fetch_add(1, Ordering::SeqCst) >= MAX_CONCURRENT_LOGINS {
+                    logins.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+                let (config, logins, stop) = (config.clone(), logins.clone(), stop.clone());
+                std::thread::spawn(move || {
+                    if let Err(error) = handle_login(stream, &config, &private_key, server_address, &stop) {
+                        println!("Login failed: {error}");
+                    }
+                    logins.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        Some(AuthServer { stopped, thread: Some(thread) })
+    }
+
+    // Closes the port and waits for it to be free, logins halfway through are refused a token
+    pub fn stop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+            println!("Login service stopped");
+        }
+    }
+}
+
+impl Drop for AuthServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn handle_login(
+    mut stream: TcpStream,
+    config: &AuthConfig,
+    private_key: &[u8; NETCODE_KEY_BYTES],
+    server_address: SocketAddr,
+    stopped: &AtomicBool,
+) -> Result<(), String> {
+    // Accepted from a nonblocking listener, which some platforms pass on
+    stream.set_nonblocking(false).map_err(|error| error.to_string())?;
+    stream.set_read_timeout(Some(LOGIN_TIMEOUT)).map_err(|error| error.to_string())?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(|error| error.to_string())?);
+    let LoginMessage::Hello { username } = read_login_message(&mut reader)? else {
+        return Err("expected a hello".to_string());
+    };
+    // Unknown names get a made up salt that stays the same, so they look just like a wrong password
+    let (salt, expected_hash) = config.account_hash(&username).unwrap_or_else(|| {
+        let salt = blake3::keyed_hash(private_key, username.as_bytes()).as_bytes()[..16].to_vec();
+        (salt, thread_rng().gen())
+    });
+    let challenge: [u8; 32] = thread_rng().gen();
+    send_login_message(&mut stream, &LoginMessage::Challenge { salt: to_hex(&salt), challenge: to_hex(&challenge) })?;
+    let LoginMessage::Proof(proof) = read_login_message(&mut reader)? else {
+        return Err("expected a proof".to_string());
+    };
+    // blake3 hashes compare in constant time
+    let proof = from_hex(&proof).and_then(|proof| <[u8; 32]>::try_from(proof).ok()).map(blake3::Hash::from_bytes);
+    if proof != Some(login_proof(&expected_hash, &challenge)) {
+        // Closing without a token is the rejection
+        return Err(format!("wrong username or password for {username}"));
+    }
+    // The host stopped hosting while this login was going on, its key is no good anymore
+    if stopped.load(Ordering::SeqCst) {
+        return Err("the login service was stopped".to_string());
+    }
+
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    let token = ConnectToken::generate(
+        current_time,
+        PROTOCOL_ID,
+        TOKEN_EXPIRE_SECONDS,
+        account_client_id(&username),
+        TOKEN_TIMEOUT_SECONDS,
+        vec![server_address],
+        Some(&username_to_user_data(&username)),
+        private_key,
+    ).map_err(|error| format!("{:?}", error))?;
+    let mut token_bytes = Vec::new();
+    token.write(&mut token_bytes).map_err(|error| error.to_string())?;
+    let sealed = token_cipher(&expected_hash, &challenge).encrypt(Nonce::from_slice(&TOKEN_NONCE), token_bytes.as_slice())
+        .map_err(|error| error.to_string())?;
+    send_login_message(&mut stream, &LoginMessage::Token(to_hex(&sealed)))?;
+    println!("Issued a connect token to {username}");
+    Ok(())
+}
+
+// Asks the host's login service for a connect token. Blocks for up to a few seconds, so it runs off the main thread.
+pub fn request_connect_token(server_address: SocketAddr, username: &str, password: &str) -> Result<ConnectToken, String> {
+    let unreachable = |error: std::io::Error| format!("Couldn't reach the login service at {server_address}: {error}");
+    let mut stream = TcpStream::connect_timeout(&server_address, LOGIN_TIMEOUT).map_err(unreachable)?;
+    stream.set_read_timeout(Some(LOGIN_TIMEOUT)).map_err(unreachable)?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(unreachable)?);
+    send_login_message(&mut stream, &LoginMessage::Hello { username: username.to_string() })?;
+    let Ok(LoginMessage::Challenge { salt, challenge }) = read_login_message(&mut reader) else {
+        return Err("The host's login service didn't answer properly".to_string());
+    };
+    let (Some(salt), Some(challenge)) = (from_hex(&salt), from_hex(&challenge)) else {
+        return Err("The host's login service didn't answer properly".to_string());
+    };
+    let hash = password_hash(&salt, password);
+    send_login_message(&mut stream, &LoginMessage::Proof(to_hex(login_proof(&hash, &challenge).as_bytes())))?;
+    let Ok(LoginMessage::Token(sealed)) = read_login_message(&mut reader) else {
+        return Err(format!("The host didn't accept the password for {username}"));
+    };
+    let token_bytes = from_hex(&sealed)
+        .and_then(|sealed| token_cipher(&hash, &challenge).decrypt(Nonce::from_slice(&TOKEN_NONCE), sealed.as_slice()).ok())
+        .ok_or_else(|| "The host sent a connect token we couldn't open".to_string())?;
+    ConnectToken::read(&mut token_bytes.as_slice()).map_err(|_| "The host sent a broken connect token".to_string())
+}
+
+// `client --add-account NAME` reads the password from stdin and saves its hash to auth.json,
+// making the file with a fresh private key the first time. Returns the process exit code.
+pub fn run_add_account(args: &[String]) -> i32 {
+    let Some(username) = args.iter().skip_while(|arg| *arg != "--add-account").nth(1).filter(|name| !name.starts_with("--")) else {
+        println!("Usage: client --add-account NAME, then type the password");
+        return 2;
+    };
+    let mut config = match std::fs::read_to_string(AUTH_CONFIG_PATH) {
+        Ok(contents) => match serde_json::from_str::<AuthConfig>(&contents) {
+            Ok(config) => config,
+            Err(error) => {
+                println!("Could not read {AUTH_CONFIG_PATH}: {error}");
+                return 1;
+            }
+        },
+        Err(_) => AuthConfig {
+            private_key: to_hex(&thread_rng().gen::<[u8; NETCODE_KEY_BYTES]>()),
+            accounts: HashMap::new(),
+        },
+    };
+    println!("Password for {username}:");
+    let mut password = String::new();
+    if std::io::stdin().read_line(&mut password).is_err() || password.trim_end_matches(['\r', '\n']).is_empty() {
+        println!("No password given, nothing saved");
+        return 1;
+    }
+    let salt: [u8; 16] = thread_rng().gen();
+    let hash = password_hash(&salt, password.trim_end_matches(['\r', '\n']));
+    config.accounts.insert(username.clone(), Account { salt: to_hex(&salt), password_hash: to_hex(&hash) });
+    let saved = serde_json::to_string_pretty(&config).map_err(|error| error.to_string())
+        .and_then(|contents| std::fs::write(AUTH_CONFIG_PATH, contents).map_err(|error| error.to_string()));
+    match saved {
+        Ok(()) => {
+            println!("Saved {username} to {AUTH_CONFIG_PATH}");
+            0
+        }
+        Err(error) => {
+            println!("Could not save {AUTH_CONFIG_PATH}: {error}");
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logins_go_through_with_the_right_password_only() {
+        let salt = [3; 16];
+        let private_key = [7; NETCODE_KEY_BYTES];
+        let config = AuthConfig {
+            private_key: to_hex(&private_key),
+            accounts: HashMap::from([("alice".to_string(), Account { salt: to_hex(&salt), password_hash: to_hex(&password_hash(&salt, "hunter2")) })]),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let results: Vec<bool> = listener.incoming().take(3)
+                .map(|stream| handle_login(stream.unwrap(), &config, &private_key, address, &AtomicBool::new(false)).is_ok())
+                .collect();
+            results
+        });
+
+        let token = request_connect_token(address, "alice", "hunter2").unwrap();
+        assert_eq!(token.client_id, account_client_id("alice"));
+        assert!(request_connect_token(address, "alice", "hunter3").is_err());
+        assert!(request_connect_token(address, "mallory", "hunter2").is_err());
+        assert_eq!(server.join().unwrap(), vec![true, false, false]);
+    }
+
+    #[test]
+    fn the_login_service_stops_with_the_server_and_can_start_again() {
+        let salt = [5; 16];
+        let config = || AuthConfig {
+            private_key: to_hex(&[9; NETCODE_KEY_BYTES]),
+            accounts: HashMap::from([("bob".to_string(), Account { salt: to_hex(&salt), password_hash: to_hex(&password_hash(&salt, "swordfish")) })]),
+        };
+        let address: SocketAddr = "127.0.0.1:41171".parse().unwrap();
+        let mut auth_server = AuthServer::start(config(), [9; NETCODE_KEY_BYTES], address).unwrap();
+        assert!(request_connect_token(address, "bob", "swordfish").is_ok());
+        auth_server.stop();
+        assert!(request_connect_token(address, "bob", "swordfish").is_err());
+        // Hosting again gets the port back
+        let _auth_server = AuthServer::start(config(), [9; NETCODE_KEY_BYTES], address).unwrap();
+        assert!(request_connect_token(address, "bob", "swordfish").is_ok());
+    }
+
+    #[test]
+    fn client_ids_are_the_same_on_every_build() {
+        // Changing this would hand every account a new id, and a new seat
+        assert_eq!(account_client_id("alice"), 4001944923846706927);
+        assert_ne!(account_client_id("alice"), account_client_id("bob"));
+    }
+}
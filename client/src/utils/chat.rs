@@ -0,0 +1,71 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+// Table chat. Clients send text, the server decides who said it and who gets to read it.
+
+// Longest chat message the server relays, in characters
+pub const MAX_CHAT_LENGTH: usize = 200;
+// Messages a client can send within CHAT_RATE_WINDOW before the rest are turned away
+pub const CHAT_RATE_LIMIT: usize = 5;
+pub const CHAT_RATE_WINDOW: Duration = Duration::from_secs(10);
+// Lines kept in the chat log
+const CHAT_HISTORY: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatLine {
+    // None for dealer and system messages, which can't be muted
+    pub sender: Option<u64>,
+    pub name: String,
+    pub text: String,
+}
+
+impl ChatLine {
+    pub fn dealer(text: impl Into<String>) -> Self {
+        ChatLine {
+            sender: None,
+            name: "Dealer".to_string(),
+            text: text.into(),
+        }
+    }
+}
+
+// Chat as the local player sees it, along with who they muted
+#[derive(Resource, Default)]
+pub struct ChatLog {
+    pub lines: VecDeque<ChatLine>,
+    pub muted: HashSet<u64>,
+}
+
+impl ChatLog {
+    pub fn push(&mut self, line: ChatLine) {
+        if line.sender.is_some_and(|sender| self.muted.contains(&sender)) {
+            return;
+        }
+        self.lines.push_back(line);
+        while self.lines.len() > CHAT_HISTORY {
+            self.lines.pop_front();
+        }
+    }
+}
+
+// Something the local player typed into the chat panel
+#[derive(Event, Debug, Clone)]
+pub enum ChatCommand {
+    Say(String),
+    Mute(u64, bool),
+}
+
+// Trims the text and checks it against the length limit, the error is shown to the sender
+pub fn clean_chat_text(text: &str) -> Result<String, String> {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("Empty messages are not sent".to_string());
+    }
+    if text.chars().count() > MAX_CHAT_LENGTH {
+        return Err(format!("Messages can be at most {MAX_CHAT_LENGTH} characters"));
+    }
+    Ok(text.to_string())
+}
@@ -2,89 +2,417 @@ use bevy_renet::*;
 use renet::*;
 use renet_netcode::*;
 use std::net::{UdpSocket, SocketAddr};
+use std::thread::JoinHandle;
 use std::time::SystemTime;
 use bevy::prelude::*;
 use crate::asset_loader::GameAssets;
 use rand::{thread_rng, Rng};
 use crate::utils::*;
+use crate::utils::auth::HostLogin;
+// Netcode drops packets with a different protocol id without a word, so this never changes.
+// Versions are compared in the handshake instead, where a mismatch can be explained to the player.
 pub const PROTOCOL_ID: u64 = 12478;
-use crate::utils::*;
 use crate::GameState;
 
-pub fn create_client(mut commands: Commands, mut game_assets: ResMut<GameAssets>, mut lobby: ResMut<Lobby>) {
-    let client_id = thread_rng().gen_range(0..u64::MAX);
-    game_assets.client_id = client_id;
-    let client_address = "127.0.0.1:0";
-    println!("Creating client connected to server at address: {}, and making socket at address: {}", game_assets.server_address, client_address);
-    let mut client = RenetClient::new(ConnectionConfig::default());
-    commands.insert_resource(client);
-    let mut rng = thread_rng();
-    let authentication = ClientAuthentication::Unsecure {
-        server_addr: game_assets.server_address,
-        client_id: client_id,
-        user_data: None,
-        protocol_id: PROTOCOL_ID,
+// How often a dropped client tries to get back to the server, in seconds
+const RECONNECT_INTERVAL: f32 = 2.0;
+
+// What to tell the player when the connection dies before the server let us in
+pub fn connect_failure_message(reason: NetcodeDisconnectReason, address: &str) -> String {
+    match reason {
+        NetcodeDisconnectReason::ConnectionDenied => format!("{address} refused the connection, the server may be full"),
+        NetcodeDisconnectReason::ConnectionRequestTimedOut | NetcodeDisconnectReason::ConnectionResponseTimedOut => {
+            format!("Nothing answered at {address}, check the address and that the server is running")
+        }
+        NetcodeDisconnectReason::ConnectTokenExpired => format!("Took too long to log in to {address}, try again"),
+        NetcodeDisconnectReason::ConnectionTimedOut | NetcodeDisconnectReason::DisconnectedByServer => {
+            format!("Lost the connection to {address} while joining")
+        }
+        NetcodeDisconnectReason::DisconnectedByClient => format!("Stopped connecting to {address}"),
+    }
+}
+
+// The id and session token stay the same for the whole run so a dropped connection can reclaim its seat
+pub fn ensure_identity(game_assets: &mut GameAssets) {
+    if game_assets.client_id == 0 {
+        game_assets.client_id = thread_rng().gen_range(1..u64::MAX);
+        game_assets.session_token = thread_rng().gen();
+    }
+}
+
+// Looking up the address and logging in, running on their own thread since either can take seconds
+#[derive(Resource)]
+pub struct PendingConnect(Option<JoinHandle<ConnectResult>>);
+
+// Where to connect and the token to do it with, if we logged in
+type ConnectResult = Result<(SocketAddr, Option<ConnectToken>), String>;
+
+// Why we couldn't even start connecting, for the connecting screen to show
+#[derive(Event)]
+pub struct ConnectFailed(pub String);
+
+// Runs when the connecting screen opens, with the address the join screen took or the host's own server
+pub fn create_client(
+    mut commands: Commands,
+    mut game_assets: ResMut<GameAssets>,
+    mut lobby: ResMut<Lobby>,
+    mut game_state: ResMut<NextState<GameState>>,
+    host_login: Option<Res<HostLogin>>,
+    transport_setup: Res<TransportSetup>,
+) {
+    ensure_identity(&mut game_assets);
+    if let Err(error) = connect(&mut commands, &game_assets, host_login.as_deref(), &transport_setup) {
+        println!("Could not connect to {}: {error}", game_assets.server_host);
+        game_assets.connection_error = Some(error);
+        game_state.set(GameState::JoinServer);
+        return;
+    }
+    lobby.add_deck(game_assets.deck.clone());
+}
+
+fn connect(commands: &mut Commands, game_assets: &GameAssets, host_login: Option<&HostLogin>, transport_setup: &TransportSetup) -> Result<(), String> {
+    if let TransportSetup::Loopback(hub) = transport_setup {
+        // Straight to the server in this process, there's no address and nobody to log in with
+        println!("Creating an in-memory client");
+        commands.insert_resource(RenetClient::new(ConnectionConfig::default()));
+        commands.insert_resource(hub.connect(game_assets.client_id));
+        return Ok(());
+    }
+    if let Some(host_login) = host_login {
+        // Hosting a secure server, we sign our own way in
+        let connect_token = host_login.connect_token(game_assets.server_address, game_assets.client_id, &game_assets.player_name)?;
+        return open_transport(commands, game_assets.server_address, ClientAuthentication::Secure { connect_token });
+    }
+    // finish_connect_system takes it from here once the thread is done
+    let (host, name, password) = (game_assets.server_host.clone(), game_assets.player_name.clone(), game_assets.password.clone());
+    commands.insert_resource(PendingConnect(Some(std::thread::spawn(move || {
+        let address = resolve_server_address(&host)?;
+        // Logged in players get their client_id from the host's login service, a wrong password stops here
+        let connect_token = if password.is_empty() {
+            None
+        } else {
+            Some(auth::request_connect_token(address, &name, &password)?)
+        };
+        Ok((address, connect_token))
+    }))));
+    Ok(())
+}
+
+// Connects with whatever the lookup and login came back with, or tells the connecting screen why we can't
+pub fn finish_connect_system(
+    mut commands: Commands,
+    mut pending: ResMut<PendingConnect>,
+    mut game_assets: ResMut<GameAssets>,
+    mut failures: EventWriter<ConnectFailed>,
+) {
+    if !pending.0.as_ref().is_some_and(|thread| thread.is_finished()) {
+        return;
+    }
+    commands.remove_resource::<PendingConnect>();
+    let Some(thread) = pending.0.take() else {
+        return;
     };
-    let socket = UdpSocket::bind(client_address).unwrap();
-    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-    let mut transport = NetcodeClientTransport::new(current_time, authentication, socket).unwrap();
+    let result = thread.join().unwrap_or_else(|_| Err("Connecting failed unexpectedly".to_string()))
+        .and_then(|(address, connect_token)| {
+            game_assets.server_address = address;
+            let authentication = match connect_token {
+                Some(connect_token) => {
+                    game_assets.client_id = connect_token.client_id;
+                    ClientAuthentication::Secure { connect_token }
+                }
+                None => ClientAuthentication::Unsecure {
+                    server_addr: address,
+                    client_id: game_assets.client_id,
+                    user_data: None,
+                    protocol_id: PROTOCOL_ID,
+                },
+            };
+            open_transport(&mut commands, address, authentication)
+        });
+    if let Err(error) = result {
+        println!("Could not connect to {}: {error}", game_assets.server_host);
+        failures.send(ConnectFailed(error));
+    }
+}
+
+fn open_transport(commands: &mut Commands, server_address: SocketAddr, authentication: ClientAuthentication) -> Result<(), String> {
+    // Any local address, so servers on other machines can be reached too
+    let client_address = if server_address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    println!("Creating client connected to server at address: {}, and making socket at address: {}", server_address, client_address);
+    let socket = UdpSocket::bind(client_address)
+        .map_err(|error| format!("Couldn't open a network socket: {error}"))?;
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let transport = UdpClientTransport::new(current_time, authentication, socket)
+        .map_err(|error| format!("Couldn't set up the connection to {server_address}: {error}"))?;
     println!("Transport created");
+    commands.insert_resource(RenetClient::new(ConnectionConfig::default()));
     commands.insert_resource(transport);
-    lobby.add_deck(game_assets.deck.clone());
+    Ok(())
+}
+
+// Keeps trying to reconnect with the same identity, the server holds our seat for a grace period
+pub fn reconnect_system(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    host_login: Option<Res<HostLogin>>,
+    transport_setup: Res<TransportSetup>,
+    time: Res<Time>,
+    mut since_last_attempt: Local<f32>,
+) {
+    // The server told us why it won't have us, retrying won't change its mind
+    if game_assets.connection_error.is_some() {
+        return;
+    }
+    *since_last_attempt += time.delta_secs();
+    if *since_last_attempt < RECONNECT_INTERVAL {
+        return;
+    }
+    *since_last_attempt = 0.0;
+    println!("Connection lost, trying to reconnect to {}", game_assets.server_host);
+    if let Err(error) = connect(&mut commands, &game_assets, host_login.as_deref(), &transport_setup) {
+        println!("Reconnecting failed: {error}");
+    }
+}
+
+// Hangs up a connection that never got as far as the lobby, when the player cancels or it fails
+pub fn drop_connection(
+    mut commands: Commands,
+    transport: Option<ResMut<UdpClientTransport>>,
+    loopback: Option<ResMut<LoopbackClientTransport>>,
+) {
+    if let Some(mut transport) = transport {
+        transport.disconnect();
+    }
+    if let Some(mut loopback) = loopback {
+        loopback.disconnect();
+    }
+    // A lookup or login still running just finishes with nobody listening
+    commands.remove_resource::<PendingConnect>();
+    commands.remove_resource::<RenetClient>();
+    commands.remove_resource::<UdpClientTransport>();
+    commands.remove_resource::<LoopbackClientTransport>();
+}
+
+// Leaves the table and hangs up when the player leaves the server, so the seat is freed right away instead of held
+pub fn destroy_client(
+    mut commands: Commands,
+    client: Option<ResMut<RenetClient>>,
+    transport: Option<ResMut<UdpClientTransport>>,
+    loopback: Option<ResMut<LoopbackClientTransport>>,
+) {
+    if let Some(mut client) = client {
+        if client.is_connected() {
+            client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::LeaveTable));
+        }
+        if let Some(mut transport) = transport {
+            transport.hang_up(&mut client);
+        }
+        if let Some(mut loopback) = loopback {
+            loopback.hang_up(&mut client);
+        }
+    }
+    println!("Disconnected from the server");
+    commands.remove_resource::<PendingConnect>();
+    commands.remove_resource::<RenetClient>();
+    commands.remove_resource::<UdpClientTransport>();
+    commands.remove_resource::<LoopbackClientTransport>();
+}
+
+// Forgets everything the server told us once we're off it, only the deck carries over
+pub fn clear_client_view(
+    mut lobby: ResMut<Lobby>,
+    mut chat_log: ResMut<ChatLog>,
+    mut table_directory: ResMut<TableDirectory>,
+    mut table_connections: ResMut<TableConnections>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let deck = lobby.deck.clone();
+    *lobby = Lobby::new();
+    lobby.add_deck(deck);
+    *chat_log = ChatLog::default();
+    table_connections.0.clear();
+    table_directory.tables.clear();
+    game_state.set(GameState::ServerSelect);
+}
 
-   
+pub fn send_handshake_system(mut client: ResMut<RenetClient>) {
+    client.send_message(HANDSHAKE_CHANNEL, Into::<Bytes>::into(Handshake::current()));
 }
 
-pub fn send_player_message_system(mut client: ResMut<RenetClient>, mut lobby: ResMut<Lobby>, game_assets: Res<GameAssets>) {
+pub fn receive_handshake_system(
+    mut client: ResMut<RenetClient>,
+    mut lobby: ResMut<Lobby>,
+    mut game_assets: ResMut<GameAssets>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut settings: ResMut<Settings>,
+    state: Res<State<GameState>>,
+) {
+    while let Some(message) = client.receive_message(HANDSHAKE_CHANNEL) {
+        match HandshakeResponse::try_from(message) {
+            Ok(HandshakeResponse::Accepted(handshake)) => {
+                println!("Connected to a {} host with features {:#b}", handshake.game_version, handshake.features);
+                // Shows up under recent servers on the join screen
+                settings.remember_server(&game_assets.server_host);
+                send_player_message(&mut client, &mut lobby, &game_assets);
+                // A reconnect mid-game stays where it is
+                if *state.get() == GameState::Connecting {
+                    game_state.set(GameState::Lobby);
+                }
+            }
+            Ok(HandshakeResponse::Rejected(reason)) => {
+                println!("The server rejected us: {reason}");
+                game_assets.connection_error = Some(reason);
+                client.disconnect();
+                game_state.set(GameState::JoinServer);
+            }
+            Err(_) => {
+                game_assets.connection_error = Some("The host runs an incompatible version of the game".to_string());
+                client.disconnect();
+                game_state.set(GameState::JoinServer);
+            }
+        }
+    }
+}
+
+fn send_player_message(client: &mut RenetClient, lobby: &mut Lobby, game_assets: &GameAssets) {
+    if game_assets.spectate {
+        client.send_message(
+            DefaultChannel::ReliableOrdered,
+            Into::<Bytes>::into(ServerMessage::Spectate(game_assets.player_name.clone(), game_assets.session_token))
+        );
+        return;
+    }
     let player = Player{
         name: game_assets.player_name.clone(),
         client_id: game_assets.client_id,
         ..Default::default()
     };
 
-    lobby.add_player(player.clone());
+    // On a reconnect we are already seated, the server will send our seat back in a snapshot
+    if lobby.get_player_mut_by_id(game_assets.client_id).is_none() {
+        lobby.add_player(player.clone());
+    }
 
-    client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::Player(player)));
+    client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::Join(player, game_assets.session_token)));
 
 }
 
-pub fn send_message_system(mut client: ResMut<RenetClient>, mut lobby: ResMut<Lobby>, mut events: EventReader<Action>, game_assets: Res<GameAssets>) {
+// Sends our action to the server, our table only changes once the server plays it and tells everyone
+pub fn send_message_system(
+    mut client: ResMut<RenetClient>,
+    lobby: Res<Lobby>,
+    mut events: EventReader<Action>,
+    game_assets: Res<GameAssets>,
+    mut sent_at: Local<Option<u32>>,
+) {
     for action in events.read() {
-        match lobby.play_turn(*action) {
-            ActionResult::Error(error, error_code) => {
-                println!("Error: {:?}", error);
-            }
-            ActionResult::Success => {
-                client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::Action(*action, game_assets.client_id)));
-            }
+        // One action per turn, anything after it is a double click or a pre-action that hasn't heard back yet
+        if lobby.current_player_id() != Some(game_assets.client_id) || *sent_at == Some(lobby.sequence) {
+            continue;
         }
+        // Tried on a copy first so a move the server would refuse never leaves
+        if let ActionResult::Error(error, _) = lobby.clone().play_turn(*action) {
+            println!("Error: {:?}", error);
+            continue;
+        }
+        *sent_at = Some(lobby.sequence);
+        client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::Action(*action, game_assets.client_id, lobby.sequence)));
     }
 }
 
 pub fn receive_message_system(
     mut client: ResMut<RenetClient>,
     mut lobby: ResMut<Lobby>,
-    mut events: EventWriter<Action>,
-    mut game_state: ResMut<NextState<GameState>>,
+    (mut game_state, state): (ResMut<NextState<GameState>>, Res<State<GameState>>),
+    // What the server says about the table besides its state
+    (mut turn_clock, mut table_connections): (ResMut<TurnClock>, ResMut<TableConnections>),
+    mut chat_log: ResMut<ChatLog>,
+    mut table_directory: ResMut<TableDirectory>,
     game_assets: Res<GameAssets>,
 ) {
     while let Some(message) = client.receive_message(DefaultChannel::ReliableOrdered) {
         // message could be a player or an action
-        let server_message = ServerMessage::from(message);
+        let server_message = match ServerMessage::try_from(message) {
+            Ok(server_message) => server_message,
+            Err(error) => {
+                // Whatever it was, our table might be out of date now
+                println!("Ignoring a bad message from the server: {error}");
+                client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::RequestSnapshot));
+                continue;
+            }
+        };
         match server_message {
             ServerMessage::Player(player) => {
-                lobby.add_player(player);
+                lobby.update_player(player);
             }
-            ServerMessage::Action(action, client_id) => {
-                if client_id != game_assets.client_id {
-                    events.send(action);
-                    lobby.play_turn(action);
+            ServerMessage::DealHand(hand) => {
+                if let Some(player) = lobby.get_player_mut_by_id(game_assets.client_id) {
+                    player.hand = hand;
+                }
+            }
+            ServerMessage::Action(action, _, sequence) => {
+                // The server's word goes for every seat, ours included
+                if sequence != lobby.sequence || matches!(lobby.play_turn(action), ActionResult::Error(..)) {
+                    // Our table has drifted from the server's, ask for the real one
+                    client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::RequestSnapshot));
                 }
             }
             ServerMessage::StartGame => {
                 game_state.set(GameState::InGame);
             }
+            ServerMessage::TableSnapshot(snapshot) => {
+                lobby.apply_snapshot(snapshot);
+                // Joined a table that's already playing, StartGame went out before we got here
+                if lobby.stage != Stage::Waiting && *state.get() == GameState::Lobby {
+                    game_state.set(GameState::InGame);
+                }
+            }
+            ServerMessage::TurnClock(clock) => {
+                *turn_clock = clock;
+            }
+            ServerMessage::ConnectionQuality(report) => {
+                table_connections.0 = report;
+            }
+            ServerMessage::Chat(line) => {
+                chat_log.push(line);
+            }
+            ServerMessage::TableList(tables) => {
+                table_directory.tables = tables;
+            }
+            ServerMessage::LeaveTable => {
+                // Away from the table, only the deck carries over to the next one
+                let deck = lobby.deck.clone();
+                *lobby = Lobby::new();
+                lobby.add_deck(deck);
+                table_connections.0.clear();
+                game_state.set(GameState::Lobby);
+            }
+            ServerMessage::RequestSnapshot
+            | ServerMessage::Join(..)
+            | ServerMessage::Spectate(..)
+            | ServerMessage::RequestSeat
+            | ServerMessage::SendChat(..)
+            | ServerMessage::Mute(..)
+            | ServerMessage::Ready(..)
+            | ServerMessage::ListTables
+            | ServerMessage::CreateTable(..)
+            | ServerMessage::JoinTable(..) => {}
         }
     }
 }
 
+
+// Counts our copy of the turn clock down between updates from the server
+pub fn tick_turn_clock_system(mut turn_clock: ResMut<TurnClock>, time: Res<Time>) {
+    turn_clock.remaining = (turn_clock.remaining - time.delta_secs()).max(0.0);
+}
+
+pub fn send_chat_system(mut client: ResMut<RenetClient>, mut events: EventReader<ChatCommand>) {
+    for event in events.read() {
+        let message = match event {
+            ChatCommand::Say(text) => ServerMessage::SendChat(text.clone()),
+            ChatCommand::Mute(client_id, muted) => ServerMessage::Mute(*client_id, *muted),
+        };
+        client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(message));
+    }
+}
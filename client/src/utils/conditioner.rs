@@ -0,0 +1,101 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+
+// Makes a perfect link behave like a bad one, to see how the game copes with players on poor Wi-Fi.
+// Both the in-memory and the UDP transports send through it, and it can be turned on or off mid game.
+
+// Longest latency or jitter it takes, anything slower is as good as lost
+pub const MAX_DELAY_MS: f32 = 10_000.0;
+
+// What the link does to packets, each side applies it to what it sends so latency counts once each way
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkConditions {
+    pub enabled: bool,
+    pub latency_ms: f32,
+    // Up to this much extra delay at random, enough of it puts packets out of order
+    pub jitter_ms: f32,
+    pub loss_percent: f32,
+    pub duplicate_percent: f32,
+}
+
+impl NetworkConditions {
+    // Reads --latency and --jitter in milliseconds, and --loss and --duplicate in percent. Giving any of them turns the conditions on.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut conditions = NetworkConditions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (field, max) = match arg.as_str() {
+                "--latency" => (&mut conditions.latency_ms, MAX_DELAY_MS),
+                "--jitter" => (&mut conditions.jitter_ms, MAX_DELAY_MS),
+                "--loss" => (&mut conditions.loss_percent, 100.0),
+                "--duplicate" => (&mut conditions.duplicate_percent, 100.0),
+                _ => continue,
+            };
+            let value = args.next().ok_or_else(|| format!("{arg} needs a value"))?;
+            *field = value.parse::<f32>().ok()
+                .filter(|value| (0.0..=max).contains(value))
+                .ok_or_else(|| format!("{value} is not a valid value for {arg}"))?;
+            conditions.enabled = true;
+        }
+        Ok(conditions)
+    }
+}
+
+impl std::fmt::Display for NetworkConditions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}ms latency, {}ms jitter, {}% loss and {}% duplicates", self.latency_ms, self.jitter_ms, self.loss_percent, self.duplicate_percent)
+    }
+}
+
+// Holds packets back until the conditions say they arrive, one per sender
+pub struct Conditioner<T> {
+    rng: StdRng,
+    queue: Vec<(Duration, T)>,
+}
+
+impl<T: Clone> Conditioner<T> {
+    pub fn new() -> Self {
+        Conditioner {
+            rng: StdRng::from_entropy(),
+            queue: Vec::new(),
+        }
+    }
+
+    // Loses, delays or doubles the packet, with the conditions off it goes out with the next release
+    pub fn push(&mut self, now: Duration, packet: T, conditions: &NetworkConditions) {
+        if !conditions.enabled {
+            self.queue.push((now, packet));
+            return;
+        }
+        if self.rng.gen_range(0.0..100.0) < conditions.loss_percent {
+            return;
+        }
+        let copies = if self.rng.gen_range(0.0..100.0) < conditions.duplicate_percent { 2 } else { 1 };
+        for _ in 0..copies {
+            let jitter = if conditions.jitter_ms > 0.0 { self.rng.gen_range(0.0..conditions.jitter_ms) } else { 0.0 };
+            let delay = Duration::from_secs_f32((conditions.latency_ms + jitter) / 1000.0);
+            self.queue.push((now + delay, packet.clone()));
+        }
+    }
+
+    // Packets that are due, in the order they're due
+    pub fn release(&mut self, now: Duration) -> Vec<T> {
+        let (mut ready, waiting): (Vec<_>, Vec<_>) = self.queue.drain(..).partition(|(due, _)| *due <= now);
+        self.queue = waiting;
+        ready.sort_by_key(|(due, _)| *due);
+        ready.into_iter().map(|(_, packet)| packet).collect()
+    }
+
+    // Everything still held back, for a last goodbye
+    pub fn release_all(&mut self) -> Vec<T> {
+        self.release(Duration::MAX)
+    }
+}
+
+impl<T: Clone> Default for Conditioner<T> {
+    fn default() -> Self {
+        Conditioner::new()
+    }
+}
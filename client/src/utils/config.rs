@@ -0,0 +1,256 @@
+use bevy::prelude::*;
+use bevy::window::{WindowMode, WindowResolution, MonitorSelection};
+use bevy_framepace::{FramepaceSettings, Limiter};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::GAME_NAME;
+
+const SETTINGS_FILE: &str = "settings.ron";
+// Recent servers past this many are forgotten, favorites are kept however many there are
+const MAX_RECENT_SERVERS: usize = 8;
+// A hand-edited file can say anything, these keep the window and frame limiter usable
+const MIN_RESOLUTION: (u32, u32) = (640, 360);
+const MAX_RESOLUTION: (u32, u32) = (7680, 4320);
+const MAX_FPS: u32 = 1000;
+
+// Everything about the player's setup that should survive a restart.
+// Missing fields fall back to their defaults, so older files keep loading as settings are added.
+#[derive(Resource, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub video: VideoSettings,
+    pub audio: AudioSettings,
+    pub player_name: String,
+    // Filled in on the join screen next time
+    pub last_server: String,
+    pub gameplay: GameplaySettings,
+    // Recently joined and favorite servers, most recent first
+    pub servers: Vec<SavedServer>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedServer {
+    pub address: String,
+    // Shown instead of the address when set
+    pub label: String,
+    pub favorite: bool,
+    // Unix seconds, zero if never
+    pub last_joined: u64,
+    pub last_seen: u64,
+    pub last_ping_ms: Option<u32>,
+}
+
+impl SavedServer {
+    pub fn name(&self) -> &str {
+        if self.label.is_empty() { &self.address } else { &self.label }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoSettings {
+    pub resolution: (u32, u32),
+    pub fps: u32,
+    pub window_mode: WindowModeSetting,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WindowModeSetting {
+    #[default]
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+impl WindowModeSetting {
+    pub fn window_mode(&self) -> WindowMode {
+        match self {
+            WindowModeSetting::Windowed => WindowMode::Windowed,
+            WindowModeSetting::Borderless => WindowMode::SizedFullscreen(MonitorSelection::Primary),
+            WindowModeSetting::Fullscreen => WindowMode::Fullscreen(MonitorSelection::Primary),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    // 0 is muted, 1 is full volume
+    pub volume: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameplaySettings {
+    // Cards and chips flying around the table, off shows everything where it lands straight away
+    pub table_animations: bool,
+    // Single keys for fold, call, raise and bet sizes at the table
+    pub keyboard_shortcuts: bool,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        VideoSettings {
+            resolution: (1920, 1080),
+            fps: 60,
+            window_mode: WindowModeSetting::Windowed,
+        }
+    }
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings { volume: 1.0 }
+    }
+}
+
+impl Default for GameplaySettings {
+    fn default() -> Self {
+        GameplaySettings {
+            table_animations: true,
+            keyboard_shortcuts: true,
+        }
+    }
+}
+
+impl Settings {
+    // Where the settings live: the platform's config directory, in a folder named after the game
+    pub fn path() -> Option<PathBuf> {
+        let config_dir = if cfg!(target_os = "windows") {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        };
+        config_dir.map(|dir| dir.join(GAME_NAME).join(SETTINGS_FILE))
+    }
+
+    // A missing or broken file gives the defaults, the game should always start
+    pub fn load() -> Self {
+        let Some(path) = Settings::path() else {
+            return Settings::default();
+        };
+        let settings = match fs::read_to_string(&path) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|error| {
+                println!("Ignoring unreadable settings in {}: {error}", path.display());
+                Settings::default()
+            }),
+            Err(_) => Settings::default(),
+        };
+        settings.clamped()
+    }
+
+    // Pulls anything out of range back in, fps 0 is left alone and means no limit
+    fn clamped(mut self) -> Self {
+        let (width, height) = self.video.resolution;
+        self.video.resolution = (width.clamp(MIN_RESOLUTION.0, MAX_RESOLUTION.0), height.clamp(MIN_RESOLUTION.1, MAX_RESOLUTION.1));
+        self.video.fps = self.video.fps.min(MAX_FPS);
+        self.audio.volume = if self.audio.volume.is_finite() { self.audio.volume.clamp(0.0, 1.0) } else { 1.0 };
+        self
+    }
+
+    // Written next to the real file first, so a crash halfway through can't leave it cut short
+    pub fn save(&self) -> io::Result<()> {
+        let path = Settings::path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let temp_path = path.with_extension("ron.tmp");
+        fs::write(&temp_path, text)?;
+        fs::rename(temp_path, path)
+    }
+
+    // Moves the server to the top of the list, adding it if it's new
+    pub fn remember_server(&mut self, address: &str) {
+        let mut server = match self.servers.iter().position(|server| server.address == address) {
+            Some(index) => self.servers.remove(index),
+            None => SavedServer {
+                address: address.to_string(),
+                ..Default::default()
+            },
+        };
+        server.last_joined = unix_time();
+        self.servers.insert(0, server);
+        let mut recent = 0;
+        self.servers.retain(|server| {
+            recent += usize::from(!server.favorite);
+            server.favorite || recent <= MAX_RECENT_SERVERS
+        });
+    }
+
+    // Saves the server as a favorite under the label, or relabels it if it's already saved
+    pub fn favorite_server(&mut self, address: &str, label: &str) {
+        match self.servers.iter_mut().find(|server| server.address == address) {
+            Some(server) => {
+                server.favorite = true;
+                server.label = label.to_string();
+            }
+            None => self.servers.push(SavedServer {
+                address: address.to_string(),
+                label: label.to_string(),
+                favorite: true,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn window_resolution(&self) -> WindowResolution {
+        WindowResolution::new(self.video.resolution.0 as f32, self.video.resolution.1 as f32)
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+pub struct ConfigPlugin;
+
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, apply_settings_system);
+    }
+}
+
+// Pushes changed settings out to the window, frame limiter and audio, then saves them.
+// Runs on the first frame too, which is how the loaded settings get applied.
+fn apply_settings_system(
+    settings: Res<Settings>,
+    mut window_query: Query<&mut Window>,
+    mut framepace_settings: ResMut<FramepaceSettings>,
+    mut global_volume: ResMut<GlobalVolume>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    for mut window in window_query.iter_mut() {
+        let resolution = settings.window_resolution();
+        if window.resolution.width() != resolution.width() || window.resolution.height() != resolution.height() {
+            window.resolution = resolution;
+        }
+        let mode = settings.video.window_mode.window_mode();
+        if window.mode != mode {
+            window.mode = mode;
+        }
+    }
+    framepace_settings.limiter = match settings.video.fps {
+        0 => Limiter::Off,
+        fps => Limiter::from_framerate(fps as f64),
+    };
+    *global_volume = GlobalVolume::new(settings.audio.volume);
+    if settings.is_added() {
+        return;
+    }
+    if let Err(error) = settings.save() {
+        println!("Couldn't save settings: {error}");
+    }
+}
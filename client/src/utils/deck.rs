@@ -1,7 +1,10 @@
 use bevy::prelude::*;
 use serde::{Serialize, Deserialize};
 use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use cards::card::{Value, Suit, Card as CCard};
+use std::fmt;
 
 #[derive(Debug, Clone,)]
 pub struct Card {
@@ -43,6 +46,27 @@ pub struct BytesCard {
     pub suit: String,
 }
 
+impl BytesCard {
+    // None if the card came off the wire with a rank or suit that doesn't exist
+    pub fn to_cards_card(&self) -> Option<CCard> {
+        Some(CCard::new(parse_value(&self.rank)?, parse_suit(&self.suit)?))
+    }
+}
+
+// Short form used in dealer messages, "As", "10h"
+impl fmt::Display for BytesCard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rank = match self.rank.as_str() {
+            "01" => "A",
+            "11" => "J",
+            "12" => "Q",
+            "13" => "K",
+            rank => rank.trim_start_matches('0'),
+        };
+        write!(f, "{}{}", rank, self.suit.chars().next().unwrap_or('?'))
+    }
+}
+
 #[derive(Debug, Clone, Resource)]
 pub struct Deck {
     pub cards: Vec<Card>,
@@ -67,9 +91,8 @@ impl Deck {
         Self { cards: Vec::new() }
     }
 
-    pub fn shuffle(&mut self) {
-        let mut rng = rand::thread_rng();
-        self.cards.shuffle(&mut rng);
+    pub fn shuffle(&mut self, rng: &mut impl Rng) {
+        self.cards.shuffle(rng);
     }
 
     pub fn draw(&mut self) -> Option<Card> {
@@ -89,8 +112,23 @@ impl Deck {
     }
 }
 
+// Shuffles every deck the server deals. Seeded from entropy unless one was put in place before the server
+// started, which is how the simulation and tests get the same hands on every run.
+#[derive(Resource)]
+pub struct ShuffleRng(pub StdRng);
+
+impl Default for ShuffleRng {
+    fn default() -> Self {
+        ShuffleRng(StdRng::from_entropy())
+    }
+}
+
 fn string_to_value(s: &str) -> Value {
-    match s {
+    parse_value(s).unwrap_or_else(|| panic!("Invalid value: {}", s))
+}
+
+fn parse_value(s: &str) -> Option<Value> {
+    let value = match s {
         "01" => Value::Ace,
         "02" => Value::Two,
         "03" => Value::Three,
@@ -104,18 +142,24 @@ fn string_to_value(s: &str) -> Value {
         "11" => Value::Jack,
         "12" => Value::Queen,
         "13" => Value::King,
-        _ => panic!("Invalid value: {}", s),
-    }
+        _ => return None,
+    };
+    Some(value)
 }
 
 fn string_to_suit(s: &str) -> Suit {
-    match s {
+    parse_suit(s).unwrap_or_else(|| panic!("Invalid suit: {}", s))
+}
+
+fn parse_suit(s: &str) -> Option<Suit> {
+    let suit = match s {
         "hearts" => Suit::Hearts,
         "diamonds" => Suit::Diamonds,
         "clubs" => Suit::Clubs,
         "spades" => Suit::Spades,
-        _ => panic!("Invalid suit: {}", s),
-    }
+        _ => return None,
+    };
+    Some(suit)
 }
 
 
@@ -0,0 +1,376 @@
+use bevy::prelude::*;
+use renet::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+use crate::utils::{AddressLookup, DecodeError, decode};
+
+// LAN discovery. Hosts broadcast a small beacon on the local network every second,
+// and the join screen listens for them so games can be joined without typing an address.
+// Hosts also answer status queries with the same beacon, which is how saved servers get their ping.
+// Queries are padded so the answer is never bigger than the question, and each address only gets an answer
+// every so often, so a host can't be used to flood someone else by spoofing queries from their address.
+
+pub const DISCOVERY_PORT: u16 = 2164;
+const BEACON_INTERVAL: Duration = Duration::from_secs(1);
+// A game that hasn't been heard from for this long is taken off the list
+const BEACON_EXPIRY: Duration = Duration::from_secs(5);
+// Status queries go this far above the game port, which steps over the discovery port with the default game port
+const STATUS_PORT_OFFSET: u16 = 2;
+// What a status query starts with, the rest is zeros up to STATUS_QUERY_SIZE. Anything else sent to the status port is ignored.
+const STATUS_QUERY: &[u8] = b"status?";
+const STATUS_QUERY_SIZE: usize = 256;
+// How often one address gets an answer, the join screen only asks every few seconds
+const STATUS_RATE_LIMIT: Duration = Duration::from_secs(1);
+// A server that hasn't answered by then is shown as offline
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Where a game server answers status queries
+pub fn status_address(game_address: SocketAddr) -> SocketAddr {
+    SocketAddr::new(game_address.ip(), game_address.port().wrapping_add(STATUS_PORT_OFFSET))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Beacon {
+    pub table_name: String,
+    // The game's UDP port, the address is wherever the beacon came from
+    pub game_port: u16,
+    pub players: u32,
+    pub max_seats: u32,
+    pub starting_stack: i32,
+    pub protocol_version: u32,
+    // The stakes go after the version, so older builds still read the version and list the game as a different version
+    pub small_blind: i32,
+    pub big_blind: i32,
+}
+
+impl TryFrom<Bytes> for Beacon {
+    type Error = DecodeError;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        decode(&value)
+    }
+}
+
+impl From<Beacon> for Bytes {
+    fn from(value: Beacon) -> Self {
+        Bytes::copy_from_slice(&bincode::serialize(&value).unwrap())
+    }
+}
+
+impl Beacon {
+    // Shortens the table name until the beacon fits in a status answer
+    fn status_answer(mut self) -> Bytes {
+        loop {
+            let bytes: Bytes = self.clone().into();
+            if bytes.len() <= STATUS_QUERY_SIZE || self.table_name.pop().is_none() {
+                return bytes;
+            }
+        }
+    }
+}
+
+fn status_query() -> Vec<u8> {
+    let mut query = STATUS_QUERY.to_vec();
+    query.resize(STATUS_QUERY_SIZE, 0);
+    query
+}
+
+// Host side, broadcasts the beacon
+#[derive(Resource)]
+pub struct BeaconSender {
+    socket: UdpSocket,
+    since_last: Duration,
+}
+
+impl BeaconSender {
+    pub fn new() -> Option<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+        socket.set_broadcast(true).ok()?;
+        socket.set_nonblocking(true).ok()?;
+        Some(BeaconSender {
+            socket,
+            since_last: BEACON_INTERVAL,
+        })
+    }
+
+    // Sends a fresh beacon once a second, the closure is only called when one is due
+    pub fn tick(&mut self, delta: Duration, beacon: impl FnOnce() -> Beacon) {
+        self.since_last += delta;
+        if self.since_last < BEACON_INTERVAL {
+            return;
+        }
+        self.since_last = Duration::ZERO;
+        let bytes: Bytes = beacon().into();
+        if let Err(error) = self.socket.send_to(&bytes, (Ipv4Addr::BROADCAST, DISCOVERY_PORT)) {
+            println!("Could not send the LAN beacon: {error}");
+        }
+    }
+}
+
+// Join screen side, collects the beacons of games on the network
+#[derive(Resource)]
+pub struct LanBrowser {
+    socket: UdpSocket,
+    pub games: HashMap<SocketAddr, Beacon>,
+    last_seen: HashMap<SocketAddr, Instant>,
+}
+
+impl LanBrowser {
+    // Only one browser per machine can listen, a second game window just won't see LAN games
+    pub fn new() -> Option<Self> {
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)) {
+            Ok(socket) => socket,
+            Err(error) => {
+                println!("Not listening for LAN games: {error}");
+                return None;
+            }
+        };
+        socket.set_nonblocking(true).ok()?;
+        Some(LanBrowser {
+            socket,
+            games: HashMap::new(),
+            last_seen: HashMap::new(),
+        })
+    }
+}
+
+// Reads every waiting beacon and forgets games that went quiet. Only marks the browser changed
+// when the list itself changed, so the join screen isn't rebuilt every second.
+pub fn lan_browser_system(mut browser: ResMut<LanBrowser>) {
+    let mut changed = false;
+    let inner = browser.bypass_change_detection();
+    let mut buffer = [0; 1024];
+    while let Ok((len, from)) = inner.socket.recv_from(&mut buffer) {
+        let Ok(beacon) = Beacon::try_from(Bytes::copy_from_slice(&buffer[..len])) else {
+            continue;
+        };
+        let address = SocketAddr::new(from.ip(), beacon.game_port);
+        inner.last_seen.insert(address, Instant::now());
+        if inner.games.get(&address) != Some(&beacon) {
+            inner.games.insert(address, beacon);
+            changed = true;
+        }
+    }
+    let expired: Vec<SocketAddr> = inner.last_seen.iter()
+        .filter(|(_, seen)| seen.elapsed() > BEACON_EXPIRY)
+        .map(|(address, _)| *address)
+        .collect();
+    for address in expired {
+        inner.last_seen.remove(&address);
+        inner.games.remove(&address);
+        changed = true;
+    }
+    if changed {
+        browser.set_changed();
+    }
+}
+
+// Host side, answers status queries from join screens anywhere, not just on the LAN
+#[derive(Resource)]
+pub struct StatusResponder {
+    socket: UdpSocket,
+    // When each address was last answered
+    answered: HashMap<IpAddr, Instant>,
+}
+
+impl StatusResponder {
+    pub fn new(game_address: SocketAddr) -> Option<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, status_address(game_address).port())).ok()?;
+        socket.set_nonblocking(true).ok()?;
+        Some(StatusResponder {
+            socket,
+            answered: HashMap::new(),
+        })
+    }
+
+    // Answers every waiting query, the closure is only called when someone asked
+    pub fn answer(&mut self, beacon: impl FnOnce() -> Beacon) {
+        self.answered.retain(|_, answered_at| answered_at.elapsed() < STATUS_RATE_LIMIT);
+        let mut buffer = [0; STATUS_QUERY_SIZE + 1];
+        let mut askers = Vec::new();
+        while let Ok((len, from)) = self.socket.recv_from(&mut buffer) {
+            if len != STATUS_QUERY_SIZE || !buffer.starts_with(STATUS_QUERY) || self.answered.contains_key(&from.ip()) {
+                continue;
+            }
+            self.answered.insert(from.ip(), Instant::now());
+            askers.push(from);
+        }
+        if askers.is_empty() {
+            return;
+        }
+        let bytes = beacon().status_answer();
+        for asker in askers {
+            let _ = self.socket.send_to(&bytes, asker);
+        }
+    }
+}
+
+// What we last heard from a server we asked about
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerStatus {
+    Waiting,
+    Online(Beacon, Duration),
+    Offline,
+}
+
+// Join screen side, asks saved servers how they are doing
+#[derive(Resource)]
+pub struct ServerPinger {
+    socket: UdpSocket,
+    // Keyed by the address as it was saved, host names and all
+    pub statuses: HashMap<String, ServerStatus>,
+    lookups: Vec<(String, AddressLookup)>,
+    // Keyed by game address, the status port is only used on the wire
+    sent: HashMap<SocketAddr, (String, Instant)>,
+}
+
+impl ServerPinger {
+    pub fn new() -> Option<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+        socket.set_nonblocking(true).ok()?;
+        Some(ServerPinger {
+            socket,
+            statuses: HashMap::new(),
+            lookups: Vec::new(),
+            sent: HashMap::new(),
+        })
+    }
+
+    // The query goes out once the address is looked up, see server_pinger_system
+    pub fn query(&mut self, address: &str) {
+        self.statuses.entry(address.to_string()).or_insert(ServerStatus::Waiting);
+        if !self.lookups.iter().any(|(looking_up, _)| looking_up == address) {
+            self.lookups.push((address.to_string(), AddressLookup::start(address)));
+        }
+    }
+
+    fn send_query(&mut self, address: String, game_address: SocketAddr) {
+        if self.socket.send_to(&status_query(), status_address(game_address)).is_err() {
+            self.statuses.insert(address, ServerStatus::Offline);
+            return;
+        }
+        self.sent.insert(game_address, (address, Instant::now()));
+    }
+}
+
+// Sends queries for addresses that were looked up, reads the answers that came in and gives up on servers that took too long.
+// Like the LAN browser, it only marks itself changed when a status actually changed.
+pub fn server_pinger_system(mut pinger: ResMut<ServerPinger>) {
+    let mut changed = false;
+    let inner = pinger.bypass_change_detection();
+    let mut looked_up = Vec::new();
+    inner.lookups.retain_mut(|(address, lookup)| match lookup.poll() {
+        Some(result) => {
+            looked_up.push((address.clone(), result));
+            false
+        }
+        None => true,
+    });
+    for (address, result) in looked_up {
+        match result {
+            Ok(game_address) => inner.send_query(address, game_address),
+            Err(_) => {
+                if inner.statuses.insert(address, ServerStatus::Offline) != Some(ServerStatus::Offline) {
+                    changed = true;
+                }
+            }
+        }
+    }
+    let mut buffer = [0; 1024];
+    while let Ok((len, from)) = inner.socket.recv_from(&mut buffer) {
+        let Ok(beacon) = Beacon::try_from(Bytes::copy_from_slice(&buffer[..len])) else {
+            continue;
+        };
+        let game_address = SocketAddr::new(from.ip(), beacon.game_port);
+        // Answers we didn't ask for, or asked for too long ago, don't count
+        let Some((address, sent)) = inner.sent.remove(&game_address) else {
+            continue;
+        };
+        inner.statuses.insert(address, ServerStatus::Online(beacon, sent.elapsed()));
+        changed = true;
+    }
+    let timed_out: Vec<SocketAddr> = inner.sent.iter()
+        .filter(|(_, (_, sent))| sent.elapsed() > STATUS_TIMEOUT)
+        .map(|(game_address, _)| *game_address)
+        .collect();
+    for game_address in timed_out {
+        let Some((address, _)) = inner.sent.remove(&game_address) else {
+            continue;
+        };
+        if inner.statuses.insert(address, ServerStatus::Offline) != Some(ServerStatus::Offline) {
+            changed = true;
+        }
+    }
+    if changed {
+        pinger.set_changed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beacon(table_name: &str) -> Beacon {
+        Beacon {
+            table_name: table_name.to_string(),
+            game_port: 2163,
+            players: 3,
+            max_seats: 8,
+            starting_stack: 1000,
+            protocol_version: 1,
+            small_blind: 5,
+            big_blind: 10,
+        }
+    }
+
+    #[test]
+    fn answers_are_never_bigger_than_the_query() {
+        let bytes = beacon(&"x".repeat(1000)).status_answer();
+        assert!(bytes.len() <= STATUS_QUERY_SIZE);
+        assert!(Beacon::try_from(bytes).is_ok());
+        assert_eq!(Beacon::try_from(beacon("Friday night").status_answer()).unwrap(), beacon("Friday night"));
+    }
+
+    #[test]
+    fn older_builds_still_read_the_version_of_a_beacon_with_stakes() {
+        // The beacon as it was before the stakes were added
+        #[derive(Deserialize)]
+        struct OldBeacon {
+            table_name: String,
+            _game_port: u16,
+            _players: u32,
+            _max_seats: u32,
+            _starting_stack: i32,
+            protocol_version: u32,
+        }
+        let bytes: Bytes = beacon("Friday night").into();
+        let old: OldBeacon = decode(&bytes).unwrap();
+        assert_eq!(old.table_name, "Friday night");
+        assert_eq!(old.protocol_version, 1);
+    }
+
+    #[test]
+    fn each_address_gets_one_answer_per_padded_query() {
+        let game_address = SocketAddr::from((Ipv4Addr::LOCALHOST, 41163));
+        let mut responder = StatusResponder::new(game_address).unwrap();
+        let asker = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        asker.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let mut buffer = [0; 1024];
+
+        // The bare query is smaller than the answer would be
+        asker.send_to(STATUS_QUERY, status_address(game_address)).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        responder.answer(|| beacon("Friday night"));
+        assert!(asker.recv_from(&mut buffer).is_err());
+
+        asker.send_to(&status_query(), status_address(game_address)).unwrap();
+        asker.send_to(&status_query(), status_address(game_address)).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        responder.answer(|| beacon("Friday night"));
+        let (len, _) = asker.recv_from(&mut buffer).unwrap();
+        assert_eq!(Beacon::try_from(Bytes::copy_from_slice(&buffer[..len])).unwrap(), beacon("Friday night"));
+        assert!(asker.recv_from(&mut buffer).is_err());
+    }
+}
@@ -0,0 +1,28 @@
+use cards::card::Card as CCard;
+use holdem::{HandRank, HandRankClass, hand_rank_to_class};
+use pokereval::eval_7cards;
+use crate::utils::BytesCard;
+
+// Hand evaluation for showdown, built on pokereval
+
+// Best rank a player can make from their hole cards and a full board, higher is better
+pub fn evaluate(hand: &[BytesCard], board: &[BytesCard]) -> Option<HandRank> {
+    let cards: Vec<CCard> = hand.iter().chain(board).map(|card| card.to_cards_card()).collect::<Option<_>>()?;
+    let cards: [&CCard; 7] = cards.iter().collect::<Vec<_>>().try_into().ok()?;
+    Some(eval_7cards(&cards))
+}
+
+// Name of the hand as the dealer would say it, "Alice wins 400 with a flush"
+pub fn describe(rank: HandRank) -> &'static str {
+    match hand_rank_to_class(&rank) {
+        HandRankClass::HighCard => "high card",
+        HandRankClass::OnePair => "a pair",
+        HandRankClass::TwoPair => "two pair",
+        HandRankClass::ThreeOfAKind => "three of a kind",
+        HandRankClass::Straight => "a straight",
+        HandRankClass::Flush => "a flush",
+        HandRankClass::FullHouse => "a full house",
+        HandRankClass::FourOfAKind => "four of a kind",
+        HandRankClass::StraightFlush => "a straight flush",
+    }
+}
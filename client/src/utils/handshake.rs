@@ -0,0 +1,128 @@
+use renet::{Bytes, DefaultChannel};
+use serde::{Deserialize, Serialize};
+use crate::utils::{DecodeError, decode};
+
+// The handshake is the first thing a client sends and the only thing both sides read before agreeing on a version.
+// It goes over its own channel and its layout must never change, so mismatched builds can always explain themselves.
+// New fields only ever go on the end, and handshakes from builds without them still decode.
+
+// Bump whenever ServerMessage or anything inside it changes shape
+pub const PROTOCOL_VERSION: u32 = 12;
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const HANDSHAKE_CHANNEL: DefaultChannel = DefaultChannel::ReliableUnordered;
+
+// Optional features, a client lists the ones it supports and a host turns it away if it lacks one the host needs
+pub const FEATURE_SECURE_LOGIN: u32 = 1 << 0;
+pub const FEATURE_SPECTATING: u32 = 1 << 1;
+pub const SUPPORTED_FEATURES: u32 = FEATURE_SECURE_LOGIN | FEATURE_SPECTATING;
+
+fn feature_name(feature: u32) -> &'static str {
+    match feature {
+        FEATURE_SECURE_LOGIN => "logging in",
+        FEATURE_SPECTATING => "spectating",
+        _ => "an unknown feature",
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    pub game_version: String,
+    pub features: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HandshakeResponse {
+    Accepted(Handshake),
+    // Human readable reason, shown to the player
+    Rejected(String),
+}
+
+impl Handshake {
+    pub fn current() -> Self {
+        Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            game_version: GAME_VERSION.to_string(),
+            features: SUPPORTED_FEATURES,
+        }
+    }
+
+    // What the server should answer to a client sending this handshake, given the features this host can't do without.
+    // An accepted client hears back the features both sides have.
+    pub fn respond(&self, required_features: u32) -> HandshakeResponse {
+        if self.protocol_version != PROTOCOL_VERSION {
+            return HandshakeResponse::Rejected(format!(
+                "Version mismatch: the host runs {} (protocol {}) but you have {} (protocol {})",
+                GAME_VERSION, PROTOCOL_VERSION, self.game_version, self.protocol_version
+            ));
+        }
+        let missing = required_features & !self.features;
+        if missing != 0 {
+            let feature = 1 << missing.trailing_zeros();
+            return HandshakeResponse::Rejected(format!("The host needs {}, which your game doesn't support", feature_name(feature)));
+        }
+        HandshakeResponse::Accepted(Handshake {
+            features: self.features & SUPPORTED_FEATURES,
+            ..Handshake::current()
+        })
+    }
+}
+
+impl TryFrom<Bytes> for Handshake {
+    type Error = DecodeError;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        // Builds from before feature flags stop after the game version, they support none of them
+        decode(&value).or_else(|error| {
+            decode::<(u32, String)>(&value)
+                .map(|(protocol_version, game_version)| Handshake { protocol_version, game_version, features: 0 })
+                .map_err(|_| error)
+        })
+    }
+}
+
+impl From<Handshake> for Bytes {
+    fn from(value: Handshake) -> Self {
+        Bytes::copy_from_slice(&bincode::serialize(&value).unwrap())
+    }
+}
+
+impl TryFrom<Bytes> for HandshakeResponse {
+    type Error = DecodeError;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        decode(&value)
+    }
+}
+
+impl From<HandshakeResponse> for Bytes {
+    fn from(value: HandshakeResponse) -> Self {
+        Bytes::copy_from_slice(&bincode::serialize(&value).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_without_feature_flags_are_told_their_version_is_wrong() {
+        let old: Bytes = Bytes::copy_from_slice(&bincode::serialize(&(9u32, "0.1.0".to_string())).unwrap());
+        let handshake = Handshake::try_from(old).unwrap();
+        assert_eq!(handshake.features, 0);
+        let HandshakeResponse::Rejected(reason) = handshake.respond(0) else {
+            panic!("An old build was let in");
+        };
+        assert!(reason.contains("protocol 9"));
+    }
+
+    #[test]
+    fn clients_missing_a_feature_the_host_needs_are_turned_away() {
+        let handshake = Handshake { features: FEATURE_SPECTATING, ..Handshake::current() };
+        assert!(matches!(handshake.respond(FEATURE_SECURE_LOGIN), HandshakeResponse::Rejected(reason) if reason.contains("logging in")));
+        let HandshakeResponse::Accepted(answer) = handshake.respond(FEATURE_SPECTATING) else {
+            panic!("A client with every feature the host needs was turned away");
+        };
+        assert_eq!(answer.features, FEATURE_SPECTATING);
+    }
+}
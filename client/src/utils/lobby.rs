@@ -1,28 +1,66 @@
 use bevy::prelude::*;
-use crate::utils::{Deck, BytesCard};
+use crate::utils::{Deck, BytesCard, DecodeError, decode, hand};
+use rand::Rng;
 use renet::Bytes;
 use serde::{Serialize, Deserialize};
+use std::fmt;
 
 // Implementation of a poker lobby
 
+pub type TableId = u32;
+// The table the host opens with the server, and where clients sit down unless they pick another
+pub const MAIN_TABLE: TableId = 0;
+// Longest table and player names the server lets through, short enough that a full table always fits in one message
+pub const MAX_TABLE_NAME_LENGTH: usize = 32;
+pub const MAX_PLAYER_NAME_LENGTH: usize = 24;
+
+// Names come from clients, they lose control characters and anything past the limit
+pub fn clean_name(name: &str, max_length: usize) -> String {
+    let name: String = name.trim().chars().filter(|c| !c.is_control()).take(max_length).collect();
+    name.trim_end().to_string()
+}
+
 // A lobby is a collection of players, a deck, and a turn
 #[derive(Debug, Clone, Resource)]
 pub struct Lobby {
+    pub id: TableId,
+    pub name: String,
+    // Client that opened the table and gets to start it, the host's own client for the main table
+    pub owner: Option<u64>,
     pub players: Vec<Player>,
     pub turn: u8,
+    // Goes up with every deal, action, street, showdown and seat freed mid-hand. Actions carry it so one meant
+    // for an earlier state of the table is never played on a later one.
+    pub sequence: u32,
     pub deck: Deck,
     pub pot: i32,
     pub current_bet: i32,
+    // Size of the last full bet or raise this street, the next raise has to be at least as big
+    pub last_raise: i32,
+    pub stage: Stage,
+    pub board: Vec<BytesCard>,
+    // Seat holding the dealer button, the blinds are posted by the seats after it
+    pub button: u8,
+    pub settings: TableSettings,
 }
 
 impl Lobby {
     pub fn new() -> Self {
         Lobby {
+            id: MAIN_TABLE,
+            name: String::new(),
+            owner: None,
             players: Vec::new(),
             turn: 0,
+            sequence: 0,
             deck: Deck::new_empty(),
             pot: 0,
             current_bet: 0,
+            last_raise: 0,
+            stage: Stage::Waiting,
+            board: Vec::new(),
+            button: 0,
+            settings: TableSettings::default(),
         }
     }
 
@@ -31,16 +69,170 @@ impl Lobby {
         
     }
 
+    // A new seat changes the table, so it gets a sequence like any other change
     pub fn add_player(&mut self, player: Player) {
         self.players.push(player);
+        self.sequence += 1;
+    }
+
+    // Replaces the player with the same client_id, or adds them if they are new
+    pub fn update_player(&mut self, player: Player) {
+        match self.get_player_mut_by_id(player.client_id) {
+            Some(existing) => *existing = player,
+            None => self.add_player(player),
+        }
+    }
+
+    pub fn get_player_mut_by_id(&mut self, id: u64) -> Option<&mut Player> {
+        self.players.iter_mut().find(|player| player.client_id == id)
+    }
+
+    // Shuffles a fresh deck, moves the button, posts the blinds and deals two hole cards to every player
+    pub fn deal_hands(&mut self, deck: Deck, rng: &mut impl Rng) {
+        self.deck = deck;
+        self.deck.shuffle(rng);
+        self.board.clear();
+        self.pot = 0;
+        self.current_bet = 0;
+        self.last_raise = self.settings.big_blind;
+        for player in self.players.iter_mut() {
+            player.hand.clear();
+            player.time_bank = (player.time_bank + self.settings.time_bank_refill_seconds).min(self.settings.time_bank_seconds);
+            // Busted players sit the hand out
+            player.is_folded = player.money <= 0;
+            player.is_all_in = false;
+            player.has_acted = false;
+            player.bet_this_turn = 0;
+            player.total_bet = 0;
+        }
+        for _ in 0..2 {
+            for player in self.players.iter_mut().filter(|player| !player.is_folded) {
+                if let Some(card) = self.deck.draw() {
+                    player.hand.push(card.to_bytes_card());
+                }
+            }
+        }
+        self.stage = Stage::PreFlop;
+        self.post_blinds();
+        self.sequence += 1;
+    }
+
+    // Next seat after `seat` that was dealt into the hand
+    fn seat_in_hand_after(&self, seat: u8) -> u8 {
+        let count = self.players.len();
+        (1..=count)
+            .map(|offset| (seat as usize + offset) % count)
+            .find(|index| !self.players[*index].is_folded)
+            .unwrap_or(0) as u8
+    }
+
+    // Heads up the button posts the small blind and acts first before the flop
+    fn post_blinds(&mut self) {
+        if self.contenders().count() < 2 {
+            self.first_to_act();
+            return;
+        }
+        self.button = self.seat_in_hand_after(self.button);
+        let small_blind = if self.contenders().count() == 2 { self.button } else { self.seat_in_hand_after(self.button) };
+        let big_blind = self.seat_in_hand_after(small_blind);
+        for (seat, blind) in [(small_blind, self.settings.small_blind), (big_blind, self.settings.big_blind)] {
+            let player = &mut self.players[seat as usize];
+            let amount = blind.min(player.money);
+            player.money -= amount;
+            player.bet_this_turn = amount;
+            player.total_bet = amount;
+            player.is_all_in = player.money == 0;
+            self.pot += amount;
+            self.current_bet = self.current_bet.max(amount);
+        }
+        // The big blind still gets their option, posting doesn't count as acting
+        self.turn = big_blind;
+        self.advance_turn();
+    }
+
+    // Players still in the hand, whether or not they can still bet
+    pub fn contenders(&self) -> impl Iterator<Item = &Player> {
+        self.players.iter().filter(|player| !player.is_folded)
+    }
+
+    // What `recipient` is allowed to see of `player`: your own cards always, everyone else's only when shown down
+    pub fn player_view_for(&self, player: &Player, recipient: u64) -> Player {
+        let is_shown = self.stage == Stage::Showdown && !player.is_folded && self.contenders().count() > 1;
+        if player.client_id == recipient || is_shown {
+            player.clone()
+        } else {
+            player.public_view()
+        }
+    }
+
+    pub fn players_view_for(&self, recipient: u64) -> Vec<Player> {
+        self.players.iter().map(|player| self.player_view_for(player, recipient)).collect()
+    }
+
+    pub fn snapshot_for(&self, recipient: u64) -> TableSnapshot {
+        TableSnapshot {
+            id: self.id,
+            name: self.name.clone(),
+            owner: self.owner,
+            players: self.players_view_for(recipient),
+            turn: self.turn,
+            sequence: self.sequence,
+            pot: self.pot,
+            current_bet: self.current_bet,
+            last_raise: self.last_raise,
+            stage: self.stage,
+            board: self.board.clone(),
+            button: self.button,
+            settings: self.settings.clone(),
+        }
+    }
+
+    // Replaces everything the server knows about with the snapshot, the local deck is kept
+    pub fn apply_snapshot(&mut self, snapshot: TableSnapshot) {
+        self.id = snapshot.id;
+        self.name = snapshot.name;
+        self.owner = snapshot.owner;
+        self.players = snapshot.players;
+        self.turn = snapshot.turn;
+        self.sequence = snapshot.sequence;
+        self.pot = snapshot.pot;
+        self.current_bet = snapshot.current_bet;
+        self.last_raise = snapshot.last_raise;
+        self.stage = snapshot.stage;
+        self.board = snapshot.board;
+        self.button = snapshot.button;
+        self.settings = snapshot.settings;
     }
     
     pub fn remove_player_by_id(&mut self, id: u64) {
+        // Keep the turn pointing at the same player when someone before them leaves
+        if let Some(index) = self.players.iter().position(|player| player.client_id == id) {
+            if index < self.turn as usize {
+                self.turn -= 1;
+            }
+            if index < self.button as usize {
+                self.button -= 1;
+            }
+            self.sequence += 1;
+        }
         self.players.retain(|player| player.client_id != id);
+        if self.turn as usize >= self.players.len() {
+            self.turn = 0;
+        }
+        // If it was their turn, it moves on to the next player still in the hand
+        if self.is_betting() && self.players.get(self.turn as usize).is_some_and(|player| !Lobby::can_act(player)) {
+            self.advance_turn();
+        }
     }
 
     pub fn play_turn(&mut self, action: Action) -> ActionResult {
-        let player = self.players.get_mut(self.turn as usize).unwrap();
+        if !self.is_betting() {
+            return ActionResult::Error("No hand is being played".to_string(), ActionErrorCode::NotYourTurn);
+        }
+        let min_raise = self.current_player_id().map_or(0, |client_id| self.min_raise(client_id));
+        let Some(player) = self.players.get_mut(self.turn as usize) else {
+            return ActionResult::Error("Nobody is seated".to_string(), ActionErrorCode::NotYourTurn);
+        };
         match action {
             Action::Check => {
                 if player.bet_this_turn < self.current_bet {
@@ -48,46 +240,484 @@ impl Lobby {
                 }
             }
             Action::Call => {
-                if player.money < self.current_bet - player.bet_this_turn {
+                let to_call = self.current_bet - player.bet_this_turn;
+                if player.money < to_call {
                     return ActionResult::Error("You don't have enough money to call".to_string(), ActionErrorCode::NotEnoughMoney);
                 }
-                player.money -= self.current_bet - player.bet_this_turn;
-                player.bet_this_turn += self.current_bet;
+                player.money -= to_call;
+                player.bet_this_turn += to_call;
+                player.total_bet += to_call;
+                self.pot += to_call;
             }
+            // The amount is what goes in on top of what the player already bet this round
             Action::Raise(amount) => {
                 if player.money < amount {
                     return ActionResult::Error("You don't have enough money to raise".to_string(), ActionErrorCode::NotEnoughMoney);
                 }
-                if amount + player.bet_this_turn < self.current_bet {
+                if amount <= 0 || amount + player.bet_this_turn < self.current_bet {
                     return ActionResult::Error("You must raise to the current bet".to_string(), ActionErrorCode::MustRaiseToCurrentBet);
                 }
+                // Short of a full raise is only allowed when it's every chip the player has
+                if amount < min_raise && amount < player.money {
+                    return ActionResult::Error(format!("You must raise to at least {}", player.bet_this_turn + min_raise), ActionErrorCode::RaiseTooSmall);
+                }
                 player.bet_this_turn += amount;
+                player.total_bet += amount;
                 player.money -= amount;
-                self.current_bet = amount;
+                self.pot += amount;
             }
             Action::Fold => {
                 player.is_folded = true;
             }
             Action::AllIn => {
-                player.is_all_in = true;
+                player.bet_this_turn += player.money;
+                player.total_bet += player.money;
+                self.pot += player.money;
+                player.money = 0;
             }
         }
-        while self.is_client_turn(self.get_client_id_from_position(self.turn)) {
-            self.turn += 1;
-        }
-        if self.turn >= self.players.len() as u8 {
-            self.turn = 0;
+        player.is_all_in = player.money == 0;
+        player.has_acted = true;
+        // A bet everyone else hasn't matched yet gives them another say
+        if player.bet_this_turn > self.current_bet {
+            self.last_raise = self.last_raise.max(player.bet_this_turn - self.current_bet);
+            self.current_bet = player.bet_this_turn;
+            let turn = self.turn as usize;
+            for (index, other) in self.players.iter_mut().enumerate() {
+                if index != turn {
+                    other.has_acted = false;
+                }
+            }
         }
+        self.advance_turn();
+        self.sequence += 1;
         ActionResult::Success
     }
+
+    // Players that can still put chips in
+    fn can_act(player: &Player) -> bool {
+        !player.is_folded && !player.is_all_in
+    }
+
+    // Everyone still betting has acted and matched the bet, or nobody is left to bet against
+    pub fn is_round_complete(&self) -> bool {
+        let acting: Vec<&Player> = self.players.iter().filter(|player| Lobby::can_act(player)).collect();
+        if acting.len() <= 1 {
+            return acting.iter().all(|player| player.bet_this_turn >= self.current_bet);
+        }
+        acting.iter().all(|player| player.has_acted && player.bet_this_turn == self.current_bet)
+    }
+
+    // Server side, called after every action. Deals the next streets once betting is done and
+    // settles the hand when it's over, returning who won what.
+    pub fn advance_hand(&mut self) -> Option<Vec<Payout>> {
+        if !self.is_betting() {
+            return None;
+        }
+        if self.contenders().count() <= 1 {
+            return Some(self.finish_hand());
+        }
+        while self.is_betting() && self.is_round_complete() {
+            self.next_street();
+        }
+        if self.stage == Stage::Showdown {
+            return Some(self.finish_hand());
+        }
+        None
+    }
+
+    fn next_street(&mut self) {
+        let (stage, cards) = match self.stage {
+            Stage::PreFlop => (Stage::Flop, 3),
+            Stage::Flop => (Stage::Turn, 1),
+            Stage::Turn => (Stage::River, 1),
+            _ => (Stage::Showdown, 0),
+        };
+        for _ in 0..cards {
+            if let Some(card) = self.deck.draw() {
+                self.board.push(card.to_bytes_card());
+            }
+        }
+        self.stage = stage;
+        self.current_bet = 0;
+        self.last_raise = self.settings.big_blind;
+        for player in self.players.iter_mut() {
+            player.bet_this_turn = 0;
+            player.has_acted = false;
+        }
+        self.first_to_act();
+        self.sequence += 1;
+    }
+
+    // Gives the turn to the first seat after the button that can still act
+    fn first_to_act(&mut self) {
+        let count = self.players.len();
+        self.turn = (1..=count)
+            .map(|offset| (self.button as usize + offset) % count)
+            .find(|index| Lobby::can_act(&self.players[*index]))
+            .unwrap_or(0) as u8;
+    }
+
+    // Splits the pot into a main pot and side pots by how much each player put in, and pays each one to
+    // the best hand among the players who paid into it. Ties split evenly with the odd chips going to the first winner.
+    fn finish_hand(&mut self) -> Vec<Payout> {
+        self.stage = Stage::Showdown;
+        let contenders: Vec<(u64, i32, Option<u16>)> = self.contenders()
+            .map(|player| (player.client_id, player.total_bet, hand::evaluate(&player.hand, &self.board)))
+            .collect();
+        let shown_down = contenders.len() > 1;
+        let mut levels: Vec<i32> = contenders.iter().map(|(_, total_bet, _)| *total_bet).collect();
+        levels.sort_unstable();
+        levels.dedup();
+        let mut pots: Vec<Pot> = Vec::new();
+        let mut previous = 0;
+        for level in levels {
+            // Everyone's chips between the last level and this one, folded players' included
+            let amount: i32 = self.players.iter()
+                .map(|player| player.total_bet.min(level) - player.total_bet.min(previous))
+                .sum();
+            let eligible = contenders.iter()
+                .filter(|(_, total_bet, _)| *total_bet >= level)
+                .map(|(client_id, _, rank)| (*client_id, *rank))
+                .collect();
+            pots.push((amount, eligible));
+            previous = level;
+        }
+        // Chips from players who left mid-hand aren't in anyone's total, they go to the last pot
+        let counted: i32 = pots.iter().map(|(amount, _)| amount).sum();
+        if let Some((amount, _)) = pots.last_mut() {
+            *amount += self.pot - counted;
+        }
+        let mut payouts: Vec<Payout> = Vec::new();
+        for (amount, eligible) in pots {
+            let best = eligible.iter().map(|(_, rank)| *rank).max().flatten();
+            let winners: Vec<(u64, Option<u16>)> = eligible.into_iter()
+                .filter(|(_, rank)| !shown_down || *rank == best)
+                .collect();
+            if winners.is_empty() || amount <= 0 {
+                continue;
+            }
+            let share = amount / winners.len() as i32;
+            let mut odd_chips = amount % winners.len() as i32;
+            for (client_id, rank) in winners {
+                let won = share + std::mem::take(&mut odd_chips);
+                if let Some(player) = self.get_player_mut_by_id(client_id) {
+                    player.money += won;
+                }
+                match payouts.iter_mut().find(|payout| payout.client_id == client_id) {
+                    Some(payout) => payout.amount += won,
+                    None => payouts.push(Payout {
+                        client_id,
+                        amount: won,
+                        hand: rank.filter(|_| shown_down).map(|rank| hand::describe(rank).to_string()),
+                    }),
+                }
+            }
+        }
+        self.pot = 0;
+        self.current_bet = 0;
+        self.sequence += 1;
+        payouts
+    }
+
+    // Moves the turn to the next player that can still act
+    fn advance_turn(&mut self) {
+        let count = self.players.len();
+        for _ in 0..count {
+            self.turn = ((self.turn as usize + 1) % count) as u8;
+            if Lobby::can_act(&self.players[self.turn as usize]) {
+                return;
+            }
+        }
+    }
+
+    // What the table list shows about this table
+    pub fn info(&self) -> TableInfo {
+        TableInfo {
+            id: self.id,
+            name: self.name.clone(),
+            owner: self.owner,
+            players: self.players.len() as u32,
+            max_seats: self.settings.max_seats as u32,
+            starting_stack: self.settings.starting_stack,
+            in_progress: self.stage != Stage::Waiting,
+        }
+    }
+
+    pub fn current_player_id(&self) -> Option<u64> {
+        self.players.get(self.turn as usize).map(|player| player.client_id)
+    }
+
+    // True while a hand is being bet on, which is when the turn clock runs
+    pub fn is_betting(&self) -> bool {
+        matches!(self.stage, Stage::PreFlop | Stage::Flop | Stage::Turn | Stage::River)
+    }
+
+    // What the current player does when their time runs out: check if they can, otherwise fold
+    pub fn timeout_action(&self) -> Action {
+        match self.players.get(self.turn as usize) {
+            Some(player) if player.bet_this_turn >= self.current_bet => Action::Check,
+            _ => Action::Fold,
+        }
+    }
     pub fn is_client_turn(&self, client_id: u64) -> bool {
         self.players.iter().any(|player| player.client_id == client_id && !player.is_folded)
     }
-    pub fn get_client_id_from_position(&self, position: u8) -> u64 {
-        self.players.iter().find(|player| player.position == position).unwrap().client_id
+
+    // Chips the player has to put in to stay in the hand
+    pub fn to_call(&self, client_id: u64) -> i32 {
+        self.players.iter()
+            .find(|player| player.client_id == client_id)
+            .map(|player| (self.current_bet - player.bet_this_turn).max(0))
+            .unwrap_or(0)
+    }
+
+    // Smallest raise allowed, in chips on top of what the player already bet: the call plus the last raise, and never less than a big blind
+    pub fn min_raise(&self, client_id: u64) -> i32 {
+        self.to_call(client_id) + self.last_raise.max(self.settings.big_blind).max(1)
+    }
+
+    // What the player can do right now, nothing unless it is their turn
+    pub fn legal_actions(&self, client_id: u64) -> Vec<Action> {
+        let Some(player) = self.players.get(self.turn as usize).filter(|player| player.client_id == client_id) else {
+            return Vec::new();
+        };
+        if !self.is_betting() || !Lobby::can_act(player) {
+            return Vec::new();
+        }
+        let to_call = self.to_call(client_id);
+        let min_raise = self.min_raise(client_id);
+        let mut actions = vec![Action::Fold];
+        if to_call == 0 {
+            actions.push(Action::Check);
+        } else if player.money > to_call {
+            actions.push(Action::Call);
+        }
+        if player.money > min_raise {
+            actions.push(Action::Raise(min_raise));
+        }
+        actions.push(Action::AllIn);
+        actions
+    }
+}
+
+// Where the current hand is at, hole cards are only revealed to everyone at showdown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stage {
+    #[default]
+    Waiting,
+    PreFlop,
+    Flop,
+    Turn,
+    River,
+    Showdown,
+}
+
+// The whole table as one recipient is allowed to see it, used to catch up late joiners
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableSnapshot {
+    pub id: TableId,
+    pub name: String,
+    pub owner: Option<u64>,
+    pub players: Vec<Player>,
+    pub turn: u8,
+    pub sequence: u32,
+    pub pot: i32,
+    pub current_bet: i32,
+    pub last_raise: i32,
+    pub stage: Stage,
+    pub board: Vec<BytesCard>,
+    pub button: u8,
+    pub settings: TableSettings,
+}
+
+// One line in the list of tables a server is running
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableInfo {
+    pub id: TableId,
+    pub name: String,
+    pub owner: Option<u64>,
+    pub players: u32,
+    pub max_seats: u32,
+    pub starting_stack: i32,
+    pub in_progress: bool,
+}
+
+// The tables on the server we are connected to, as of the last TableList
+#[derive(Debug, Clone, Default, Resource)]
+pub struct TableDirectory {
+    pub tables: Vec<TableInfo>,
+}
+
+// The game played at a table, only one so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Variant {
+    #[default]
+    NoLimitHoldem,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 1] = [Variant::NoLimitHoldem];
+
+    // The one after this in ALL, the lobby screen clicks through them
+    pub fn next(self) -> Variant {
+        let index = Variant::ALL.iter().position(|variant| *variant == self).unwrap_or(0);
+        Variant::ALL[(index + 1) % Variant::ALL.len()]
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variant::NoLimitHoldem => write!(f, "No Limit Hold'em"),
+        }
+    }
+}
+
+// What a player opening a table can pick from, the server clamps anything outside it
+pub const MIN_SEATS: usize = 2;
+pub const MAX_SEATS: usize = 9;
+// The biggest big blind still leaves everyone this many to start with
+const MIN_STARTING_BIG_BLINDS: i32 = 10;
+
+// Rules for a table, chosen by the host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableSettings {
+    pub variant: Variant,
+    pub small_blind: i32,
+    pub big_blind: i32,
+    // Seconds a player gets for each action before the time bank kicks in
+    pub action_seconds: f32,
+    // Most a time bank can hold, and what it starts at
+    pub time_bank_seconds: f32,
+    // Added back to every time bank at the start of each hand
+    pub time_bank_refill_seconds: f32,
+    pub max_seats: usize,
+    // Chips every player sits down with
+    pub starting_stack: i32,
+    // How far behind the live table spectators are shown, so they can't relay it to a player
+    pub spectator_delay_seconds: f32,
+}
+
+impl Default for TableSettings {
+    fn default() -> Self {
+        TableSettings {
+            variant: Variant::NoLimitHoldem,
+            small_blind: 25,
+            big_blind: 50,
+            action_seconds: 30.0,
+            time_bank_seconds: 60.0,
+            time_bank_refill_seconds: 10.0,
+            max_seats: MAX_SEATS,
+            starting_stack: 5000,
+            spectator_delay_seconds: 0.0,
+        }
+    }
+}
+
+impl TableSettings {
+    // The variant, blinds and seats a player asked for, kept to what can be played. The rest stays at the server's defaults.
+    pub fn for_new_table(requested: &TableSettings) -> TableSettings {
+        let defaults = TableSettings::default();
+        let big_blind = requested.big_blind.clamp(2, defaults.starting_stack / MIN_STARTING_BIG_BLINDS);
+        TableSettings {
+            variant: requested.variant,
+            small_blind: requested.small_blind.clamp(1, big_blind),
+            big_blind,
+            max_seats: requested.max_seats.clamp(MIN_SEATS, MAX_SEATS),
+            ..defaults
+        }
+    }
+}
+
+// Countdown for the player whose turn it is. The server runs the real clock, clients mirror it to draw it.
+#[derive(Debug, Clone, Copy, Default, Resource, Serialize, Deserialize)]
+pub struct TurnClock {
+    pub client_id: u64,
+    pub remaining: f32,
+    pub using_time_bank: bool,
+}
+
+// A ping or loss past these and the player shows up as lagging
+pub const LAG_PING_MS: u32 = 300;
+pub const LAG_PACKET_LOSS: f32 = 0.1;
+// Under these the connection counts as good
+const GOOD_PING_MS: u32 = 150;
+const GOOD_PACKET_LOSS: f32 = 0.02;
+
+// How a seated player's connection looks from the server, measured by renet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionQuality {
+    pub client_id: u64,
+    // Round trip time
+    pub ping_ms: u32,
+    // Share of recent packets that never arrived, from 0 to 1
+    pub packet_loss: f32,
+}
+
+impl ConnectionQuality {
+    pub fn new(client_id: u64, rtt_seconds: f64, packet_loss: f64) -> Self {
+        ConnectionQuality {
+            client_id,
+            ping_ms: (rtt_seconds * 1000.0).round() as u32,
+            packet_loss: packet_loss as f32,
+        }
+    }
+
+    pub fn is_lagging(&self) -> bool {
+        self.ping_ms >= LAG_PING_MS || self.packet_loss >= LAG_PACKET_LOSS
+    }
+
+    pub fn grade(&self) -> ConnectionGrade {
+        if self.is_lagging() {
+            ConnectionGrade::Poor
+        } else if self.ping_ms < GOOD_PING_MS && self.packet_loss < GOOD_PACKET_LOSS {
+            ConnectionGrade::Good
+        } else {
+            ConnectionGrade::Fair
+        }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionGrade {
+    Good,
+    Fair,
+    Poor,
+}
+
+impl fmt::Display for ConnectionGrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ConnectionGrade::Good => "good",
+            ConnectionGrade::Fair => "fair",
+            ConnectionGrade::Poor => "poor",
+        };
+        write!(f, "{name}")
+    }
+}
+
+// Latest connection reports for everyone seated at our table, clients keep it to draw the seats
+#[derive(Debug, Clone, Default, Resource)]
+pub struct TableConnections(pub Vec<ConnectionQuality>);
+
+impl TableConnections {
+    pub fn get(&self, client_id: u64) -> Option<&ConnectionQuality> {
+        self.0.iter().find(|quality| quality.client_id == client_id)
+    }
+}
+
+// Chips a player won at the end of a hand, with the hand they won it with if it went to showdown
+#[derive(Debug, Clone)]
+pub struct Payout {
+    pub client_id: u64,
+    pub amount: i32,
+    pub hand: Option<String>,
+}
+
+// Chips in one pot and who can win them, with each player's hand rank
+type Pot = (i32, Vec<(u64, Option<u16>)>);
+
 pub enum ActionResult {
     Success,
     Error(String, ActionErrorCode),
@@ -96,6 +726,8 @@ pub enum ActionErrorCode {
     NotEnoughMoney,
     MustCallCurrentBet,
     MustRaiseToCurrentBet,
+    RaiseTooSmall,
+    NotYourTurn,
 }
 
 // A player is a collection of a name, a hand, money, and a position
@@ -108,10 +740,30 @@ pub struct Player {
     pub is_all_in: bool,
     pub is_folded: bool,
     pub bet_this_turn: i32,
+    // Chips put in over the whole hand, side pots are built from it
+    pub total_bet: i32,
+    // Whether the player has had their say since the last raise
+    pub has_acted: bool,
+    // Set by the server while the player's connection is down and their seat is being held
+    pub is_disconnected: bool,
+    // The player said they are ready for the first hand, the owner decides when to start regardless
+    pub is_ready: bool,
+    // Extra seconds the player can spend once their action clock runs out
+    pub time_bank: f32,
     // ID used to identify the player from server to client
     pub client_id: u64,
 }
 
+impl Player {
+    // The player as seen by opponents, with the hole cards removed
+    pub fn public_view(&self) -> Player {
+        Player {
+            hand: Vec::new(),
+            ..self.clone()
+        }
+    }
+}
+
 impl Default for Player {
     fn default() -> Self {
         Player {
@@ -122,6 +774,11 @@ impl Default for Player {
             is_all_in: false,
             is_folded: false,
             bet_this_turn: 0,
+            total_bet: 0,
+            has_acted: false,
+            is_disconnected: false,
+            is_ready: false,
+            time_bank: 0.0,
             client_id: 0,
         }
     }
@@ -137,9 +794,11 @@ pub enum Action {
     AllIn,
 }
 
-impl From<Bytes> for Action {
-    fn from(value: Bytes) -> Self {
-        bincode::deserialize::<Action>(&value).unwrap()
+impl TryFrom<Bytes> for Action {
+    type Error = DecodeError;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        decode(&value)
     }
 }
 impl Into<Bytes> for Action {
@@ -147,9 +806,11 @@ impl Into<Bytes> for Action {
         Bytes::copy_from_slice(&bincode::serialize(&self).unwrap())
     }
 }
-impl From<Bytes> for Player {
-    fn from(value: Bytes) -> Self {
-        bincode::deserialize::<Player>(&value).unwrap()
+impl TryFrom<Bytes> for Player {
+    type Error = DecodeError;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        decode(&value)
     }
 }
 impl Into<Bytes> for Player {
@@ -162,4 +823,247 @@ impl Default for Lobby {
     fn default() -> Self {
         Lobby::new()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // Seats a player per stack, client ids counting up from 1, and deals the first hand from a deck shuffled the same way every run
+    fn table(stacks: &[i32]) -> Lobby {
+        let mut lobby = Lobby::new();
+        for (index, money) in stacks.iter().enumerate() {
+            lobby.add_player(Player {
+                name: format!("Player {}", index + 1),
+                client_id: index as u64 + 1,
+                money: *money,
+                ..Default::default()
+            });
+        }
+        lobby.deal_hands(Deck::new(vec![Handle::default(); 52]), &mut StdRng::seed_from_u64(7));
+        lobby
+    }
+
+    fn play(lobby: &mut Lobby, action: Action) {
+        if let ActionResult::Error(error, _) = lobby.play_turn(action) {
+            panic!("{action:?} was refused: {error}");
+        }
+    }
+
+    fn player(lobby: &Lobby, client_id: u64) -> &Player {
+        lobby.players.iter().find(|player| player.client_id == client_id).unwrap()
+    }
+
+    fn chips(lobby: &Lobby) -> i32 {
+        lobby.players.iter().map(|player| player.money).sum::<i32>() + lobby.pot
+    }
+
+    #[test]
+    fn checking_into_a_bet_is_refused() {
+        let mut lobby = table(&[5000, 5000]);
+        // The small blind still owes 25
+        assert!(matches!(lobby.play_turn(Action::Check), ActionResult::Error(_, ActionErrorCode::MustCallCurrentBet)));
+        assert_eq!(lobby.current_player_id(), Some(2));
+    }
+
+    #[test]
+    fn calls_and_raises_move_chips_into_the_pot() {
+        let mut lobby = table(&[5000, 5000]);
+        play(&mut lobby, Action::Call);
+        assert_eq!(player(&lobby, 2).money, 4950);
+        assert_eq!(lobby.pot, 100);
+        play(&mut lobby, Action::Raise(100));
+        assert_eq!(lobby.current_bet, 150);
+        assert_eq!(lobby.pot, 200);
+        assert!(!lobby.is_round_complete());
+        play(&mut lobby, Action::Fold);
+        assert!(player(&lobby, 2).is_folded);
+        assert_eq!(chips(&lobby), 10000);
+    }
+
+    #[test]
+    fn streets_are_dealt_once_everyone_has_acted() {
+        let mut lobby = table(&[5000, 5000]);
+        play(&mut lobby, Action::Call);
+        // The big blind still has their option
+        assert!(lobby.advance_hand().is_none());
+        assert_eq!(lobby.stage, Stage::PreFlop);
+        play(&mut lobby, Action::Check);
+        assert!(lobby.advance_hand().is_none());
+        assert_eq!(lobby.stage, Stage::Flop);
+        assert_eq!(lobby.board.len(), 3);
+        assert_eq!(lobby.current_bet, 0);
+        // After the flop the seat after the button speaks first
+        assert_eq!(lobby.current_player_id(), Some(1));
+        for (stage, board) in [(Stage::Turn, 4), (Stage::River, 5)] {
+            play(&mut lobby, Action::Check);
+            play(&mut lobby, Action::Check);
+            assert!(lobby.advance_hand().is_none());
+            assert_eq!(lobby.stage, stage);
+            assert_eq!(lobby.board.len(), board);
+        }
+        play(&mut lobby, Action::Check);
+        play(&mut lobby, Action::Check);
+        let payouts = lobby.advance_hand().unwrap();
+        assert_eq!(lobby.stage, Stage::Showdown);
+        assert_eq!(payouts.iter().map(|payout| payout.amount).sum::<i32>(), 100);
+        assert!(payouts.iter().all(|payout| payout.hand.is_some()));
+        assert_eq!(lobby.pot, 0);
+        assert_eq!(chips(&lobby), 10000);
+        assert!(matches!(lobby.play_turn(Action::Check), ActionResult::Error(_, ActionErrorCode::NotYourTurn)));
+    }
+
+    #[test]
+    fn last_player_standing_takes_the_pot_without_showing() {
+        let mut lobby = table(&[5000, 5000, 5000]);
+        play(&mut lobby, Action::Fold);
+        play(&mut lobby, Action::Fold);
+        let payouts = lobby.advance_hand().unwrap();
+        assert_eq!(payouts.len(), 1);
+        assert_eq!(payouts[0].client_id, 1);
+        assert_eq!(payouts[0].amount, 75);
+        assert!(payouts[0].hand.is_none());
+        assert!(lobby.board.is_empty());
+        assert_eq!(player(&lobby, 1).money, 5025);
+    }
+
+    #[test]
+    fn all_in_players_have_the_board_run_out() {
+        let mut lobby = table(&[5000, 300]);
+        play(&mut lobby, Action::AllIn);
+        play(&mut lobby, Action::Call);
+        assert!(lobby.advance_hand().is_some());
+        assert_eq!(lobby.stage, Stage::Showdown);
+        assert_eq!(lobby.board.len(), 5);
+        assert_eq!(chips(&lobby), 5300);
+    }
+
+    #[test]
+    fn calling_with_the_last_chips_is_all_in() {
+        // Heads up the button is seat 2, posts the small blind and acts first
+        let mut lobby = table(&[5000, 300]);
+        play(&mut lobby, Action::Call);
+        play(&mut lobby, Action::Raise(250));
+        assert_eq!(lobby.current_player_id(), Some(2));
+        play(&mut lobby, Action::Call);
+        assert_eq!(player(&lobby, 2).money, 0);
+        assert!(player(&lobby, 2).is_all_in);
+        assert!(lobby.is_round_complete());
+    }
+
+    #[test]
+    fn raises_must_be_as_big_as_the_last_raise() {
+        let mut lobby = table(&[5000, 5000]);
+        // Small blind completes to 50 and raises 200 on top
+        play(&mut lobby, Action::Raise(225));
+        assert_eq!(lobby.min_raise(1), 400);
+        assert!(matches!(lobby.play_turn(Action::Raise(300)), ActionResult::Error(_, ActionErrorCode::RaiseTooSmall)));
+        play(&mut lobby, Action::Raise(400));
+        assert_eq!(lobby.current_bet, 450);
+        assert_eq!(lobby.last_raise, 200);
+    }
+
+    #[test]
+    fn short_all_in_raise_does_not_change_the_minimum() {
+        let mut lobby = table(&[5000, 5000, 300]);
+        // Seat 2 has the button, seat 3 the small blind and seat 1 the big blind
+        assert_eq!(lobby.current_player_id(), Some(2));
+        play(&mut lobby, Action::Raise(250));
+        // 275 more from the small blind is all of it, and only 50 on top of the bet
+        play(&mut lobby, Action::Raise(275));
+        assert!(player(&lobby, 3).is_all_in);
+        assert_eq!(lobby.current_bet, 300);
+        assert_eq!(lobby.last_raise, 200);
+        assert_eq!(lobby.min_raise(1), 250 + 200);
+    }
+
+    fn cards(cards: &[(&str, &str)]) -> Vec<BytesCard> {
+        cards.iter().map(|(rank, suit)| BytesCard { rank: rank.to_string(), suit: suit.to_string() }).collect()
+    }
+
+    // A river everyone checked through, with each player's cards and everything they put in over the hand
+    fn showdown(players: &[(Vec<BytesCard>, i32, bool)]) -> Lobby {
+        let mut lobby = Lobby::new();
+        lobby.stage = Stage::River;
+        lobby.board = cards(&[("02", "hearts"), ("07", "diamonds"), ("09", "clubs"), ("11", "spades"), ("03", "hearts")]);
+        for (index, (hand, total_bet, is_folded)) in players.iter().enumerate() {
+            lobby.pot += total_bet;
+            lobby.add_player(Player {
+                client_id: index as u64 + 1,
+                hand: hand.clone(),
+                money: 0,
+                total_bet: *total_bet,
+                is_folded: *is_folded,
+                ..Default::default()
+            });
+        }
+        lobby
+    }
+
+    fn winnings(payouts: &[Payout], client_id: u64) -> i32 {
+        payouts.iter().filter(|payout| payout.client_id == client_id).map(|payout| payout.amount).sum()
+    }
+
+    #[test]
+    fn all_in_player_only_wins_what_they_covered() {
+        let aces = cards(&[("01", "spades"), ("01", "diamonds")]);
+        let kings = cards(&[("13", "spades"), ("13", "diamonds")]);
+        let queens = cards(&[("12", "spades"), ("12", "diamonds")]);
+        let folded = cards(&[("04", "clubs"), ("05", "clubs")]);
+        let mut lobby = showdown(&[(aces, 100, false), (kings, 500, false), (queens, 500, false), (folded, 200, true)]);
+        let payouts = lobby.finish_hand();
+        // 100 from each of the four, then the rest of what the kings, queens and folded player put in
+        assert_eq!(winnings(&payouts, 1), 400);
+        assert_eq!(winnings(&payouts, 2), 900);
+        assert_eq!(winnings(&payouts, 3), 0);
+        assert_eq!(lobby.players.iter().map(|player| player.money).sum::<i32>(), 1300);
+        assert_eq!(lobby.pot, 0);
+    }
+
+    #[test]
+    fn uncalled_chips_go_back_and_ties_split() {
+        let aces = cards(&[("01", "spades"), ("01", "diamonds")]);
+        let other_aces = cards(&[("01", "hearts"), ("01", "clubs")]);
+        let mut lobby = showdown(&[(aces, 101, false), (other_aces, 500, false)]);
+        let payouts = lobby.finish_hand();
+        // The odd chip of the split goes to the first winner, the 399 nobody matched goes back
+        assert_eq!(winnings(&payouts, 1), 101);
+        assert_eq!(winnings(&payouts, 2), 101 + 399);
+    }
+
+    #[test]
+    fn chips_of_players_who_left_stay_in_the_pot() {
+        let aces = cards(&[("01", "spades"), ("01", "diamonds")]);
+        let kings = cards(&[("13", "spades"), ("13", "diamonds")]);
+        let mut lobby = showdown(&[(aces, 300, false), (kings, 300, false)]);
+        // Someone put 150 in and had their seat freed before the showdown
+        lobby.pot += 150;
+        let payouts = lobby.finish_hand();
+        assert_eq!(winnings(&payouts, 1), 750);
+    }
+
+    #[test]
+    fn new_tables_are_kept_to_playable_settings() {
+        let requested = TableSettings {
+            small_blind: 500,
+            big_blind: -10,
+            max_seats: 40,
+            starting_stack: i32::MAX,
+            action_seconds: 0.0,
+            ..Default::default()
+        };
+        let settings = TableSettings::for_new_table(&requested);
+        assert_eq!((settings.small_blind, settings.big_blind), (2, 2));
+        assert_eq!(settings.max_seats, MAX_SEATS);
+        // Only the variant, blinds and seats are up to the player
+        assert_eq!(settings.starting_stack, TableSettings::default().starting_stack);
+        assert_eq!(settings.action_seconds, TableSettings::default().action_seconds);
+
+        let requested = TableSettings { small_blind: 10, big_blind: 1_000_000, max_seats: 0, ..Default::default() };
+        let settings = TableSettings::for_new_table(&requested);
+        assert_eq!(settings.big_blind * MIN_STARTING_BIG_BLINDS, settings.starting_stack);
+        assert_eq!(settings.small_blind, 10);
+        assert_eq!(settings.max_seats, MIN_SEATS);
+    }
+}
@@ -0,0 +1,307 @@
+use bevy::prelude::*;
+use bevy_renet::{RenetClientPlugin, RenetServerPlugin, RenetReceive, RenetSend};
+use crossbeam_channel::{unbounded, Receiver, Sender, TryRecvError};
+use renet::{ClientId, RenetClient, RenetServer};
+use std::time::Duration;
+use crate::utils::{Conditioner, NetworkConditions};
+
+// In-memory transport, a server and any number of clients pass renet packets through channels instead of sockets.
+// Packets arrive in the order they were sent and never get lost, so a game played over it always plays out the same,
+// unless NetworkConditions are turned on to make it behave like a bad network.
+// The server and its clients can live in the same app or in different ones, even on different threads.
+
+// Each side sends an empty packet after this long with nothing else to send, so the other knows it's still there
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+// Hearing nothing for this long counts as a dropped connection, the same as netcode's default
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+// Which transport create_server and create_client set up, UDP unless something asks for loopback
+#[derive(Resource, Clone, Default)]
+pub enum TransportSetup {
+    #[default]
+    Udp,
+    Loopback(LoopbackHub),
+}
+
+enum ToServer {
+    Connect(ClientId, Sender<Vec<u8>>),
+    Packet(ClientId, Vec<u8>),
+    Disconnect(ClientId),
+}
+
+// What clients use to find the server, cheap to clone and hand out
+#[derive(Clone)]
+pub struct LoopbackHub {
+    to_server: Sender<ToServer>,
+    incoming: Receiver<ToServer>,
+}
+
+impl Default for LoopbackHub {
+    fn default() -> Self {
+        LoopbackHub::new()
+    }
+}
+
+impl LoopbackHub {
+    pub fn new() -> Self {
+        let (to_server, incoming) = unbounded();
+        LoopbackHub { to_server, incoming }
+    }
+
+    // The server's end, there should only be one per hub
+    pub fn server_transport(&self) -> LoopbackServerTransport {
+        LoopbackServerTransport {
+            incoming: self.incoming.clone(),
+            clients: Vec::new(),
+            conditioner: Conditioner::new(),
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+
+    // Connects straight away, the server adds the client the next time it reads its packets
+    pub fn connect(&self, client_id: ClientId) -> LoopbackClientTransport {
+        let (to_client, incoming) = unbounded();
+        // Only fails once the hub is gone, and then the client finds out it's disconnected on its next update
+        let _ = self.to_server.send(ToServer::Connect(client_id, to_client));
+        LoopbackClientTransport {
+            client_id,
+            to_server: self.to_server.clone(),
+            incoming,
+            hung_up: false,
+            conditioner: Conditioner::new(),
+            last_sent: None,
+            last_received: None,
+        }
+    }
+}
+
+struct LoopbackPeer {
+    client_id: ClientId,
+    to_client: Sender<Vec<u8>>,
+    last_sent: Duration,
+    last_received: Duration,
+}
+
+#[derive(Resource)]
+pub struct LoopbackServerTransport {
+    incoming: Receiver<ToServer>,
+    clients: Vec<LoopbackPeer>,
+    conditioner: Conditioner<(ClientId, Vec<u8>)>,
+    // Everything that went through the server since it started, for measuring bandwidth
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl LoopbackServerTransport {
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    pub fn update(&mut self, server: &mut RenetServer, now: Duration) {
+        while let Ok(message) = self.incoming.try_recv() {
+            match message {
+                ToServer::Connect(client_id, to_client) => {
+                    // A reconnect with the same id replaces the old connection
+                    self.drop_client(server, client_id);
+                    server.add_connection(client_id);
+                    self.clients.push(LoopbackPeer {
+                        client_id,
+                        to_client,
+                        last_sent: now,
+                        last_received: now,
+                    });
+                }
+                ToServer::Packet(client_id, packet) => {
+                    // Packets still in flight from a client we already dropped are ignored
+                    let Some(peer) = self.clients.iter_mut().find(|peer| peer.client_id == client_id) else {
+                        continue;
+                    };
+                    peer.last_received = now;
+                    self.bytes_received += packet.len() as u64;
+                    // Empty ones only say the client is still there
+                    if !packet.is_empty() {
+                        let _ = server.process_packet_from(&packet, client_id);
+                    }
+                }
+                ToServer::Disconnect(client_id) => self.drop_client(server, client_id),
+            }
+        }
+        let timed_out: Vec<ClientId> = self.clients.iter()
+            .filter(|peer| now.saturating_sub(peer.last_received) > TIMEOUT)
+            .map(|peer| peer.client_id)
+            .collect();
+        for client_id in timed_out {
+            println!("Client {client_id} timed out");
+            self.drop_client(server, client_id);
+        }
+        // Clients the server kicked, dropping their channel is how they find out
+        for client_id in server.disconnections_id() {
+            self.drop_client(server, client_id);
+        }
+    }
+
+    pub fn send_packets(&mut self, server: &mut RenetServer, now: Duration, conditions: &NetworkConditions) {
+        for peer in self.clients.iter_mut() {
+            let Ok(mut packets) = server.get_packets_to_send(peer.client_id) else {
+                continue;
+            };
+            if packets.is_empty() && now.saturating_sub(peer.last_sent) >= KEEPALIVE_INTERVAL {
+                packets.push(Vec::new());
+            }
+            if !packets.is_empty() {
+                peer.last_sent = now;
+            }
+            for packet in packets {
+                self.conditioner.push(now, (peer.client_id, packet), conditions);
+            }
+        }
+        for (client_id, packet) in self.conditioner.release(now) {
+            // The client may have gone while its packets were on the way
+            if let Some(peer) = self.clients.iter().find(|peer| peer.client_id == client_id) {
+                self.bytes_sent += packet.len() as u64;
+                let _ = peer.to_client.send(packet);
+            }
+        }
+    }
+
+    pub fn disconnect_all(&mut self, server: &mut RenetServer) {
+        server.disconnect_all();
+        let client_ids: Vec<ClientId> = self.clients.iter().map(|peer| peer.client_id).collect();
+        for client_id in client_ids {
+            self.drop_client(server, client_id);
+        }
+    }
+
+    fn drop_client(&mut self, server: &mut RenetServer, client_id: ClientId) {
+        self.clients.retain(|peer| peer.client_id != client_id);
+        server.remove_connection(client_id);
+    }
+}
+
+#[derive(Resource)]
+pub struct LoopbackClientTransport {
+    client_id: ClientId,
+    to_server: Sender<ToServer>,
+    incoming: Receiver<Vec<u8>>,
+    // Set once we told the server we're gone, or found out it's gone
+    hung_up: bool,
+    conditioner: Conditioner<Vec<u8>>,
+    // Unknown until the first update, the hub doesn't know the time
+    last_sent: Option<Duration>,
+    last_received: Option<Duration>,
+}
+
+impl LoopbackClientTransport {
+    pub fn update(&mut self, client: &mut RenetClient, now: Duration) {
+        if client.is_connecting() && !self.hung_up {
+            // Nothing to negotiate in memory
+            client.set_connected();
+        }
+        let last_received = self.last_received.get_or_insert(now);
+        loop {
+            match self.incoming.try_recv() {
+                Ok(packet) => {
+                    *last_received = now;
+                    // Empty ones only say the server is still there
+                    if !packet.is_empty() {
+                        client.process_packet(&packet);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // The server dropped us or shut down
+                    self.hung_up = true;
+                    client.disconnect_due_to_transport();
+                    break;
+                }
+            }
+        }
+        if !self.hung_up && now.saturating_sub(*last_received) > TIMEOUT {
+            println!("The server stopped answering");
+            self.disconnect();
+            client.disconnect_due_to_transport();
+        }
+    }
+
+    pub fn send_packets(&mut self, client: &mut RenetClient, now: Duration, conditions: &NetworkConditions) {
+        if client.is_disconnected() {
+            self.disconnect();
+            return;
+        }
+        let mut packets = client.get_packets_to_send();
+        let last_sent = self.last_sent.get_or_insert(now);
+        if packets.is_empty() && now.saturating_sub(*last_sent) >= KEEPALIVE_INTERVAL {
+            packets.push(Vec::new());
+        }
+        if !packets.is_empty() {
+            *last_sent = now;
+        }
+        for packet in packets {
+            self.conditioner.push(now, packet, conditions);
+        }
+        for packet in self.conditioner.release(now) {
+            let _ = self.to_server.send(ToServer::Packet(self.client_id, packet));
+        }
+    }
+
+    // Sends everything still on the way straight away, then disconnects, for saying goodbye
+    pub fn hang_up(&mut self, client: &mut RenetClient) {
+        if !self.hung_up {
+            let packets = self.conditioner.release_all().into_iter().chain(client.get_packets_to_send());
+            for packet in packets {
+                let _ = self.to_server.send(ToServer::Packet(self.client_id, packet));
+            }
+        }
+        self.disconnect();
+    }
+
+    pub fn disconnect(&mut self) {
+        if !self.hung_up {
+            self.hung_up = true;
+            let _ = self.to_server.send(ToServer::Disconnect(self.client_id));
+        }
+    }
+}
+
+// Runs the loopback transports alongside renet's own, in the same system sets the netcode ones use
+pub struct LoopbackPlugin;
+
+impl Plugin for LoopbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkConditions>()
+        .add_systems(PreUpdate, (
+            server_update_system
+                .run_if(resource_exists::<LoopbackServerTransport>.and(resource_exists::<RenetServer>))
+                .after(RenetServerPlugin::update_system)
+                .before(RenetServerPlugin::emit_server_events_system),
+            client_update_system
+                .run_if(resource_exists::<LoopbackClientTransport>.and(resource_exists::<RenetClient>))
+                .after(RenetClientPlugin::update_system),
+        ).in_set(RenetReceive))
+        .add_systems(PostUpdate, (
+            server_send_system.run_if(resource_exists::<LoopbackServerTransport>.and(resource_exists::<RenetServer>)),
+            client_send_system.run_if(resource_exists::<LoopbackClientTransport>.and(resource_exists::<RenetClient>)),
+        ).in_set(RenetSend));
+    }
+}
+
+fn server_update_system(mut transport: ResMut<LoopbackServerTransport>, mut server: ResMut<RenetServer>, time: Res<Time>) {
+    transport.update(&mut server, time.elapsed());
+}
+
+fn server_send_system(mut transport: ResMut<LoopbackServerTransport>, mut server: ResMut<RenetServer>, time: Res<Time>, conditions: Res<NetworkConditions>) {
+    transport.send_packets(&mut server, time.elapsed(), &conditions);
+}
+
+fn client_update_system(mut transport: ResMut<LoopbackClientTransport>, mut client: ResMut<RenetClient>, time: Res<Time>) {
+    transport.update(&mut client, time.elapsed());
+}
+
+fn client_send_system(mut transport: ResMut<LoopbackClientTransport>, mut client: ResMut<RenetClient>, time: Res<Time>, conditions: Res<NetworkConditions>) {
+    transport.send_packets(&mut client, time.elapsed(), &conditions);
+}
@@ -1,17 +1,91 @@
 use renet::Bytes;
 use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use bincode::Options;
+use std::fmt;
 use crate::utils::lobby::*;
+use crate::utils::BytesCard;
+use crate::utils::chat::ChatLine;
+
+// Nothing we send comes close to this, anything bigger is rejected before decoding
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    TooLarge(usize),
+    Malformed(bincode::Error),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::TooLarge(size) => write!(f, "message of {size} bytes is over the {MAX_MESSAGE_SIZE} byte limit"),
+            DecodeError::Malformed(error) => write!(f, "malformed message: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Same wire format as bincode::serialize, but never reads or allocates past MAX_MESSAGE_SIZE
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DecodeError> {
+    if bytes.len() > MAX_MESSAGE_SIZE {
+        return Err(DecodeError::TooLarge(bytes.len()));
+    }
+    bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE as u64)
+        .deserialize(bytes)
+        .map_err(DecodeError::Malformed)
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ServerMessage {
+    // Public view of a player, hole cards are only filled in for the recipient or at showdown
     Player(Player),
-    Action(Action, u64),
+    // Sent by a client to take a seat, or to reclaim it after a reconnect using the session token
+    Join(Player, u64),
+    // Sent by a client to watch the table instead of playing, with its name and session token
+    Spectate(String, u64),
+    // A spectator asking for the next free seat
+    RequestSeat,
+    // The recipient's own hole cards, only ever sent to that one client
+    DealHand(Vec<BytesCard>),
+    // A move and the table sequence it was made at. The server relays it to the table with the id of the player who made it.
+    Action(Action, u64, u32),
+    // A seated player saying whether they are ready for the first hand
+    Ready(bool),
+    // Sent by the owner of a table to deal the first hand, and by the server to everyone at it
     StartGame,
+    // Full table state, sent on connect and whenever a client asks for it
+    TableSnapshot(TableSnapshot),
+    RequestSnapshot,
+    // Sent whenever a turn starts or moves onto the time bank, clients count down from it
+    TurnClock(TurnClock),
+    // Chat text typed by a client, the server relays it as a Chat line
+    SendChat(String),
+    // A chat line from a player or the dealer
+    Chat(ChatLine),
+    // A client muting or unmuting another player's chat
+    Mute(u64, bool),
+    // Asks for the tables on the server, answered with a TableList
+    ListTables,
+    TableList(Vec<TableInfo>),
+    // Opens a new table with the given name and rules and sits the client down at it, the server clamps the rules
+    CreateTable(String, TableSettings),
+    JoinTable(TableId),
+    // Gets up from the current table, sent back by the server once the client is away from it
+    LeaveTable,
+    // Ping and loss of everyone seated at the table, sent every few seconds
+    ConnectionQuality(Vec<ConnectionQuality>),
 }
 
-impl From<Bytes> for ServerMessage {
-    fn from(value: Bytes) -> Self {
-        bincode::deserialize::<ServerMessage>(&value).unwrap()
+impl TryFrom<Bytes> for ServerMessage {
+    type Error = DecodeError;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        decode(&value)
     }
 }
 
@@ -20,3 +94,44 @@ impl Into<Bytes> for ServerMessage {
         Bytes::copy_from_slice(&bincode::serialize(&self).unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::Handle;
+    use crate::utils::Deck;
+    use crate::utils::server::MAX_TABLES;
+
+    // As long as the server lets a name be, in characters that take four bytes each
+    fn longest(max_length: usize) -> String {
+        "🂡".repeat(max_length)
+    }
+
+    fn round_trip(message: ServerMessage) {
+        let bytes: Bytes = message.into();
+        assert!(bytes.len() <= MAX_MESSAGE_SIZE, "{} bytes is over the limit", bytes.len());
+        assert!(ServerMessage::try_from(bytes).is_ok());
+    }
+
+    #[test]
+    fn a_full_table_of_the_longest_names_still_decodes() {
+        let mut lobby = Lobby::new();
+        lobby.name = longest(MAX_TABLE_NAME_LENGTH);
+        // Tables can be opened with fewer seats, never more
+        for client_id in 0..MAX_SEATS as u64 {
+            lobby.add_player(Player {
+                name: longest(MAX_PLAYER_NAME_LENGTH),
+                client_id,
+                money: lobby.settings.starting_stack,
+                ..Default::default()
+            });
+        }
+        lobby.deal_hands(Deck::new(vec![Handle::default(); 52]), &mut rand::thread_rng());
+        lobby.board = lobby.players.iter().flat_map(|player| player.hand.clone()).take(5).collect();
+        for player in lobby.players.clone() {
+            round_trip(ServerMessage::Player(player));
+        }
+        round_trip(ServerMessage::TableSnapshot(lobby.snapshot_for(0)));
+        round_trip(ServerMessage::TableList(vec![lobby.info(); MAX_TABLES]));
+    }
+}
@@ -1,11 +1,31 @@
 use bevy::prelude::*;
-use crate::state::ServerMode;
+use crate::state::{GameState, ServerMode};
+use bevy_renet::{client_just_connected, client_disconnected};
+use bevy_renet::renet::RenetClient;
 mod deck;
 pub use deck::*;
 
 mod server;
 
 mod client;
+pub use client::{connect_failure_message, ConnectFailed};
+
+mod address;
+pub use address::*;
+
+mod auth;
+pub use auth::run_add_account;
+
+mod handshake;
+pub use handshake::*;
+
+mod hand;
+
+pub mod chat;
+pub use chat::*;
+
+mod discovery;
+pub use discovery::*;
 
 pub mod lobby;
 pub use lobby::*;
@@ -13,17 +33,50 @@ pub use lobby::*;
 mod message;
 pub use message::*;
 
+mod config;
+pub use config::*;
+
+mod conditioner;
+pub use conditioner::*;
+
+mod loopback;
+pub use loopback::*;
+
+mod udp;
+pub use udp::*;
+
+mod simulation;
+pub use simulation::run_simulation;
+
 pub struct ServerPlugin;
 
 impl Plugin for ServerPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<TurnClock>()
+            .init_resource::<TableConnections>()
+            .init_resource::<ChatLog>()
+            .init_resource::<TableDirectory>()
+            .init_resource::<TransportSetup>()
+            .add_event::<ChatCommand>()
+            .add_event::<ConnectFailed>();
+
         // Server systems
         app.add_systems(OnEnter(ServerMode::Host), server::create_server)
-        .add_systems(Update, (server::send_message_system, server::receive_message_system, server::handle_events_system).run_if(in_state(ServerMode::Host)));
+        // Our own client says goodbye before the server goes away
+        .add_systems(OnExit(ServerMode::Host), server::destroy_server.after(client::destroy_client))
+        .add_systems(Update, (server::handshake_system, server::receive_message_system, server::handle_events_system, server::reconnect_timeout_system, server::turn_timer_system, server::promote_spectators_system, server::flush_spectator_feed_system, server::next_hand_system, server::beacon_system, server::status_system, server::close_empty_tables_system, server::connection_quality_system).run_if(in_state(ServerMode::Host)));
 
 
-        // Client systems
-        app.add_systems(OnEnter(ServerMode::Join), client::create_client)
-        .add_systems(Update, (client::send_message_system, client::receive_message_system).run_if(in_state(ServerMode::Join)));
+        // Client systems, the host runs them too and plays through its own server like everyone else.
+        // The client only exists from the connecting screen on, the join screen is just picking where to go.
+        app.add_systems(OnEnter(GameState::Connecting), client::create_client.run_if(not(in_state(ServerMode::None))))
+        .add_systems(OnEnter(GameState::JoinServer), client::drop_connection.run_if(in_state(ServerMode::Join)))
+        .add_systems(OnExit(ServerMode::Join), (client::destroy_client, client::clear_client_view).chain())
+        .add_systems(OnExit(ServerMode::Host), (client::destroy_client, client::clear_client_view).chain())
+        .add_systems(Update, (client::send_message_system, client::receive_handshake_system, client::receive_message_system, client::tick_turn_clock_system, client::send_chat_system).run_if(not(in_state(ServerMode::None)).and(resource_exists::<RenetClient>)))
+        .add_systems(Update, client::send_handshake_system.run_if(not(in_state(ServerMode::None)).and(client_just_connected)))
+        .add_systems(Update, client::finish_connect_system.run_if(not(in_state(ServerMode::None)).and(resource_exists::<client::PendingConnect>)))
+        // A first connection that fails is the connecting screen's to report, only a dropped one is retried, and not while a lookup or login is still running
+        .add_systems(Update, client::reconnect_system.run_if(not(in_state(ServerMode::None)).and(resource_exists::<RenetClient>).and(client_disconnected).and(not(in_state(GameState::Connecting))).and(not(resource_exists::<client::PendingConnect>))));
     }
 }
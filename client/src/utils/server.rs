@@ -1,82 +1,925 @@
 use bevy::prelude::*;
 use bevy_renet::*;
 use renet::*;
-use local_ip_address::local_ip;
-use public_ip::*;
-use std::default;
 use std::net::{UdpSocket, SocketAddr};
 use renet_netcode::*;
-use std::time::SystemTime;
+use std::time::{SystemTime, Duration, Instant};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use crate::asset_loader::GameAssets;
 use crate::utils::client::PROTOCOL_ID;
 use crate::utils::message::ServerMessage;
+use crate::utils::auth::{self, AuthConfig, AuthServer, HostLogin};
+use crate::utils::client;
+use crate::utils::handshake::{Handshake, HandshakeResponse, FEATURE_SECURE_LOGIN, FEATURE_SPECTATING, HANDSHAKE_CHANNEL};
 use crate::utils::*;
 
+// How long a dropped player's seat and chips are held before their hand is folded and the seat freed
+const RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+// How long a rejected client gets to read why before it is kicked
+const REJECTED_LINGER: Duration = Duration::from_secs(2);
+// How long a client gets to send its handshake before it is kicked, so nobody can hold a connection slot without one
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Well formed messages a client had no business sending before it gets kicked, garbage gets kicked right away
+const MAX_STRIKES: u32 = 3;
+// How long the result of a hand stays on the table before the next one is dealt
+const HAND_END_PAUSE: Duration = Duration::from_secs(5);
+// How often each table hears how everyone's connection is doing
+const CONNECTION_REPORT_INTERVAL: Duration = Duration::from_secs(2);
+// Tables the server runs at once, the main table included
+pub const MAX_TABLES: usize = 16;
+
+// Every table the server is running, each one plays its own hands
+#[derive(Resource)]
+pub struct Tables {
+    tables: BTreeMap<TableId, Lobby>,
+    next_id: TableId,
+}
+
+impl Tables {
+    fn new(main: Lobby) -> Self {
+        Tables {
+            tables: BTreeMap::from([(MAIN_TABLE, main)]),
+            next_id: MAIN_TABLE + 1,
+        }
+    }
+
+    pub fn main(&self) -> &Lobby {
+        &self.tables[&MAIN_TABLE]
+    }
+
+    // Table the client has a seat at, including one held for them while they are away
+    fn seat_of(&self, client_id: u64) -> Option<TableId> {
+        self.tables.values()
+            .find(|lobby| lobby.players.iter().any(|player| player.client_id == client_id))
+            .map(|lobby| lobby.id)
+    }
+
+    fn create(&mut self, name: String, settings: TableSettings, owner: u64, deck: Deck) -> TableId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut lobby = Lobby::new();
+        lobby.id = id;
+        lobby.name = name;
+        lobby.settings = settings;
+        lobby.owner = Some(owner);
+        lobby.add_deck(deck);
+        self.tables.insert(id, lobby);
+        id
+    }
+
+    fn infos(&self) -> Vec<TableInfo> {
+        self.tables.values().map(Lobby::info).collect()
+    }
+}
+
+// Server side record of who owns which seat, and who is currently away from it
+#[derive(Resource, Default)]
+pub struct Sessions {
+    tokens: HashMap<u64, u64>,
+    disconnected: HashMap<u64, Duration>,
+    // Account names of clients that logged in through the login service
+    accounts: HashMap<u64, String>,
+    // Name each client goes by at the tables, its account name if it logged in
+    names: HashMap<u64, String>,
+    // Clients whose handshake was accepted and the features we agreed on, nothing else is sent to or read from anyone else
+    verified: HashMap<u64, u32>,
+    // Clients that haven't sent a handshake yet, and when they connected
+    awaiting_handshake: HashMap<u64, Duration>,
+    rejected: HashMap<u64, Duration>,
+    strikes: HashMap<u64, u32>,
+    // Table each client is at, seated or watching
+    table_of: HashMap<u64, TableId>,
+    // Spectators, and the ones waiting for a seat at their table in the order they asked
+    spectators: HashSet<u64>,
+    seat_queue: VecDeque<u64>,
+    // Everything sent to spectators waits here, so the table can be shown to them on a delay
+    spectator_feed: VecDeque<(Instant, u64, ServerMessage)>,
+    // Who each client muted, their chat isn't relayed to them
+    muted: HashMap<u64, HashSet<u64>>,
+    // When each client last chatted, for rate limiting
+    chat_times: HashMap<u64, VecDeque<Instant>>,
+}
+
+impl Sessions {
+    // Ties the session token to the client the first time, and checks it every time after
+    fn claim(&mut self, client_id: u64, session_token: u64) -> bool {
+        if self.tokens.get(&client_id).is_some_and(|token| *token != session_token) {
+            return false;
+        }
+        self.tokens.insert(client_id, session_token);
+        self.disconnected.remove(&client_id);
+        true
+    }
+
+    // Connected clients at a table, seated or watching
+    fn at_table(&self, table_id: TableId) -> Vec<u64> {
+        self.verified.keys()
+            .filter(|client_id| self.table_of.get(client_id) == Some(&table_id))
+            .copied()
+            .collect()
+    }
+
+    fn has_feature(&self, client_id: u64, feature: u32) -> bool {
+        self.verified.get(&client_id).is_some_and(|features| features & feature != 0)
+    }
+
+    // Records a chat message unless the client already used up its messages for this window
+    fn allow_chat(&mut self, client_id: u64) -> bool {
+        let times = self.chat_times.entry(client_id).or_default();
+        while times.front().is_some_and(|sent_at| sent_at.elapsed() > CHAT_RATE_WINDOW) {
+            times.pop_front();
+        }
+        if times.len() >= CHAT_RATE_LIMIT {
+            return false;
+        }
+        times.push_back(Instant::now());
+        true
+    }
+}
+
+// The server's copy of each table's turn clock, the one that actually runs out.
+// Each one is for the table sequence it started at, so a player acting twice in a row gets a fresh clock.
+#[derive(Resource, Default)]
+pub struct TurnTimer {
+    clocks: HashMap<TableId, (u32, TurnClock)>,
+}
+
+// Sends a game message to one client, spectators get theirs through the delayed feed
+pub fn send_to(server: &mut RenetServer, sessions: &mut Sessions, client_id: u64, message: ServerMessage) {
+    if sessions.spectators.contains(&client_id) {
+        sessions.spectator_feed.push_back((Instant::now(), client_id, message));
+    } else {
+        server.send_message(client_id, DefaultChannel::ReliableOrdered, Into::<Bytes>::into(message));
+    }
+}
+
+// Sends a game message to everyone at a table
+pub fn broadcast(server: &mut RenetServer, sessions: &mut Sessions, table_id: TableId, message: ServerMessage) {
+    for client_id in sessions.at_table(table_id) {
+        send_to(server, sessions, client_id, message.clone());
+    }
+}
+
+// Relays a chat line to everyone at the table who hasn't muted the sender
+pub fn relay_chat(server: &mut RenetServer, sessions: &mut Sessions, table_id: TableId, line: ChatLine) {
+    let client_ids: Vec<u64> = sessions.at_table(table_id).into_iter()
+        .filter(|client_id| !line.sender.is_some_and(|sender| sessions.muted.get(client_id).is_some_and(|muted| muted.contains(&sender))))
+        .collect();
+    for client_id in client_ids {
+        send_to(server, sessions, client_id, ServerMessage::Chat(line.clone()));
+    }
+}
+
+pub fn dealer_message(server: &mut RenetServer, sessions: &mut Sessions, table_id: TableId, text: String) {
+    relay_chat(server, sessions, table_id, ChatLine::dealer(text));
+}
 
 pub fn create_server(
     mut commands: Commands,
-    game_assets: Res<GameAssets>,
+    mut game_assets: ResMut<GameAssets>,
+    transport_setup: Res<TransportSetup>,
 ) {
-    println!("Creating server at address: {}", game_assets.server_address);
-    let server = RenetServer::new(ConnectionConfig::default());
-    commands.insert_resource(server);
-    let server_address: SocketAddr = game_assets.server_address;
+    // The host plays as a client of its own server, it needs its id now to be given the main table
+    client::ensure_identity(&mut game_assets);
+    game_assets.spectate = false;
+    commands.insert_resource(RenetServer::new(ConnectionConfig::default()));
+    match &*transport_setup {
+        TransportSetup::Udp => start_udp_transport(&mut commands, game_assets.server_address),
+        TransportSetup::Loopback(hub) => {
+            // Nobody outside this process can reach it, so there's nothing to advertise either
+            println!("Creating an in-memory server");
+            commands.insert_resource(hub.server_transport());
+        }
+    }
+    let mut main = Lobby::new();
+    main.name = format!("{}'s table", game_assets.player_name);
+    main.owner = Some(game_assets.client_id);
+    main.add_deck(game_assets.deck.clone());
+    commands.insert_resource(Tables::new(main));
+    commands.insert_resource(Sessions::default());
+    commands.insert_resource(TurnTimer::default());
+    commands.init_resource::<ShuffleRng>();
+}
+
+// Opens the game port, along with the login service, LAN beacon and status answers that go with it
+fn start_udp_transport(commands: &mut Commands, server_address: SocketAddr) {
+    println!("Creating server at address: {}", server_address);
     let socket = UdpSocket::bind(server_address).unwrap();
+    // With an auth config, only players holding a connect token from our login service can join
+    let authentication = match AuthConfig::load().and_then(|config| config.private_key().map(|key| (config, key))) {
+        Some((config, private_key)) => {
+            if let Some(auth_server) = AuthServer::start(config, private_key, server_address) {
+                commands.insert_resource(auth_server);
+            }
+            // The host doesn't have an account, it signs its own connect token instead
+            commands.insert_resource(HostLogin { private_key });
+            ServerAuthentication::Secure { private_key }
+        }
+        None => ServerAuthentication::Unsecure,
+    };
     let server_config = ServerConfig {
         current_time: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap(),
         max_clients: 64,
         protocol_id: PROTOCOL_ID,
         public_addresses: vec![server_address],
-        authentication: ServerAuthentication::Unsecure
+        authentication,
     };
-    let transport = NetcodeServerTransport::new(server_config, socket).unwrap();
+    let transport = UdpServerTransport::new(server_config, socket).unwrap();
     commands.insert_resource(transport);
+    match BeaconSender::new() {
+        Some(beacon_sender) => commands.insert_resource(beacon_sender),
+        None => println!("Could not start the LAN beacon, players will have to type the address"),
+    }
+    match StatusResponder::new(server_address) {
+        Some(status_responder) => commands.insert_resource(status_responder),
+        None => println!("Could not listen for status queries, saved servers will show this one as offline"),
+    }
+}
+
+// What the beacon and status answers say about the server, which is the main table
+fn main_table_beacon(tables: &Tables, game_assets: &GameAssets) -> Beacon {
+    let main = tables.main();
+    Beacon {
+        table_name: main.name.clone(),
+        game_port: game_assets.server_address.port(),
+        players: main.players.len() as u32,
+        max_seats: main.settings.max_seats as u32,
+        starting_stack: main.settings.starting_stack,
+        protocol_version: PROTOCOL_VERSION,
+        small_blind: main.settings.small_blind,
+        big_blind: main.settings.big_blind,
+    }
+}
+
+// Lets players on the local network find the server from the join screen, it advertises the main table
+pub fn beacon_system(beacon_sender: Option<ResMut<BeaconSender>>, tables: Res<Tables>, game_assets: Res<GameAssets>, time: Res<Time>) {
+    let Some(mut beacon_sender) = beacon_sender else {
+        return;
+    };
+    beacon_sender.tick(time.delta(), || main_table_beacon(&tables, &game_assets));
+}
+
+// Answers saved-server pings from join screens
+pub fn status_system(status_responder: Option<ResMut<StatusResponder>>, tables: Res<Tables>, game_assets: Res<GameAssets>) {
+    if let Some(mut status_responder) = status_responder {
+        status_responder.answer(|| main_table_beacon(&tables, &game_assets));
+    }
 }
 
-pub fn send_message_system(mut server: ResMut<RenetServer>, mut lobby: ResMut<Lobby>, mut events: EventReader<Action>, game_assets: Res<GameAssets>) {
-    if lobby.is_client_turn(game_assets.client_id) {
-        for action in events.read() {
-            server.broadcast_message(
-                DefaultChannel::ReliableOrdered, 
-                Into::<Bytes>::into(ServerMessage::Action(*action, game_assets.client_id))
-            );
+// Kicks everyone and shuts the server down when the host leaves, the host's own client is hung up by destroy_client.
+// The login service goes too, so hosting again can start its own on whatever port it uses.
+pub fn destroy_server(
+    mut commands: Commands,
+    server: Option<ResMut<RenetServer>>,
+    transport: Option<ResMut<UdpServerTransport>>,
+    loopback: Option<ResMut<LoopbackServerTransport>>,
+    auth_server: Option<ResMut<AuthServer>>,
+) {
+    if let Some(mut auth_server) = auth_server {
+        auth_server.stop();
+    }
+    if let Some(mut server) = server {
+        if let Some(mut transport) = transport {
+            transport.disconnect_all(&mut server);
         }
+        if let Some(mut loopback) = loopback {
+            loopback.disconnect_all(&mut server);
+        }
+    }
+    println!("Shutting the server down");
+    commands.remove_resource::<RenetServer>();
+    commands.remove_resource::<UdpServerTransport>();
+    commands.remove_resource::<LoopbackServerTransport>();
+    commands.remove_resource::<Tables>();
+    commands.remove_resource::<Sessions>();
+    commands.remove_resource::<TurnTimer>();
+    commands.remove_resource::<BeaconSender>();
+    commands.remove_resource::<StatusResponder>();
+    commands.remove_resource::<HostLogin>();
+    commands.remove_resource::<AuthServer>();
+}
+
+// Deals a new hand, each client only ever gets its own hole cards
+fn start_hand(server: &mut RenetServer, lobby: &mut Lobby, sessions: &mut Sessions, deck: Deck, rng: &mut ShuffleRng) {
+    lobby.deal_hands(deck, &mut rng.0);
+    dealer_message(server, sessions, lobby.id, "Dealing a new hand".to_string());
+    for player in lobby.players.iter() {
+        send_to(server, sessions, player.client_id, ServerMessage::DealHand(player.hand.clone()));
     }
+    send_snapshots(server, lobby, sessions);
+    // The blinds can put everyone all in before anybody gets to act
+    settle(server, lobby, sessions);
 }
 
-pub fn receive_message_system(mut server: ResMut<RenetServer>, mut lobby: ResMut<Lobby>) {
+// Deals the next hand at each table a little while after its last one was settled, as long as two players have chips
+pub fn next_hand_system(
+    mut server: ResMut<RenetServer>,
+    mut tables: ResMut<Tables>,
+    mut sessions: ResMut<Sessions>,
+    mut shuffle_rng: ResMut<ShuffleRng>,
+    game_assets: Res<GameAssets>,
+    time: Res<Time>,
+    mut finished_at: Local<HashMap<TableId, Duration>>,
+) {
+    for lobby in tables.tables.values_mut() {
+        if lobby.stage != Stage::Showdown {
+            finished_at.remove(&lobby.id);
+            continue;
+        }
+        let finished_at = *finished_at.entry(lobby.id).or_insert(time.elapsed());
+        if time.elapsed() - finished_at < HAND_END_PAUSE {
+            continue;
+        }
+        if lobby.players.iter().filter(|player| player.money > 0).count() < 2 {
+            continue;
+        }
+        start_hand(&mut server, lobby, &mut sessions, game_assets.deck.clone(), &mut shuffle_rng);
+    }
+}
+
+// Plays an action for whoever's turn it is and tells the table, then moves the hand along
+fn resolve_action(
+    server: &mut RenetServer,
+    lobby: &mut Lobby,
+    sessions: &mut Sessions,
+    client_id: u64,
+    action: Action,
+) -> Result<(), String> {
+    let sequence = lobby.sequence;
+    if let ActionResult::Error(error, _) = lobby.play_turn(action) {
+        return Err(error);
+    }
+    // Relay with the id of the connection it came from, never the id the client claimed
+    broadcast(server, sessions, lobby.id, ServerMessage::Action(action, client_id, sequence));
+    if let Some(player) = lobby.players.iter().find(|player| player.client_id == client_id) {
+        let text = match action {
+            Action::Check => format!("{} checks", player.name),
+            Action::Call => format!("{} calls", player.name),
+            Action::Raise(_) => format!("{} raises to {}", player.name, player.bet_this_turn),
+            Action::Fold => format!("{} folds", player.name),
+            Action::AllIn => format!("{} is all in for {}", player.name, player.bet_this_turn),
+        };
+        dealer_message(server, sessions, lobby.id, text);
+    }
+    settle(server, lobby, sessions);
+    Ok(())
+}
+
+// Deals the next street once betting is done and pays out when the hand is over
+fn settle(server: &mut RenetServer, lobby: &mut Lobby, sessions: &mut Sessions) {
+    let stage = lobby.stage;
+    let payouts = lobby.advance_hand().unwrap_or_default();
+    if lobby.stage == stage {
+        return;
+    }
+    if !lobby.board.is_empty() {
+        let street = match lobby.stage {
+            Stage::Flop => "Flop",
+            Stage::Turn => "Turn",
+            Stage::River => "River",
+            _ => "Board",
+        };
+        let cards: Vec<String> = lobby.board.iter().map(|card| card.to_string()).collect();
+        dealer_message(server, sessions, lobby.id, format!("{street}: {}", cards.join(" ")));
+    }
+    send_snapshots(server, lobby, sessions);
+    for payout in payouts {
+        let Some(player) = lobby.players.iter().find(|player| player.client_id == payout.client_id) else {
+            continue;
+        };
+        let text = match payout.hand {
+            Some(hand) => format!("{} wins {} with {}", player.name, payout.amount, hand),
+            None => format!("{} wins {}", player.name, payout.amount),
+        };
+        dealer_message(server, sessions, lobby.id, text);
+    }
+}
+
+// Puts the client at a table, in a seat if it wants one and there is one free, otherwise watching.
+// A client that already has a seat there just gets it back.
+fn sit_down(server: &mut RenetServer, lobby: &mut Lobby, sessions: &mut Sessions, client_id: u64, spectate: bool) {
+    sessions.table_of.insert(client_id, lobby.id);
+    let name = sessions.names.get(&client_id).cloned().unwrap_or_default();
+    // Someone sitting down mid-hand would be a live seat with no cards, so they wait for the next hand
+    let must_wait = lobby.players.len() >= lobby.settings.max_seats || lobby.is_betting();
+    let announcement = match lobby.get_player_mut_by_id(client_id) {
+        Some(seated) => {
+            // Reconnecting, the seat, chips and cards are right where they were left
+            seated.is_disconnected = false;
+            seated.name = name;
+            println!("Client {client_id} reclaimed their seat");
+            Some(format!("{} is back", seated.name))
+        }
+        None if spectate || must_wait => {
+            if !spectate {
+                // Watch and wait for the next free seat, promote_spectators_system hands it out between hands
+                println!("Client {client_id} is waiting for a seat at table {}", lobby.id);
+                sessions.seat_queue.push_back(client_id);
+            }
+            println!("Client {client_id} is watching table {} as {name}", lobby.id);
+            sessions.spectators.insert(client_id);
+            None
+        }
+        None => {
+            // The server decides who the player is, what they hold and what they sit down with
+            let announcement = format!("{name} sat down");
+            lobby.add_player(Player {
+                name,
+                client_id,
+                money: lobby.settings.starting_stack,
+                time_bank: lobby.settings.time_bank_seconds,
+                ..Default::default()
+            });
+            Some(announcement)
+        }
+    };
+    if let Some(announcement) = announcement {
+        dealer_message(server, sessions, lobby.id, announcement);
+        send_player_views(server, lobby, sessions);
+    }
+    // Late joiners get the pot, board and turn straight away
+    send_snapshot(server, lobby, sessions, client_id);
+}
+
+// Gets the client up from wherever it is sitting or watching, folding its hand if it is in one
+fn leave_table(server: &mut RenetServer, tables: &mut Tables, sessions: &mut Sessions, client_id: u64) {
+    let Some(table_id) = sessions.table_of.remove(&client_id) else {
+        return;
+    };
+    sessions.spectators.remove(&client_id);
+    sessions.seat_queue.retain(|waiting| *waiting != client_id);
+    sessions.spectator_feed.retain(|(_, recipient, _)| *recipient != client_id);
+    let Some(lobby) = tables.tables.get_mut(&table_id) else {
+        return;
+    };
+    let Some(player) = lobby.get_player_mut_by_id(client_id) else {
+        return;
+    };
+    player.is_folded = true;
+    let announcement = format!("{} left the table", player.name);
+    lobby.remove_player_by_id(client_id);
+    // Whoever opened the table hands it over to the next player so it can still be started
+    if lobby.owner == Some(client_id) {
+        lobby.owner = lobby.players.first().map(|player| player.client_id);
+    }
+    dealer_message(server, sessions, table_id, announcement);
+    // Their leaving might have ended the hand or the betting round
+    settle(server, lobby, sessions);
+    send_snapshots(server, lobby, sessions);
+}
+
+// Sends every client at the table its own view of it, opponents' hands are redacted until showdown
+pub fn send_player_views(server: &mut RenetServer, lobby: &Lobby, sessions: &mut Sessions) {
+    for client_id in sessions.at_table(lobby.id) {
+        for player in lobby.players_view_for(client_id) {
+            send_to(server, sessions, client_id, ServerMessage::Player(player));
+        }
+    }
+}
+
+// Answers handshakes, and kicks clients that were rejected once they had time to read why or never sent one
+pub fn handshake_system(
+    mut server: ResMut<RenetServer>,
+    mut sessions: ResMut<Sessions>,
+    tables: Res<Tables>,
+    host_login: Option<Res<HostLogin>>,
+    time: Res<Time>,
+) {
+    // A secure host only lets in clients that know how to log in
+    let required_features = if host_login.is_some() { FEATURE_SECURE_LOGIN } else { 0 };
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, HANDSHAKE_CHANNEL) {
+            sessions.awaiting_handshake.remove(&client_id);
+            let response = match Handshake::try_from(message) {
+                Ok(handshake) => handshake.respond(required_features),
+                Err(_) => HandshakeResponse::Rejected("The host could not understand your game version".to_string()),
+            };
+            server.send_message(client_id, HANDSHAKE_CHANNEL, Into::<Bytes>::into(response.clone()));
+            match response {
+                HandshakeResponse::Accepted(handshake) => {
+                    sessions.verified.insert(client_id, handshake.features);
+                    // Tells the client what it can pick from before it sits down anywhere
+                    server.send_message(client_id, DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::TableList(tables.infos())));
+                }
+                HandshakeResponse::Rejected(reason) => {
+                    println!("Rejected client {client_id}: {reason}");
+                    sessions.rejected.insert(client_id, time.elapsed());
+                }
+            }
+        }
+    }
+    let expired: Vec<u64> = sessions.rejected.iter()
+        .filter(|(_, rejected_at)| time.elapsed() - **rejected_at > REJECTED_LINGER)
+        .map(|(client_id, _)| *client_id)
+        .collect();
+    for client_id in expired {
+        sessions.rejected.remove(&client_id);
+        server.disconnect(client_id);
+    }
+    let silent: Vec<u64> = sessions.awaiting_handshake.iter()
+        .filter(|(_, connected_at)| time.elapsed() - **connected_at > HANDSHAKE_TIMEOUT)
+        .map(|(client_id, _)| *client_id)
+        .collect();
+    for client_id in silent {
+        println!("Disconnecting client {client_id}, it never sent a handshake");
+        sessions.awaiting_handshake.remove(&client_id);
+        server.disconnect(client_id);
+    }
+}
+
+pub fn receive_message_system(
+    mut server: ResMut<RenetServer>,
+    mut tables: ResMut<Tables>,
+    mut sessions: ResMut<Sessions>,
+    mut shuffle_rng: ResMut<ShuffleRng>,
+    game_assets: Res<GameAssets>,
+) {
     for client_id in server.clients_id() {
-        if lobby.is_client_turn(client_id) {
-            while let Some(message) = server.receive_message(client_id, DefaultChannel::ReliableOrdered) {
-                let client_message = ServerMessage::from(message.clone());
-                match client_message {
-                    ServerMessage::Action(action, _) => {
-                        lobby.play_turn(action);
+        if !sessions.verified.contains_key(&client_id) {
+            // Could be an incompatible version, don't even try to decode it
+            while server.receive_message(client_id, DefaultChannel::ReliableOrdered).is_some() {}
+            continue;
+        }
+        while let Some(message) = server.receive_message(client_id, DefaultChannel::ReliableOrdered) {
+            let client_message = match ServerMessage::try_from(message) {
+                Ok(client_message) => client_message,
+                Err(error) => {
+                    println!("Disconnecting client {client_id}, it sent a bad message: {error}");
+                    server.disconnect(client_id);
+                    break;
+                }
+            };
+            let table_id = sessions.table_of.get(&client_id).copied();
+            match client_message {
+                ServerMessage::Join(Player { name, .. }, session_token) | ServerMessage::Spectate(name, session_token) if !sessions.claim(client_id, session_token) => {
+                    println!("Client {client_id} ({name}) tried to sit down with the wrong session token");
+                    server.disconnect(client_id);
+                    break;
+                }
+                ServerMessage::Spectate(..) | ServerMessage::RequestSeat if !sessions.has_feature(client_id, FEATURE_SPECTATING) => {
+                    println!("Client {client_id} asked to spectate without saying it can");
+                }
+                ServerMessage::Join(Player { name, .. }, _) | ServerMessage::Spectate(name, _) if table_id.is_some() && tables.seat_of(client_id).is_none() => {
+                    println!("Client {client_id} ({name}) is already watching table {:?}", table_id);
+                }
+                ServerMessage::Join(Player { name, .. }, _) => {
+                    let name = player_name_for(&sessions, client_id, &name);
+                    sessions.names.insert(client_id, name);
+                    // Back to the seat being held for us, otherwise the main table
+                    let table_id = tables.seat_of(client_id).unwrap_or(MAIN_TABLE);
+                    if let Some(lobby) = tables.tables.get_mut(&table_id) {
+                        sit_down(&mut server, lobby, &mut sessions, client_id, false);
+                    }
+                }
+                ServerMessage::Spectate(name, _) => {
+                    let name = player_name_for(&sessions, client_id, &name);
+                    sessions.names.insert(client_id, name);
+                    let table_id = tables.seat_of(client_id).unwrap_or(MAIN_TABLE);
+                    if let Some(lobby) = tables.tables.get_mut(&table_id) {
+                        sit_down(&mut server, lobby, &mut sessions, client_id, true);
+                    }
+                }
+                ServerMessage::RequestSeat if sessions.spectators.contains(&client_id) => {
+                    if !sessions.seat_queue.contains(&client_id) {
+                        sessions.seat_queue.push_back(client_id);
+                    }
+                }
+                ServerMessage::Ready(is_ready) => {
+                    let lobby = table_id.and_then(|table_id| tables.tables.get_mut(&table_id));
+                    if let Some(lobby) = lobby {
+                        if let Some(player) = lobby.get_player_mut_by_id(client_id) {
+                            player.is_ready = is_ready;
+                            send_player_views(&mut server, lobby, &mut sessions);
+                        }
+                    }
+                }
+                ServerMessage::RequestSnapshot => {
+                    if let Some(lobby) = table_id.and_then(|table_id| tables.tables.get(&table_id)) {
+                        send_snapshot(&mut server, lobby, &mut sessions, client_id);
+                    }
+                }
+                ServerMessage::Action(action, _, sequence) => {
+                    let Some(lobby) = table_id.and_then(|table_id| tables.tables.get_mut(&table_id)) else {
+                        println!("Ignoring an action from client {client_id}, who isn't at a table");
+                        continue;
+                    };
+                    if lobby.current_player_id() != Some(client_id) || lobby.sequence != sequence {
+                        // Usually an action that lost the race against the turn clock, or made on a table the client hadn't caught up with
+                        println!("Ignoring an out of turn action from client {client_id}");
+                    } else if let Err(error) = resolve_action(&mut server, lobby, &mut sessions, client_id, action) {
+                        println!("Client {client_id} made an illegal move: {error}");
+                    } else {
+                        continue;
+                    }
+                    // Whatever the client thought was going on, this is the real table
+                    send_snapshot(&mut server, lobby, &mut sessions, client_id);
+                }
+                ServerMessage::StartGame => {
+                    let lobby = table_id.and_then(|table_id| tables.tables.get_mut(&table_id));
+                    match lobby {
+                        Some(lobby) if lobby.owner == Some(client_id) && lobby.stage == Stage::Waiting => {
+                            broadcast(&mut server, &mut sessions, lobby.id, ServerMessage::StartGame);
+                            start_hand(&mut server, lobby, &mut sessions, game_assets.deck.clone(), &mut shuffle_rng);
+                        }
+                        _ => println!("Client {client_id} tried to start a table it doesn't run"),
+                    }
+                }
+                ServerMessage::SendChat(text) => {
+                    // Only people at a table, seated or watching, get to talk
+                    let (Some(table_id), Some(name)) = (table_id, sessions.names.get(&client_id).cloned()) else {
+                        continue;
+                    };
+                    let text = if sessions.allow_chat(client_id) {
+                        clean_chat_text(&text)
+                    } else {
+                        Err(format!("Slow down, you can send {} messages every {} seconds", CHAT_RATE_LIMIT, CHAT_RATE_WINDOW.as_secs()))
+                    };
+                    match text {
+                        Ok(text) => relay_chat(&mut server, &mut sessions, table_id, ChatLine { sender: Some(client_id), name, text }),
+                        Err(reason) => send_to(&mut server, &mut sessions, client_id, ServerMessage::Chat(ChatLine::dealer(reason))),
+                    }
+                }
+                ServerMessage::Mute(target, muted) => {
+                    let muted_by_client = sessions.muted.entry(client_id).or_default();
+                    if muted {
+                        muted_by_client.insert(target);
+                    } else {
+                        muted_by_client.remove(&target);
+                    }
+                }
+                ServerMessage::ListTables => {
+                    server.send_message(client_id, DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::TableList(tables.infos())));
+                }
+                ServerMessage::CreateTable(name, settings) => {
+                    let Some(player_name) = sessions.names.get(&client_id).cloned() else {
+                        continue;
+                    };
+                    if tables.tables.len() >= MAX_TABLES {
+                        let line = ChatLine::dealer("The server can't open any more tables");
+                        send_to(&mut server, &mut sessions, client_id, ServerMessage::Chat(line));
+                        continue;
+                    }
+                    let name = clean_name(&name, MAX_TABLE_NAME_LENGTH);
+                    let name = if name.is_empty() { format!("{player_name}'s table") } else { name };
+                    leave_table(&mut server, &mut tables, &mut sessions, client_id);
+                    let table_id = tables.create(name, TableSettings::for_new_table(&settings), client_id, game_assets.deck.clone());
+                    println!("Client {client_id} opened table {table_id}");
+                    if let Some(lobby) = tables.tables.get_mut(&table_id) {
+                        sit_down(&mut server, lobby, &mut sessions, client_id, false);
+                    }
+                }
+                ServerMessage::JoinTable(new_table_id) if tables.tables.contains_key(&new_table_id) && sessions.names.contains_key(&client_id) => {
+                    if table_id == Some(new_table_id) {
+                        continue;
                     }
-                    _ => {
-                        println!("Unknown message from client {client_id}: {:?}", message);
+                    leave_table(&mut server, &mut tables, &mut sessions, client_id);
+                    if let Some(lobby) = tables.tables.get_mut(&new_table_id) {
+                        sit_down(&mut server, lobby, &mut sessions, client_id, false);
+                    }
+                }
+                ServerMessage::JoinTable(_) => {
+                    // Most likely the table closed since the client last looked
+                    server.send_message(client_id, DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::TableList(tables.infos())));
+                }
+                ServerMessage::LeaveTable => {
+                    leave_table(&mut server, &mut tables, &mut sessions, client_id);
+                    server.send_message(client_id, DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::LeaveTable));
+                    server.send_message(client_id, DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::TableList(tables.infos())));
+                }
+                unexpected => {
+                    let strikes = sessions.strikes.entry(client_id).or_insert(0);
+                    *strikes += 1;
+                    println!("Unexpected message from client {client_id} (strike {strikes}): {:?}", unexpected);
+                    if *strikes >= MAX_STRIKES {
+                        println!("Disconnecting client {client_id} for misbehaving");
+                        server.disconnect(client_id);
                         break;
                     }
                 }
-                server.broadcast_message(DefaultChannel::ReliableOrdered, message);
             }
         }
     }
 }
 
-pub fn handle_events_system(mut server_events: EventReader<ServerEvent>) {
+// What a client is called at the tables, its account name if it logged in, otherwise whatever it asked for cleaned up
+fn player_name_for(sessions: &Sessions, client_id: u64, requested: &str) -> String {
+    let name = clean_name(sessions.accounts.get(&client_id).map_or(requested, String::as_str), MAX_PLAYER_NAME_LENGTH);
+    if name.is_empty() { format!("Player {client_id}") } else { name }
+}
+
+pub fn send_snapshot(server: &mut RenetServer, lobby: &Lobby, sessions: &mut Sessions, client_id: u64) {
+    send_to(server, sessions, client_id, ServerMessage::TableSnapshot(lobby.snapshot_for(client_id)));
+}
+
+pub fn send_snapshots(server: &mut RenetServer, lobby: &Lobby, sessions: &mut Sessions) {
+    for client_id in sessions.at_table(lobby.id) {
+        send_snapshot(server, lobby, sessions, client_id);
+    }
+}
+
+pub fn handle_events_system(
+    mut server_events: EventReader<ServerEvent>,
+    mut server: ResMut<RenetServer>,
+    mut tables: ResMut<Tables>,
+    mut sessions: ResMut<Sessions>,
+    // Only netcode connections carry account names, in-memory ones never log in
+    transport: Option<Res<UdpServerTransport>>,
+    time: Res<Time>,
+) {
     //println!("Handling events");
     for event in server_events.read() {
         match event {
             ServerEvent::ClientConnected { client_id } => {
                 println!("Client {client_id} connected");
+                sessions.awaiting_handshake.insert(*client_id, time.elapsed());
+                if let Some(user_data) = transport.as_ref().and_then(|transport| transport.user_data(*client_id)).filter(|user_data| user_data.iter().any(|byte| *byte != 0)) {
+                    sessions.accounts.insert(*client_id, auth::user_data_to_username(&user_data));
+                }
             }
             ServerEvent::ClientDisconnected { client_id, reason } => {
                 println!("Client {client_id} disconnected: {reason}");
-            },
-            _ => {
-                println!("Unknown event: {:?}", event);
+                sessions.verified.remove(client_id);
+                sessions.awaiting_handshake.remove(client_id);
+                sessions.rejected.remove(client_id);
+                sessions.strikes.remove(client_id);
+                sessions.muted.remove(client_id);
+                sessions.chat_times.remove(client_id);
+                // Hold the seat so the player can come back with the same session token, spectators just go
+                let seat = tables.seat_of(*client_id).and_then(|table_id| tables.tables.get_mut(&table_id));
+                let Some(lobby) = seat else {
+                    leave_table(&mut server, &mut tables, &mut sessions, *client_id);
+                    continue;
+                };
+                if let Some(player) = lobby.get_player_mut_by_id(*client_id) {
+                    player.is_disconnected = true;
+                    let announcement = format!("{} lost connection", player.name);
+                    sessions.disconnected.insert(*client_id, time.elapsed());
+                    dealer_message(&mut server, &mut sessions, lobby.id, announcement);
+                    send_player_views(&mut server, lobby, &mut sessions);
+                }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// Folds and unseats players that did not come back within the grace period
+pub fn reconnect_timeout_system(
+    mut server: ResMut<RenetServer>,
+    mut tables: ResMut<Tables>,
+    mut sessions: ResMut<Sessions>,
+    time: Res<Time>,
+) {
+    let expired: Vec<u64> = sessions.disconnected.iter()
+        .filter(|(_, disconnected_at)| time.elapsed() - **disconnected_at > RECONNECT_GRACE_PERIOD)
+        .map(|(client_id, _)| *client_id)
+        .collect();
+    for client_id in expired {
+        println!("Client {client_id} did not reconnect in time, folding and freeing their seat");
+        sessions.disconnected.remove(&client_id);
+        sessions.tokens.remove(&client_id);
+        sessions.names.remove(&client_id);
+        leave_table(&mut server, &mut tables, &mut sessions, client_id);
+    }
+}
+
+// Closes tables other than the main one once nobody is sitting at or watching them
+pub fn close_empty_tables_system(mut tables: ResMut<Tables>, sessions: Res<Sessions>) {
+    let empty: Vec<TableId> = tables.tables.values()
+        .filter(|lobby| lobby.id != MAIN_TABLE && lobby.players.is_empty())
+        .filter(|lobby| !sessions.table_of.values().any(|table_id| *table_id == lobby.id))
+        .map(|lobby| lobby.id)
+        .collect();
+    for table_id in empty {
+        println!("Closing empty table {table_id}");
+        tables.tables.remove(&table_id);
+    }
+}
+
+// Tells each table how everyone seated at it is connected, players whose seat is being held are already shown as away
+pub fn connection_quality_system(
+    mut server: ResMut<RenetServer>,
+    mut sessions: ResMut<Sessions>,
+    tables: Res<Tables>,
+    time: Res<Time>,
+    mut since_last_report: Local<Duration>,
+) {
+    *since_last_report += time.delta();
+    if *since_last_report < CONNECTION_REPORT_INTERVAL {
+        return;
+    }
+    *since_last_report = Duration::ZERO;
+    for lobby in tables.tables.values() {
+        let report: Vec<ConnectionQuality> = lobby.players.iter()
+            .filter(|player| !player.is_disconnected)
+            .map(|player| ConnectionQuality::new(player.client_id, server.rtt(player.client_id), server.packet_loss(player.client_id)))
+            .collect();
+        if !report.is_empty() {
+            broadcast(&mut server, &mut sessions, lobby.id, ServerMessage::ConnectionQuality(report));
+        }
+    }
+}
+
+// Runs the clock for whoever is to act at each table, moving onto their time bank and then checking or folding for them
+pub fn turn_timer_system(
+    mut server: ResMut<RenetServer>,
+    mut tables: ResMut<Tables>,
+    mut timer: ResMut<TurnTimer>,
+    mut sessions: ResMut<Sessions>,
+    time: Res<Time>,
+) {
+    for lobby in tables.tables.values_mut() {
+        let Some(client_id) = lobby.current_player_id().filter(|_| lobby.is_betting()) else {
+            timer.clocks.remove(&lobby.id);
+            continue;
+        };
+        let sequence = lobby.sequence;
+        let Some((_, clock)) = timer.clocks.get_mut(&lobby.id).filter(|(started_at, clock)| *started_at == sequence && clock.client_id == client_id) else {
+            // A new turn started, give the player a fresh clock
+            let clock = TurnClock {
+                client_id,
+                remaining: lobby.settings.action_seconds,
+                using_time_bank: false,
+            };
+            timer.clocks.insert(lobby.id, (sequence, clock));
+            broadcast(&mut server, &mut sessions, lobby.id, ServerMessage::TurnClock(clock));
+            continue;
+        };
+
+        clock.remaining -= time.delta_secs();
+        let Some(player) = lobby.get_player_mut_by_id(client_id) else {
+            continue;
+        };
+        if clock.using_time_bank {
+            player.time_bank = clock.remaining.max(0.0);
+        }
+        if clock.remaining > 0.0 {
+            continue;
+        }
+        if !clock.using_time_bank && player.time_bank > 0.0 {
+            clock.using_time_bank = true;
+            clock.remaining = player.time_bank;
+            let clock = *clock;
+            broadcast(&mut server, &mut sessions, lobby.id, ServerMessage::TurnClock(clock));
+            continue;
+        }
+
+        let announcement = format!("{} ran out of time", player.name);
+        let action = lobby.timeout_action();
+        println!("Client {client_id} ran out of time, playing {:?} for them", action);
+        timer.clocks.remove(&lobby.id);
+        dealer_message(&mut server, &mut sessions, lobby.id, announcement);
+        if let Err(error) = resolve_action(&mut server, lobby, &mut sessions, client_id, action) {
+            println!("Could not play the timeout action for client {client_id}: {error}");
+        }
+    }
+}
+
+// Seats waiting spectators whenever a seat is free at their table and no hand is being played there
+pub fn promote_spectators_system(mut server: ResMut<RenetServer>, mut tables: ResMut<Tables>, mut sessions: ResMut<Sessions>) {
+    let waiting: Vec<u64> = sessions.seat_queue.iter().copied().collect();
+    for client_id in waiting {
+        let Some(lobby) = sessions.table_of.get(&client_id).and_then(|table_id| tables.tables.get_mut(table_id)) else {
+            continue;
+        };
+        if lobby.is_betting() || lobby.players.len() >= lobby.settings.max_seats {
+            continue;
+        }
+        sessions.seat_queue.retain(|waiting| *waiting != client_id);
+        if !sessions.spectators.remove(&client_id) {
+            continue;
+        }
+        let name = sessions.names.get(&client_id).cloned().unwrap_or_default();
+        println!("Spectator {client_id} took a seat at table {} as {name}", lobby.id);
+        // Anything still on the delayed feed is older than what the new player is about to get
+        sessions.spectator_feed.retain(|(_, recipient, _)| *recipient != client_id);
+        lobby.add_player(Player {
+            name,
+            client_id,
+            money: lobby.settings.starting_stack,
+            time_bank: lobby.settings.time_bank_seconds,
+            ..Default::default()
+        });
+        send_player_views(&mut server, lobby, &mut sessions);
+        send_snapshot(&mut server, lobby, &mut sessions, client_id);
+    }
+}
+
+// Hands spectators what happened at their table once that table's delay has passed
+pub fn flush_spectator_feed_system(mut server: ResMut<RenetServer>, mut sessions: ResMut<Sessions>, tables: Res<Tables>) {
+    let Sessions { spectator_feed, table_of, .. } = &mut *sessions;
+    let mut due = Vec::new();
+    spectator_feed.retain(|(sent_at, client_id, message)| {
+        let delay = table_of.get(client_id)
+            .and_then(|table_id| tables.tables.get(table_id))
+            .map(|lobby| Duration::from_secs_f32(lobby.settings.spectator_delay_seconds.max(0.0)))
+            .unwrap_or_default();
+        if sent_at.elapsed() < delay {
+            return true;
+        }
+        due.push((*client_id, message.clone()));
+        false
+    });
+    for (client_id, message) in due {
+        server.send_message(client_id, DefaultChannel::ReliableOrdered, Into::<Bytes>::into(message));
+    }
+}
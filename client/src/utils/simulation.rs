@@ -0,0 +1,490 @@
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy_renet::{RenetClientPlugin, RenetServerPlugin};
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use renet::{DefaultChannel, RenetClient};
+use std::time::{Duration, Instant};
+use crate::{GameAssets, GameState, ServerMode};
+use crate::utils::client;
+use crate::utils::server::Tables;
+use crate::utils::*;
+
+// Headless stress test, a host and scripted bots play each other over the loopback transport with no window or rendering.
+// Every tick it checks that no chips appear or vanish at the host's table, and that each bot that has caught up with the server
+// sees the same table it does. Once a game is down to one player with chips a new one starts, until enough hands are played.
+// Run with `client --simulate [--bots N] [--hands H] [--seed S]`, it exits with 1 if anything went wrong.
+// Adding --latency, --jitter, --loss or --duplicate plays over a bad network instead of a perfect one.
+// The seed drives the bots and the shuffles, so over a perfect network the same seed plays the same hands. A bad network is still random.
+
+// Simulated time every update moves forward by
+const TICK: Duration = Duration::from_millis(100);
+// Longest a hand may take in simulated time, the pause between hands included, before the table counts as stuck
+const STALL_LIMIT: Duration = Duration::from_secs(120);
+// How long after a hand ends every bot has to have caught up, late enough for a bad network
+// but before the server deals the next hand five seconds in
+const SETTLE_TIME: Duration = Duration::from_secs(4);
+// How long a bot gets to sit down before the next one connects
+const JOIN_TICKS: u32 = 50;
+// Problems printed as they happen, the rest are only counted
+const MAX_REPORTED: usize = 20;
+
+struct SimulationOptions {
+    bots: usize,
+    hands: u32,
+    seed: u64,
+    network: NetworkConditions,
+}
+
+impl SimulationOptions {
+    fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = SimulationOptions {
+            bots: 4,
+            hands: 1000,
+            seed: 0,
+            network: NetworkConditions::from_args(args)?,
+        };
+        let mut args = args.iter().skip_while(|arg| *arg != "--simulate").skip(1);
+        while let Some(arg) = args.next() {
+            let value = args.next().ok_or_else(|| format!("{arg} needs a value"))?;
+            let invalid = |_| format!("{value} is not a valid value for {arg}");
+            match arg.as_str() {
+                "--bots" => options.bots = value.parse().map_err(invalid)?,
+                "--hands" => options.hands = value.parse().map_err(invalid)?,
+                "--seed" => options.seed = value.parse().map_err(invalid)?,
+                // Already read by NetworkConditions::from_args
+                "--latency" | "--jitter" | "--loss" | "--duplicate" => {}
+                _ => return Err(format!("Unknown option {arg}")),
+            }
+        }
+        let max_seats = TableSettings::default().max_seats;
+        if options.bots < 2 || options.bots > max_seats {
+            return Err(format!("Need between 2 and {max_seats} bots"));
+        }
+        Ok(options)
+    }
+}
+
+// Plays whatever it's dealt, mostly checking and calling with the odd fold, raise and shove
+#[derive(Resource)]
+struct Bot {
+    rng: StdRng,
+    table_size: usize,
+    started: bool,
+    // Table sequence we last acted at, the action stays ours to make until the server plays it
+    acted_at: Option<u32>,
+}
+
+fn bot_system(
+    mut bot: ResMut<Bot>,
+    lobby: Res<Lobby>,
+    game_assets: Res<GameAssets>,
+    client: Option<ResMut<RenetClient>>,
+    mut actions: EventWriter<Action>,
+) {
+    let Some(mut client) = client else {
+        return;
+    };
+    if !client.is_connected() {
+        return;
+    }
+    let client_id = game_assets.client_id;
+    // The host's bot starts the table once everyone sat down, like pressing Start
+    if !bot.started && lobby.owner == Some(client_id) && lobby.stage == Stage::Waiting && lobby.players.len() == bot.table_size {
+        bot.started = true;
+        client.send_message(DefaultChannel::ReliableOrdered, Into::<Bytes>::into(ServerMessage::StartGame));
+    }
+    let legal_actions = lobby.legal_actions(client_id);
+    if legal_actions.is_empty() || bot.acted_at == Some(lobby.sequence) {
+        return;
+    }
+    bot.acted_at = Some(lobby.sequence);
+    let passive = legal_actions.iter().copied().find(|action| matches!(action, Action::Check | Action::Call));
+    let raise = legal_actions.iter().copied().find(|action| matches!(action, Action::Raise(_)));
+    let roll = bot.rng.gen_range(0..100);
+    let action = if roll < 10 && !matches!(passive, Some(Action::Check)) {
+        Action::Fold
+    } else if roll < 80 {
+        passive.unwrap_or(Action::AllIn)
+    } else if roll < 97 {
+        raise.or(passive).unwrap_or(Action::AllIn)
+    } else {
+        Action::AllIn
+    };
+    actions.send(action);
+}
+
+// One player's whole game, the first one also runs the server
+fn bot_app(hub: &LoopbackHub, client_id: u64, rng: &mut StdRng, options: &SimulationOptions) -> App {
+    let mut app = App::new();
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(TICK))
+        .add_plugins((MinimalPlugins, StatesPlugin, RenetServerPlugin, RenetClientPlugin, LoopbackPlugin, ServerPlugin))
+        .init_state::<GameState>()
+        .init_state::<ServerMode>()
+        .insert_resource(GameAssets {
+            // Nothing gets drawn, the cards only need to be there
+            deck: Deck::new(vec![Handle::default(); 52]),
+            player_name: format!("Bot {client_id}"),
+            client_id,
+            session_token: rng.gen(),
+            ..Default::default()
+        })
+        .insert_resource(Settings::default())
+        .insert_resource(TransportSetup::Loopback(hub.clone()))
+        .insert_resource(options.network)
+        // Only the host deals, but every bot gets one so the first app isn't special
+        .insert_resource(ShuffleRng(StdRng::seed_from_u64(rng.gen())))
+        .insert_resource(Bot {
+            rng: StdRng::seed_from_u64(rng.gen()),
+            table_size: options.bots,
+            started: false,
+            acted_at: None,
+        })
+        .init_resource::<Lobby>()
+        .add_event::<Action>()
+        // Sent the frame it's picked, like a click
+        .add_systems(Update, bot_system.before(client::send_message_system));
+    app.finish();
+    app.cleanup();
+    app
+}
+
+// Everything that isn't the same on a bot's table as on the server's, ignoring hole cards bots aren't shown
+fn table_differences(server: &Lobby, client: &Lobby) -> Option<String> {
+    if server.sequence != client.sequence || server.stage != client.stage || server.pot != client.pot || server.current_bet != client.current_bet
+        || server.turn != client.turn || server.button != client.button || server.board != client.board {
+        return Some(format!(
+            "sees #{} {:?} with pot {}, bet {}, turn {}, button {} and board {:?}, the server has #{} {:?} with pot {}, bet {}, turn {}, button {} and board {:?}",
+            client.sequence, client.stage, client.pot, client.current_bet, client.turn, client.button, client.board,
+            server.sequence, server.stage, server.pot, server.current_bet, server.turn, server.button, server.board,
+        ));
+    }
+    let server_ids: Vec<u64> = server.players.iter().map(|player| player.client_id).collect();
+    let client_ids: Vec<u64> = client.players.iter().map(|player| player.client_id).collect();
+    if server_ids != client_ids {
+        return Some(format!("seats {client_ids:?}, the server seats {server_ids:?}"));
+    }
+    server.players.iter().zip(client.players.iter()).find_map(|(theirs, ours)| {
+        let same = theirs.money == ours.money && theirs.bet_this_turn == ours.bet_this_turn
+            && theirs.is_folded == ours.is_folded && theirs.is_all_in == ours.is_all_in;
+        (!same).then(|| format!(
+            "sees {} with {} chips, bet {}, folded {}, all in {}, the server has {} chips, bet {}, folded {}, all in {}",
+            ours.name, ours.money, ours.bet_this_turn, ours.is_folded, ours.is_all_in,
+            theirs.money, theirs.bet_this_turn, theirs.is_folded, theirs.is_all_in,
+        ))
+    })
+}
+
+#[derive(Default)]
+struct Problems {
+    count: usize,
+}
+
+impl Problems {
+    fn report(&mut self, hand: u32, problem: String) {
+        self.count += 1;
+        if self.count <= MAX_REPORTED {
+            println!("Hand {hand}: {problem}");
+        }
+    }
+}
+
+// A host and its bots playing one game, stepped together a tick at a time
+struct Simulation {
+    apps: Vec<App>,
+    settle_ticks: u32,
+    // What the table should hold, known once everyone sat down
+    chips: Option<i32>,
+    hands: u32,
+    ticks: u64,
+    // Ticks since the first hand was dealt
+    playing_ticks: u64,
+    showdown_ticks: u32,
+    last_progress: u64,
+}
+
+impl Simulation {
+    fn new(options: &SimulationOptions, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let hub = LoopbackHub::new();
+        let mut apps: Vec<App> = (1..=options.bots as u64)
+            .map(|client_id| bot_app(&hub, client_id, &mut rng, options))
+            .collect();
+        // The host's server has to be up before its own client connects
+        apps[0].world_mut().resource_mut::<NextState<ServerMode>>().set(ServerMode::Host);
+        apps[0].update();
+        // One at a time, the server reads clients in no particular order and the same seed should give the same seats
+        for index in 0..apps.len() {
+            if index > 0 {
+                apps[index].world_mut().resource_mut::<NextState<ServerMode>>().set(ServerMode::Join);
+            }
+            apps[index].world_mut().resource_mut::<NextState<GameState>>().set(GameState::Connecting);
+            for _ in 0..JOIN_TICKS {
+                for app in apps.iter_mut() {
+                    app.update();
+                }
+                if apps[0].world().resource::<Tables>().main().players.len() > index {
+                    break;
+                }
+            }
+        }
+        Simulation {
+            apps,
+            settle_ticks: (SETTLE_TIME.as_millis() / TICK.as_millis()) as u32,
+            chips: None,
+            hands: 0,
+            ticks: 0,
+            playing_ticks: 0,
+            showdown_ticks: 0,
+            last_progress: 0,
+        }
+    }
+
+    fn server_table(&self) -> &Lobby {
+        self.apps[0].world().resource::<Tables>().main()
+    }
+
+    fn bot_table(&self, index: usize) -> &Lobby {
+        self.apps[index].world().resource::<Lobby>()
+    }
+
+    // Updates every app once and checks the table, reporting anything wrong with the hand number
+    fn tick(&mut self, problems: &mut Problems, hand_offset: u32) {
+        for app in self.apps.iter_mut() {
+            app.update();
+        }
+        self.ticks += 1;
+        let hand = hand_offset + self.hands;
+        let server = self.server_table();
+        let stage = server.stage;
+
+        // Chips only ever move between stacks and the pot
+        let table_chips = server.players.iter().map(|player| player.money).sum::<i32>() + server.pot;
+        match self.chips {
+            None if server.players.len() == self.apps.len() => self.chips = Some(table_chips),
+            Some(expected) if table_chips != expected => {
+                problems.report(hand, format!("{table_chips} chips at the table during {stage:?}, there should be {expected}"));
+                self.chips = Some(table_chips);
+            }
+            _ => {}
+        }
+
+        // A bot that has heard about everything the server did has to see exactly what the server sees.
+        // Over a perfect network that's every bot after every tick, except the host's own which hears back a tick later.
+        if stage != Stage::Waiting {
+            for index in 0..self.apps.len() {
+                let bot = self.bot_table(index);
+                if bot.sequence != self.server_table().sequence {
+                    continue;
+                }
+                if let Some(difference) = table_differences(self.server_table(), bot) {
+                    problems.report(hand, format!("Bot {} {difference}", index + 1));
+                }
+            }
+        }
+
+        if stage == Stage::Showdown {
+            self.showdown_ticks += 1;
+        } else {
+            self.showdown_ticks = 0;
+        }
+        if stage != Stage::Waiting {
+            self.playing_ticks += 1;
+        }
+        if self.showdown_ticks == 1 {
+            self.hands += 1;
+            self.last_progress = self.ticks;
+        }
+        // However bad the network, everyone should have heard how the hand ended by now
+        if self.showdown_ticks == self.settle_ticks {
+            for index in 0..self.apps.len() {
+                let (server, bot) = (self.server_table(), self.bot_table(index));
+                if bot.sequence != server.sequence {
+                    problems.report(hand, format!("Bot {} is stuck at #{} while the server is at #{}", index + 1, bot.sequence, server.sequence));
+                }
+            }
+        }
+    }
+
+    // Once one player has every chip the server won't deal again
+    fn is_over(&self) -> bool {
+        let server = self.server_table();
+        self.showdown_ticks >= self.settle_ticks && server.players.iter().filter(|player| player.money > 0).count() < 2
+    }
+
+    fn is_stuck(&self) -> bool {
+        self.ticks - self.last_progress > (STALL_LIMIT.as_millis() / TICK.as_millis()) as u64
+    }
+}
+
+// Plays the hands and prints what it found, returns the process exit code
+pub fn run_simulation(args: &[String]) -> i32 {
+    let options = match SimulationOptions::from_args(args) {
+        Ok(options) => options,
+        Err(error) => {
+            println!("{error}");
+            println!("Usage: client --simulate [--bots N] [--hands H] [--seed S] [--latency MS] [--jitter MS] [--loss PERCENT] [--duplicate PERCENT]");
+            return 2;
+        }
+    };
+    println!("Simulating {} hands between {} bots with seed {}", options.hands, options.bots, options.seed);
+    if options.network.enabled {
+        println!("Over a network with {}", options.network);
+    }
+    let mut seeds = StdRng::seed_from_u64(options.seed);
+    let started = Instant::now();
+    let mut problems = Problems::default();
+    let mut hands = 0;
+    let mut games = 0;
+    let mut playing_ticks = 0;
+    let (mut bytes_sent, mut bytes_received) = (0, 0);
+    while hands < options.hands {
+        let mut game = Simulation::new(&options, seeds.gen());
+        games += 1;
+        while hands + game.hands < options.hands && !game.is_over() && !game.is_stuck() {
+            let played = game.hands;
+            game.tick(&mut problems, hands);
+            if game.hands != played && (hands + game.hands).is_multiple_of(500) {
+                println!("{} hands played", hands + game.hands);
+            }
+        }
+        if game.is_stuck() {
+            let server = game.server_table();
+            problems.report(hands + game.hands, format!("The table is stuck in {:?} on seat {}'s turn", server.stage, server.turn));
+        }
+        hands += game.hands;
+        playing_ticks += game.playing_ticks;
+        let transport = game.apps[0].world().resource::<LoopbackServerTransport>();
+        bytes_sent += transport.bytes_sent();
+        bytes_received += transport.bytes_received();
+        if game.is_stuck() {
+            break;
+        }
+    }
+
+    let elapsed = started.elapsed().as_secs_f64();
+    let per_hand = |total: u64| total as f64 / f64::from(hands.max(1));
+    println!("Played {hands} hands over {games} games in {elapsed:.2}s, {:.1} hands per second", f64::from(hands) / elapsed.max(f64::EPSILON));
+    println!(
+        "Each hand took {:.1} updates ({:.1}s of game time), the server sent {:.0} bytes and received {:.0} bytes",
+        per_hand(playing_ticks),
+        per_hand(playing_ticks) * TICK.as_secs_f64(),
+        per_hand(bytes_sent),
+        per_hand(bytes_received),
+    );
+    if problems.count == 0 {
+        println!("No problems found");
+        0
+    } else {
+        println!("{} problems found", problems.count);
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(bots: usize) -> SimulationOptions {
+        SimulationOptions {
+            bots,
+            hands: 0,
+            seed: 0,
+            network: NetworkConditions::default(),
+        }
+    }
+
+    // Plays until the game has had this many hands, failing on anything the simulation would report
+    fn play(bots: usize, seed: u64, hands: u32) -> Simulation {
+        let mut game = Simulation::new(&options(bots), seed);
+        let mut problems = Problems::default();
+        while game.hands < hands && !game.is_over() {
+            assert!(!game.is_stuck(), "The table got stuck in {:?}", game.server_table().stage);
+            game.tick(&mut problems, 0);
+            if game.server_table().stage == Stage::Waiting {
+                continue;
+            }
+            // Nothing is in flight on a perfect network, every bot already heard about the server's last move
+            for index in 1..bots {
+                assert_eq!(game.bot_table(index).sequence, game.server_table().sequence, "Bot {} fell behind", index + 1);
+            }
+        }
+        assert_eq!(problems.count, 0, "The simulation found problems, see the output above");
+        game
+    }
+
+    #[test]
+    fn bots_see_the_server_table_after_every_action() {
+        let game = play(4, 1, 10);
+        assert!(game.hands == 10 || game.is_over());
+    }
+
+    #[test]
+    fn heads_up_stays_in_sync() {
+        let game = play(2, 2, 50);
+        assert!(game.hands > 0);
+    }
+
+    #[test]
+    fn a_full_table_stays_in_sync() {
+        let game = play(TableSettings::default().max_seats, 2, 50);
+        assert!(game.hands == 50 || game.is_over());
+    }
+
+    #[test]
+    fn a_player_who_joins_mid_hand_waits_for_the_next_one() {
+        let mut game = Simulation::new(&options(2), 3);
+        let mut problems = Problems::default();
+        // Bot 2's turn, so only the host's app has to keep running while the late bot connects
+        while !(game.server_table().is_betting() && game.server_table().current_player_id() == Some(2)) {
+            assert!(!game.is_stuck(), "The first hand never got going");
+            game.tick(&mut problems, 0);
+        }
+        let TransportSetup::Loopback(hub) = game.apps[0].world().resource::<TransportSetup>().clone() else {
+            panic!("The simulation plays in memory");
+        };
+        let mut late = bot_app(&hub, 3, &mut StdRng::seed_from_u64(3), &options(2));
+        late.world_mut().resource_mut::<NextState<ServerMode>>().set(ServerMode::Join);
+        late.world_mut().resource_mut::<NextState<GameState>>().set(GameState::Connecting);
+        for _ in 0..JOIN_TICKS {
+            game.apps[0].update();
+            late.update();
+        }
+        assert_eq!(late.world().resource::<Lobby>().players.len(), 2, "The late bot never got to see the table");
+        assert_eq!(game.server_table().players.len(), 2, "The late bot sat down in the middle of a hand");
+        game.apps.push(late);
+        // Its chips only join the count once it has a seat
+        game.chips = None;
+
+        // The hand plays out between the two bots that were dealt in
+        let hand = game.hands;
+        while game.hands == hand {
+            assert!(!game.is_stuck(), "The hand got stuck in {:?}", game.server_table().stage);
+            game.tick(&mut problems, 0);
+            let seated = game.server_table().players.iter().any(|player| player.client_id == 3);
+            assert!(!seated || !game.server_table().is_betting(), "The late bot sat down in the middle of a hand");
+        }
+        // And the next one deals the late bot in
+        while game.server_table().stage != Stage::PreFlop {
+            assert!(!game.is_over(), "The game ended before the late bot got a hand");
+            assert!(!game.is_stuck(), "The next hand never got going");
+            game.tick(&mut problems, 0);
+        }
+        let late_hand = game.server_table().players.iter().find(|player| player.client_id == 3).map(|player| player.hand.len());
+        assert_eq!(late_hand, Some(2), "The late bot wasn't dealt in");
+        assert_eq!(problems.count, 0, "The simulation found problems, see the output above");
+    }
+
+    #[test]
+    fn the_same_seed_plays_the_same_hands() {
+        let first = play(3, 7, 5);
+        let second = play(3, 7, 5);
+        let (first, second) = (first.server_table(), second.server_table());
+        assert_eq!(first.sequence, second.sequence);
+        assert_eq!(first.board, second.board);
+        let hands = |table: &Lobby| table.players.iter().map(|player| (player.money, player.hand.clone())).collect::<Vec<_>>();
+        assert_eq!(hands(first), hands(second));
+    }
+}
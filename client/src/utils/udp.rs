@@ -0,0 +1,438 @@
+use bevy::prelude::*;
+use bevy_renet::{RenetClientPlugin, RenetServerPlugin, RenetReceive, RenetSend};
+use renet::{ClientId, RenetClient, RenetServer};
+use renet_netcode::{ClientAuthentication, NetcodeDisconnectReason, NetcodeError, NetcodeTransportError, ServerConfig, NETCODE_USER_DATA_BYTES};
+use renetcode::{NetcodeClient, NetcodeServer, ServerResult, NETCODE_MAX_PACKET_BYTES};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+use crate::utils::{Conditioner, NetworkConditions};
+
+// Netcode over a UDP socket, the same as renet_netcode's transports except everything we send goes through
+// the conditioner first. That way NetworkConditions slow down real games too, not just in-memory ones.
+
+#[derive(Resource)]
+pub struct UdpServerTransport {
+    socket: UdpSocket,
+    netcode_server: NetcodeServer,
+    buffer: [u8; NETCODE_MAX_PACKET_BYTES],
+    // Netcode's own packets from the last update, they wait here for send_packets to condition them
+    outgoing: Vec<(SocketAddr, Vec<u8>)>,
+    conditioner: Conditioner<(SocketAddr, Vec<u8>)>,
+}
+
+impl UdpServerTransport {
+    pub fn new(server_config: ServerConfig, socket: UdpSocket) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(UdpServerTransport {
+            socket,
+            netcode_server: NetcodeServer::new(server_config),
+            buffer: [0; NETCODE_MAX_PACKET_BYTES],
+            outgoing: Vec::new(),
+            conditioner: Conditioner::new(),
+        })
+    }
+
+    pub fn user_data(&self, client_id: ClientId) -> Option<[u8; NETCODE_USER_DATA_BYTES]> {
+        self.netcode_server.user_data(client_id)
+    }
+
+    pub fn update(&mut self, duration: Duration, server: &mut RenetServer) -> Result<(), NetcodeTransportError> {
+        self.netcode_server.update(duration);
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((len, addr)) => {
+                    let result = self.netcode_server.process_packet(addr, &mut self.buffer[..len]);
+                    handle_server_result(result, &mut self.outgoing, server);
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::Interrupted => break,
+                // A client that went away, Windows reports it on the next read
+                Err(ref error) if error.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+        for client_id in self.netcode_server.clients_id() {
+            let result = self.netcode_server.update_client(client_id);
+            handle_server_result(result, &mut self.outgoing, server);
+        }
+        // Clients the server kicked
+        for client_id in server.disconnections_id() {
+            let result = self.netcode_server.disconnect(client_id);
+            handle_server_result(result, &mut self.outgoing, server);
+        }
+        Ok(())
+    }
+
+    pub fn send_packets(&mut self, server: &mut RenetServer, now: Duration, conditions: &NetworkConditions) {
+        for packet in self.outgoing.drain(..) {
+            self.conditioner.push(now, packet, conditions);
+        }
+        for client_id in server.clients_id() {
+            let Ok(packets) = server.get_packets_to_send(client_id) else {
+                continue;
+            };
+            for packet in packets {
+                match self.netcode_server.generate_payload_packet(client_id, &packet) {
+                    Ok((addr, payload)) => self.conditioner.push(now, (addr, payload.to_vec()), conditions),
+                    Err(error) => {
+                        println!("Could not encrypt a packet for client {client_id}: {error}");
+                        break;
+                    }
+                }
+            }
+        }
+        for (addr, packet) in self.conditioner.release(now) {
+            send_packet(&self.socket, &packet, addr);
+        }
+    }
+
+    // Sends everything still on the way and then the disconnects straight away, for shutting down
+    pub fn disconnect_all(&mut self, server: &mut RenetServer) {
+        for client_id in self.netcode_server.clients_id() {
+            let result = self.netcode_server.disconnect(client_id);
+            handle_server_result(result, &mut self.outgoing, server);
+        }
+        let packets = self.conditioner.release_all().into_iter().chain(self.outgoing.drain(..));
+        for (addr, packet) in packets {
+            send_packet(&self.socket, &packet, addr);
+        }
+    }
+}
+
+fn handle_server_result(result: ServerResult, outgoing: &mut Vec<(SocketAddr, Vec<u8>)>, server: &mut RenetServer) {
+    match result {
+        ServerResult::None => {}
+        ServerResult::PacketToSend { payload, addr } => outgoing.push((addr, payload.to_vec())),
+        ServerResult::Payload { client_id, payload } => {
+            if let Err(error) = server.process_packet_from(payload, client_id) {
+                println!("Could not read a packet from client {client_id}: {error}");
+            }
+        }
+        ServerResult::ClientConnected { client_id, addr, payload, .. } => {
+            server.add_connection(client_id);
+            outgoing.push((addr, payload.to_vec()));
+        }
+        ServerResult::ClientDisconnected { client_id, addr, payload } => {
+            server.remove_connection(client_id);
+            if let Some(payload) = payload {
+                outgoing.push((addr, payload.to_vec()));
+            }
+        }
+    }
+}
+
+fn send_packet(socket: &UdpSocket, packet: &[u8], addr: SocketAddr) {
+    if let Err(error) = socket.send_to(packet, addr) {
+        println!("Could not send a packet to {addr}: {error}");
+    }
+}
+
+#[derive(Resource)]
+pub struct UdpClientTransport {
+    socket: UdpSocket,
+    netcode_client: NetcodeClient,
+    buffer: [u8; NETCODE_MAX_PACKET_BYTES],
+    // Connection requests and keepalives from the last update, they wait here for send_packets to condition them
+    outgoing: Vec<(SocketAddr, Vec<u8>)>,
+    conditioner: Conditioner<(SocketAddr, Vec<u8>)>,
+}
+
+impl UdpClientTransport {
+    pub fn new(current_time: Duration, authentication: ClientAuthentication, socket: UdpSocket) -> Result<Self, NetcodeError> {
+        socket.set_nonblocking(true)?;
+        Ok(UdpClientTransport {
+            socket,
+            netcode_client: NetcodeClient::new(current_time, authentication)?,
+            buffer: [0; NETCODE_MAX_PACKET_BYTES],
+            outgoing: Vec::new(),
+            conditioner: Conditioner::new(),
+        })
+    }
+
+    pub fn disconnect_reason(&self) -> Option<NetcodeDisconnectReason> {
+        self.netcode_client.disconnect_reason()
+    }
+
+    pub fn update(&mut self, duration: Duration, client: &mut RenetClient) -> Result<(), NetcodeTransportError> {
+        if let Some(reason) = self.netcode_client.disconnect_reason() {
+            client.disconnect_due_to_transport();
+            return Err(NetcodeError::Disconnected(reason).into());
+        }
+        if let Some(reason) = client.disconnect_reason() {
+            self.disconnect();
+            return Err(reason.into());
+        }
+        if self.netcode_client.is_connected() {
+            client.set_connected();
+        } else if self.netcode_client.is_connecting() {
+            client.set_connecting();
+        }
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                // Anyone but the server is ignored
+                Ok((_, addr)) if addr != self.netcode_client.server_addr() => continue,
+                Ok((len, _)) => {
+                    if let Some(payload) = self.netcode_client.process_packet(&mut self.buffer[..len]) {
+                        client.process_packet(payload);
+                    }
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::Interrupted => break,
+                Err(error) => return Err(error.into()),
+            }
+        }
+        if let Some((packet, addr)) = self.netcode_client.update(duration) {
+            self.outgoing.push((addr, packet.to_vec()));
+        }
+        Ok(())
+    }
+
+    pub fn send_packets(&mut self, client: &mut RenetClient, now: Duration, conditions: &NetworkConditions) -> Result<(), NetcodeTransportError> {
+        if let Some(reason) = self.netcode_client.disconnect_reason() {
+            return Err(NetcodeError::Disconnected(reason).into());
+        }
+        for packet in self.outgoing.drain(..) {
+            self.conditioner.push(now, packet, conditions);
+        }
+        for packet in client.get_packets_to_send() {
+            let (addr, payload) = self.netcode_client.generate_payload_packet(&packet)?;
+            self.conditioner.push(now, (addr, payload.to_vec()), conditions);
+        }
+        for (addr, packet) in self.conditioner.release(now) {
+            self.socket.send_to(&packet, addr)?;
+        }
+        Ok(())
+    }
+
+    // Sends everything still on the way straight away, then disconnects, for saying goodbye
+    pub fn hang_up(&mut self, client: &mut RenetClient) {
+        if !self.netcode_client.is_disconnected() {
+            let mut packets = self.conditioner.release_all();
+            for packet in client.get_packets_to_send() {
+                match self.netcode_client.generate_payload_packet(&packet) {
+                    Ok((addr, payload)) => packets.push((addr, payload.to_vec())),
+                    Err(error) => println!("Could not tell the server we are leaving: {error}"),
+                }
+            }
+            for (addr, packet) in packets {
+                send_packet(&self.socket, &packet, addr);
+            }
+        }
+        self.disconnect();
+    }
+
+    // The disconnect packet goes out straight away, there's no one left to wait for it
+    pub fn disconnect(&mut self) {
+        if self.netcode_client.is_disconnected() {
+            return;
+        }
+        match self.netcode_client.disconnect() {
+            Ok((addr, packet)) => send_packet(&self.socket, packet, addr),
+            Err(error) => println!("Could not hang up: {error}"),
+        }
+    }
+}
+
+// Runs the UDP transports in place of bevy_renet's netcode plugins, in the same system sets
+pub struct UdpPlugin;
+
+impl Plugin for UdpPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkConditions>()
+        .add_event::<NetcodeTransportError>()
+        .add_systems(PreUpdate, (
+            server_update_system
+                .run_if(resource_exists::<UdpServerTransport>.and(resource_exists::<RenetServer>))
+                .after(RenetServerPlugin::update_system)
+                .before(RenetServerPlugin::emit_server_events_system),
+            client_update_system
+                .run_if(resource_exists::<UdpClientTransport>.and(resource_exists::<RenetClient>))
+                .after(RenetClientPlugin::update_system),
+        ).in_set(RenetReceive))
+        .add_systems(PostUpdate, (
+            server_send_system.run_if(resource_exists::<UdpServerTransport>.and(resource_exists::<RenetServer>)),
+            client_send_system.run_if(resource_exists::<UdpClientTransport>.and(resource_exists::<RenetClient>)),
+        ).in_set(RenetSend))
+        .add_systems(Last, (
+            server_exit_system.run_if(resource_exists::<UdpServerTransport>.and(resource_exists::<RenetServer>)),
+            client_exit_system.run_if(resource_exists::<UdpClientTransport>),
+        ));
+    }
+}
+
+fn server_update_system(mut transport: ResMut<UdpServerTransport>, mut server: ResMut<RenetServer>, time: Res<Time>, mut errors: EventWriter<NetcodeTransportError>) {
+    if let Err(error) = transport.update(time.delta(), &mut server) {
+        errors.send(error);
+    }
+}
+
+fn server_send_system(mut transport: ResMut<UdpServerTransport>, mut server: ResMut<RenetServer>, time: Res<Time>, conditions: Res<NetworkConditions>) {
+    transport.send_packets(&mut server, time.elapsed(), &conditions);
+}
+
+fn server_exit_system(exit: EventReader<AppExit>, mut transport: ResMut<UdpServerTransport>, mut server: ResMut<RenetServer>) {
+    if !exit.is_empty() {
+        transport.disconnect_all(&mut server);
+    }
+}
+
+fn client_update_system(mut transport: ResMut<UdpClientTransport>, mut client: ResMut<RenetClient>, time: Res<Time>, mut errors: EventWriter<NetcodeTransportError>) {
+    if let Err(error) = transport.update(time.delta(), &mut client) {
+        errors.send(error);
+    }
+}
+
+fn client_send_system(
+    mut transport: ResMut<UdpClientTransport>,
+    mut client: ResMut<RenetClient>,
+    time: Res<Time>,
+    conditions: Res<NetworkConditions>,
+    mut errors: EventWriter<NetcodeTransportError>,
+) {
+    if let Err(error) = transport.send_packets(&mut client, time.elapsed(), &conditions) {
+        errors.send(error);
+    }
+}
+
+fn client_exit_system(exit: EventReader<AppExit>, mut transport: ResMut<UdpClientTransport>) {
+    if !exit.is_empty() {
+        transport.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use renet::{ConnectionConfig, DefaultChannel};
+    use renet_netcode::ServerAuthentication;
+    use crate::utils::client::PROTOCOL_ID;
+
+    const STEP: Duration = Duration::from_millis(10);
+    const CLIENT_ID: u64 = 7;
+
+    // A server and a client talking over real sockets on this machine
+    struct Pair {
+        server: RenetServer,
+        server_transport: UdpServerTransport,
+        client: RenetClient,
+        client_transport: UdpClientTransport,
+        now: Duration,
+    }
+
+    impl Pair {
+        fn new() -> Self {
+            let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let server_address = server_socket.local_addr().unwrap();
+            let server_transport = UdpServerTransport::new(ServerConfig {
+                current_time: Duration::ZERO,
+                max_clients: 4,
+                protocol_id: PROTOCOL_ID,
+                public_addresses: vec![server_address],
+                authentication: ServerAuthentication::Unsecure,
+            }, server_socket).unwrap();
+            let client_transport = UdpClientTransport::new(Duration::ZERO, ClientAuthentication::Unsecure {
+                server_addr: server_address,
+                client_id: CLIENT_ID,
+                user_data: None,
+                protocol_id: PROTOCOL_ID,
+            }, UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap();
+            Pair {
+                server: RenetServer::new(ConnectionConfig::default()),
+                server_transport,
+                client: RenetClient::new(ConnectionConfig::default()),
+                client_transport,
+                now: Duration::ZERO,
+            }
+        }
+
+        // One frame on both sides, what each side sends goes through its own conditions
+        fn step(&mut self, server_conditions: &NetworkConditions, client_conditions: &NetworkConditions) {
+            self.now += STEP;
+            self.server.update(STEP);
+            self.server_transport.update(STEP, &mut self.server).unwrap();
+            self.client.update(STEP);
+            self.client_transport.update(STEP, &mut self.client).unwrap();
+            self.server_transport.send_packets(&mut self.server, self.now, server_conditions);
+            self.client_transport.send_packets(&mut self.client, self.now, client_conditions).unwrap();
+            // Long enough for the OS to hand over what was just sent
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // Sends a message each way at the same time, and how long each took to arrive, the one to the server first
+        fn time_both_ways(&mut self, server_conditions: &NetworkConditions, client_conditions: &NetworkConditions) -> (Duration, Duration) {
+            self.client.send_message(DefaultChannel::ReliableOrdered, "to the server".as_bytes().to_vec());
+            self.server.send_message(CLIENT_ID, DefaultChannel::ReliableOrdered, "to the client".as_bytes().to_vec());
+            let sent_at = self.now;
+            let mut to_server = None;
+            let mut to_client = None;
+            while self.now < sent_at + Duration::from_secs(5) && (to_server.is_none() || to_client.is_none()) {
+                self.step(server_conditions, client_conditions);
+                if to_server.is_none() && self.server.receive_message(CLIENT_ID, DefaultChannel::ReliableOrdered).is_some() {
+                    to_server = Some(self.now - sent_at);
+                }
+                if to_client.is_none() && self.client.receive_message(DefaultChannel::ReliableOrdered).is_some() {
+                    to_client = Some(self.now - sent_at);
+                }
+            }
+            (to_server.unwrap(), to_client.unwrap())
+        }
+    }
+
+    #[test]
+    fn both_sides_are_slowed_down_and_still_get_through() {
+        let conditions = NetworkConditions {
+            enabled: true,
+            latency_ms: 100.0,
+            jitter_ms: 20.0,
+            loss_percent: 10.0,
+            duplicate_percent: 10.0,
+        };
+        let mut pair = Pair::new();
+        let mut connected_at = None;
+        let mut server_heard = None;
+        let mut client_heard = None;
+        while pair.now < Duration::from_secs(10) && (server_heard.is_none() || client_heard.is_none()) {
+            pair.step(&conditions, &conditions);
+            if pair.client.is_connected() && connected_at.is_none() {
+                connected_at = Some(pair.now);
+                pair.client.send_message(DefaultChannel::ReliableOrdered, "hello server".as_bytes().to_vec());
+                pair.server.send_message(CLIENT_ID, DefaultChannel::ReliableOrdered, "hello client".as_bytes().to_vec());
+            }
+            if let Some(message) = pair.server.receive_message(CLIENT_ID, DefaultChannel::ReliableOrdered) {
+                server_heard = Some(message);
+            }
+            if let Some(message) = pair.client.receive_message(DefaultChannel::ReliableOrdered) {
+                client_heard = Some(message);
+            }
+        }
+        // Netcode takes two round trips to connect, every leg of them held back by the latency
+        assert!(connected_at.unwrap() >= Duration::from_millis(400));
+        assert_eq!(server_heard.as_deref(), Some("hello server".as_bytes()));
+        assert_eq!(client_heard.as_deref(), Some("hello client".as_bytes()));
+    }
+
+    #[test]
+    fn each_side_only_slows_down_what_it_sends() {
+        let clear = NetworkConditions::default();
+        let slow = NetworkConditions {
+            enabled: true,
+            latency_ms: 300.0,
+            ..clear
+        };
+        let latency = Duration::from_millis(300);
+        let mut pair = Pair::new();
+        while !pair.client.is_connected() {
+            assert!(pair.now < Duration::from_secs(5), "never connected");
+            pair.step(&clear, &clear);
+        }
+
+        // Only the server's packets are held back, so only the way to the client is slow
+        let (to_server, to_client) = pair.time_both_ways(&slow, &clear);
+        assert!(to_client >= latency, "{to_client:?} to the client");
+        assert!(to_server < latency, "{to_server:?} to the server");
+
+        // And the other way around
+        let (to_server, to_client) = pair.time_both_ways(&clear, &slow);
+        assert!(to_server >= latency, "{to_server:?} to the server");
+        assert!(to_client < latency, "{to_client:?} to the client");
+    }
+}